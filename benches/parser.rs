@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+use jlox::tree_walker::parser::Parser;
+use jlox::tree_walker::scanner::Scanner;
+use jlox::tree_walker::tokens::Token;
+
+// Heavy on operators, every one of them goes through the precedence levels
+// below it before being matched.
+const SNIPPET: &str = "var total = (count + 2) * limit - 1 >= 10 == !done or a and b;\n\
+                       fun area(w, h) { return w * h / 2 ?? -1; }\n\
+                       print area(total, [1, 2][0]) < 3 ? \"small\" : \"big\";\n";
+
+fn tokens(bytes: usize) -> Vec<Token> {
+    let source = SNIPPET.repeat(bytes / SNIPPET.len() + 1);
+    Scanner::new(source).scan_tokens()
+}
+
+// Scanning and copying the tokens are left out, only the parser is measured.
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for bytes in [10_000, 100_000] {
+        let tokens = tokens(bytes);
+        group.throughput(Throughput::Elements(tokens.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(bytes), &tokens, |b, tokens| {
+            b.iter_batched(
+                || tokens.to_owned(),
+                |tokens| Parser::new(tokens).parse(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,98 @@
+use std::io;
+use std::rc::Rc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use jlox::tree_walker::interpreter::{Interpreter, Options};
+use jlox::tree_walker::parser::Parser;
+use jlox::tree_walker::resolver::Resolver;
+use jlox::tree_walker::scanner::Scanner;
+use jlox::tree_walker::syntax_tree::{Exprs, Stmt};
+use jlox::tree_walker::tokens::Token;
+
+// Small programs that each lean on a different part of the interpreter.
+fn programs() -> Vec<(&'static str, String)> {
+    let fib = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }\n\
+               print fib(20);\n";
+    let strings = "var s = \"\";\n\
+                   for (var i = 0; i < 2000; i = i + 1) { s = s + \"ab\"; }\n\
+                   print len(s);\n";
+    let loops = "var total = 0;\n\
+                 var i = 0;\n\
+                 while (i < 100000) {\n\
+                   if (i < 50000) total = total + i; else total = total - 1;\n\
+                   i = i + 1;\n\
+                 }\n\
+                 print total;\n";
+    let deep = format!("print {}1{};\n", "(1 + ".repeat(2000), ")".repeat(2000));
+
+    vec![
+        ("fib", fib.to_string()),
+        ("strings", strings.to_string()),
+        ("loops", loops.to_string()),
+        ("deep", deep),
+    ]
+}
+
+fn tokens(source: &str) -> Vec<Token> {
+    Scanner::new(source.to_string()).scan_tokens()
+}
+
+fn compile(source: &str) -> (Vec<Stmt>, Rc<Exprs>) {
+    let mut parser = Parser::new(tokens(source));
+    let statements = parser.parse();
+    Resolver::new(&parser.exprs).resolve(&statements);
+    (statements, Rc::new(parser.exprs))
+}
+
+// Scanning, parsing and running are measured apart, each stage gets its input
+// from the ones before it outside of the timings.
+fn stages(c: &mut Criterion) {
+    for (name, source) in programs() {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("scan", |b| b.iter(|| tokens(&source)));
+        group.bench_function("parse", |b| {
+            b.iter_batched(
+                || tokens(&source),
+                |tokens| Parser::new(tokens).parse(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("run", |b| {
+            let (statements, exprs) = compile(&source);
+            b.iter_batched(
+                || Interpreter::new(Options::default()).with_output(Box::new(io::sink())),
+                |mut interpreter| interpreter.interpret(&statements, &exprs),
+                BatchSize::SmallInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+// 100k parts added to a string one by one, which copies the whole string on
+// every step, against pushing them to a list and joining it once.
+fn concatenation(c: &mut Criterion) {
+    let plus = "var s = \"\";\n\
+                for (var i = 0; i < 100000; i = i + 1) { s = s + \"ab\"; }\n";
+    let join = "var parts = [];\n\
+                for (var i = 0; i < 100000; i = i + 1) { push(parts, \"ab\"); }\n\
+                var s = join(parts, \"\");\n";
+
+    let mut group = c.benchmark_group("concatenation");
+    group.sample_size(10);
+    for (name, source) in [("plus", plus), ("join", join)] {
+        let (statements, exprs) = compile(source);
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || Interpreter::new(Options::default()),
+                |mut interpreter| interpreter.interpret(&statements, &exprs),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stages, concatenation);
+criterion_main!(benches);
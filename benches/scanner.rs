@@ -0,0 +1,39 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use jlox::tree_walker::scanner::Scanner;
+
+// A bit of everything the scanner knows: keywords, identifiers, numbers,
+// strings, operators and comments, some of it non-ASCII.
+const SNIPPET: &str = "var total_1 = 1_000.5 * (count + 2) >= limit; // säkerhet\n\
+                       fun greet(name) { print \"héllo, \" + name; }\n";
+
+// The snippet repeated until it's about `bytes` long, either as many lines or
+// squeezed onto a single one.
+fn source(bytes: usize, one_line: bool) -> String {
+    let snippet = if one_line {
+        SNIPPET
+            .replace("// säkerhet\n", "/* säkerhet */ ")
+            .replace('\n', " ")
+    } else {
+        SNIPPET.to_string()
+    };
+    snippet.repeat(bytes / snippet.len() + 1)
+}
+
+// Throughput should stay flat as the sources grow, scanning is linear.
+fn scan(c: &mut Criterion) {
+    for (name, one_line) in [("lines", false), ("one_line", true)] {
+        let mut group = c.benchmark_group(format!("scan/{}", name));
+        for bytes in [10_000, 100_000, 1_000_000] {
+            let source = source(bytes, one_line);
+            group.throughput(Throughput::Bytes(source.len() as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(bytes), &source, |b, source| {
+                b.iter(|| Scanner::new(source.to_owned()).scan_tokens())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
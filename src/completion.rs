@@ -0,0 +1,95 @@
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use jlox::tree_walker::scanner::KEYWORDS;
+
+// Completes the word under the cursor at the prompt with keywords and the
+// globals defined so far, natives among them. The prompt refreshes `globals`
+// after every line it runs.
+#[derive(Default)]
+pub struct Completion {
+    pub globals: Vec<String>,
+}
+
+impl Completion {
+    // Where the word being completed starts and what it could become.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+
+        // Properties aren't known until the code runs.
+        if prefix.is_empty() || line[..start].ends_with('.') {
+            return (start, vec![]);
+        }
+
+        let mut names: Vec<String> = KEYWORDS
+            .iter()
+            .map(|(word, _)| *word)
+            .chain(self.globals.iter().map(String::as_str))
+            .filter(|name| name.starts_with(prefix))
+            .map(String::from)
+            .collect();
+        names.sort();
+        names.dedup();
+        (start, names)
+    }
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn candidates() {
+        let completion = Completion {
+            globals: vec!["clock".to_string(), "counter".to_string()],
+        };
+
+        assert_eq!(
+            completion.candidates("print c", 7),
+            (
+                6,
+                vec!["class", "clock", "const", "continue", "counter"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            )
+        );
+        assert_eq!(completion.candidates("var x = wh", 10).1, vec!["while"]);
+        // Only the part before the cursor counts.
+        assert_eq!(
+            completion.candidates("fu(1)", 2),
+            (0, vec!["fun".to_string()])
+        );
+        assert!(completion.candidates("a.c", 3).1.is_empty());
+        assert!(completion.candidates("print ", 6).1.is_empty());
+    }
+}
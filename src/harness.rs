@@ -0,0 +1,181 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// What a test script says it should do, from its comments:
+//   print 1; // expect: 1
+//   nil();   // expect runtime error: Can only call functions and classes.
+#[derive(Debug, Default, PartialEq)]
+pub struct Expectations {
+    output: Vec<String>,
+    runtime_error: Option<String>,
+}
+
+impl Expectations {
+    pub fn parse(source: &str) -> Self {
+        let mut expectations = Expectations::default();
+        for line in source.lines() {
+            if let Some((_, expected)) = line.split_once("// expect: ") {
+                expectations.output.push(expected.to_string());
+            } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+                expectations.runtime_error = Some(message.to_string());
+            }
+        }
+        expectations
+    }
+
+    // Everything the run did differently from what was expected, empty if it
+    // passed.
+    pub fn check(&self, stdout: &str, stderr: &str, code: Option<i32>) -> Vec<String> {
+        let mut failures = vec![];
+
+        let output: Vec<&str> = stdout.lines().collect();
+        for (i, expected) in self.output.iter().enumerate() {
+            match output.get(i) {
+                Some(line) if line == expected => (),
+                Some(line) => failures.push(format!(
+                    "Expected output '{}' on line {} and got '{}'.",
+                    expected,
+                    i + 1,
+                    line
+                )),
+                None => failures.push(format!("Missing expected output '{}'.", expected)),
+            }
+        }
+        for line in output.iter().skip(self.output.len()) {
+            failures.push(format!("Got unexpected output '{}'.", line));
+        }
+
+        match &self.runtime_error {
+            Some(message) => {
+                if !stderr.lines().any(|line| line.trim_end() == message) {
+                    failures.push(format!("Expected runtime error '{}'.", message));
+                }
+                if code != Some(70) {
+                    failures.push(format!(
+                        "Expected exit code 70 and got {}.",
+                        exit_code(code)
+                    ));
+                }
+            }
+            None if code != Some(0) => {
+                failures.push(format!("Expected exit code 0 and got {}.", exit_code(code)));
+                failures.extend(stderr.lines().map(|line| format!("  {}", line)));
+            }
+            None => (),
+        }
+
+        failures
+    }
+}
+
+// A process killed by a signal has no exit code to show.
+fn exit_code(code: Option<i32>) -> String {
+    match code {
+        Some(code) => code.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+// Runs every script under the directory in a jlox process of its own and
+// checks it against its expectations. Tells whether they all passed, a
+// directory that can't be read or has no scripts in it fails.
+pub fn run(dir: &Path) -> bool {
+    let mut scripts = vec![];
+    if let Err(error) = collect_scripts(dir, &mut scripts) {
+        eprintln!("Could not read {}: {}.", dir.display(), error);
+        return false;
+    }
+    if scripts.is_empty() {
+        eprintln!("No scripts found in {}.", dir.display());
+        return false;
+    }
+    scripts.sort();
+
+    let jlox = std::env::current_exe().expect("the running jlox binary");
+    let (mut passed, mut failed) = (0, 0);
+    for script in scripts {
+        let failures = match (
+            fs::read_to_string(&script),
+            Command::new(&jlox).arg(&script).output(),
+        ) {
+            (Ok(source), Ok(output)) => Expectations::parse(&source).check(
+                &String::from_utf8_lossy(&output.stdout),
+                &String::from_utf8_lossy(&output.stderr),
+                output.status.code(),
+            ),
+            (Err(error), _) | (_, Err(error)) => vec![format!("Could not run it: {}.", error)],
+        };
+
+        if failures.is_empty() {
+            passed += 1;
+            println!("PASS {}", script.display());
+        } else {
+            failed += 1;
+            println!("FAIL {}", script.display());
+            for failure in failures {
+                println!("  {}", failure);
+            }
+        }
+    }
+
+    println!("\n{} passed, {} failed.", passed, failed);
+    failed == 0
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            scripts.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expectations() {
+        let source = "print 1; // expect: 1\nprint \"a\"; // expect: a\nnil(); // expect runtime error: Boom.";
+        let expectations = Expectations::parse(source);
+
+        assert!(expectations
+            .check("1\na\n", "Boom. \n[line 3]\n", Some(70))
+            .is_empty());
+        assert_eq!(
+            expectations.check("1\nb\nc\n", "", Some(0)),
+            vec![
+                "Expected output 'a' on line 2 and got 'b'.",
+                "Got unexpected output 'c'.",
+                "Expected runtime error 'Boom.'.",
+                "Expected exit code 70 and got 0.",
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let expectations = Expectations::parse("print 1; // expect: 1");
+        assert_eq!(
+            expectations.check("1\n", "Boom.\n", Some(65)),
+            vec!["Expected exit code 0 and got 65.", "  Boom."]
+        );
+        assert_eq!(
+            expectations.check("1\n", "", None),
+            vec!["Expected exit code 0 and got none."]
+        );
+    }
+
+    #[test]
+    fn missing_directory() {
+        let mut scripts = vec![];
+        assert!(collect_scripts(Path::new("no/such/dir"), &mut scripts).is_err());
+        assert!(!run(Path::new("no/such/dir")));
+    }
+}
@@ -0,0 +1,12 @@
+// The interpreter as a library, for hosts embedding Lox. The jlox binary is
+// built on top of it.
+pub mod session;
+pub mod tree_walker;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+pub mod worker;
+
+pub use session::{run, Run, Session};
+pub use tree_walker::errors::LoxError;
+pub use tree_walker::scanner::{tokenize, tokenize_with_trivia};
+pub use worker::Worker;
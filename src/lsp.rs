@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value as Json};
+
+use jlox::tree_walker::errors::{Diagnostics, Severity};
+use jlox::tree_walker::parser::Parser;
+use jlox::tree_walker::resolver::Resolver;
+use jlox::tree_walker::scanner::Scanner;
+use jlox::tree_walker::stack;
+use jlox::tree_walker::syntax_tree::{Expr, ExprId, Exprs, Function, Stmt};
+use jlox::tree_walker::tokens::{Token, TokenType};
+
+// LSP symbol kinds.
+const CLASS: u32 = 5;
+const METHOD: u32 = 6;
+const FUNCTION: u32 = 12;
+const VARIABLE: u32 = 13;
+const CONSTANT: u32 = 14;
+
+// Serves the Language Server Protocol over stdin and stdout: diagnostics,
+// go to definition, hover and document symbols. Documents are synced whole.
+pub fn serve() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout().lock();
+    let mut documents: HashMap<String, Analysis> = HashMap::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "jlox" },
+            })),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"]
+                        .as_array()
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change["text"].as_str()),
+                };
+                let analysis = Analysis::new(text.unwrap_or_default());
+                publish(&mut output, uri, analysis.diagnostics.clone())?;
+                documents.insert(uri.to_string(), analysis);
+                None
+            }
+            "textDocument/didClose" => {
+                documents.remove(uri);
+                publish(&mut output, uri, vec![])?;
+                None
+            }
+            "textDocument/definition" | "textDocument/hover" => {
+                let line = params["position"]["line"].as_u64().unwrap_or_default();
+                let character = params["position"]["character"].as_u64().unwrap_or_default();
+                let found = documents
+                    .get(uri)
+                    .and_then(|analysis| analysis.declaration_at(line, character));
+                Some(match (method, found) {
+                    (_, None) => Json::Null,
+                    ("textDocument/hover", Some(declaration)) => json!({
+                        "contents": {
+                            "kind": "markdown",
+                            "value": format!("```lox\n{}\n```", declaration.detail),
+                        },
+                    }),
+                    (_, Some(declaration)) => json!({
+                        "uri": uri,
+                        "range": range(&declaration.name),
+                    }),
+                })
+            }
+            "textDocument/documentSymbol" => Some(
+                documents
+                    .get(uri)
+                    .map(|analysis| json!(analysis.symbols))
+                    .unwrap_or(json!([])),
+            ),
+            "shutdown" => Some(Json::Null),
+            "exit" => return Ok(()),
+            _ => None,
+        };
+
+        // Notifications get no answer, requests always do.
+        let Some(id) = message.get("id") else {
+            continue;
+        };
+        let response = match result {
+            Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            None => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("Unknown method {}.", method) },
+            }),
+        };
+        write_message(&mut output, &response)?;
+    }
+
+    Ok(())
+}
+
+// Messages come as a Content-Length header, a blank line and the JSON body.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(": ") {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.parse::<usize>().ok();
+            }
+        }
+    }
+
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Missing Content-Length.",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn publish(output: &mut impl Write, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    });
+    write_message(output, &notification)
+}
+
+// Lines and columns count from zero in LSP, from one in tokens.
+fn range(token: &Token) -> Json {
+    let line = token.line.saturating_sub(1);
+    let start = token.column.saturating_sub(1);
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": start + token.length },
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Declaration {
+    name: Token,
+    // What hovering shows, like `fun add(a, b)`.
+    detail: String,
+}
+
+// Everything the server knows about one document, worked out when it changes.
+struct Analysis {
+    diagnostics: Vec<Json>,
+    declarations: Vec<Declaration>,
+    // Every variable reference and the declaration it refers to.
+    references: Vec<(Token, usize)>,
+    symbols: Vec<Json>,
+}
+
+impl Analysis {
+    fn new(source: &str) -> Self {
+        let mut diagnostics = Diagnostics::new();
+        let tokens: Vec<Token> = Scanner::new(source.to_string())
+            .scan_tokens()
+            .into_iter()
+            .filter(|token| {
+                if token.r#type == TokenType::Error {
+                    diagnostics.error(token, &token.lexeme);
+                    return false;
+                }
+                true
+            })
+            .collect();
+
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        diagnostics.extend(parser.diagnostics);
+
+        let mut resolver = Resolver::new(&parser.exprs);
+        resolver.resolve(&statements);
+        diagnostics.extend(resolver.diagnostics);
+
+        let mut symbols = Symbols {
+            exprs: parser.exprs,
+            ..Default::default()
+        };
+        for statement in &statements {
+            if let Some(symbol) = symbol(statement) {
+                symbols.top_level.push(symbol);
+            }
+            symbols.stmt(statement);
+        }
+        for name in std::mem::take(&mut symbols.unresolved) {
+            if let Some(&index) = symbols.globals.get(&name.lexeme) {
+                symbols.references.push((name, index));
+            }
+        }
+
+        Analysis {
+            diagnostics: diagnostics
+                .iter()
+                .map(|error| {
+                    let line = error.line.saturating_sub(1);
+                    let start = error.column.saturating_sub(1);
+                    json!({
+                        "range": {
+                            "start": { "line": line, "character": start },
+                            "end": { "line": line, "character": start + error.length.max(1) },
+                        },
+                        "severity": if error.severity == Severity::Error { 1 } else { 2 },
+                        "source": "jlox",
+                        "message": error.message,
+                    })
+                })
+                .collect(),
+            declarations: symbols.declarations,
+            references: symbols.references,
+            symbols: symbols.top_level,
+        }
+    }
+
+    // The declaration of whatever name is under the position, a declaration
+    // being its own.
+    fn declaration_at(&self, line: u64, character: u64) -> Option<&Declaration> {
+        let covers = |token: &Token| {
+            token.line == line + 1
+                && token.column <= character + 1
+                && character + 1 < token.column + token.length.max(1)
+        };
+
+        self.references
+            .iter()
+            .find(|(token, _)| covers(token))
+            .map(|(_, index)| &self.declarations[*index])
+            .or_else(|| self.declarations.iter().find(|d| covers(&d.name)))
+    }
+}
+
+fn symbol(statement: &Stmt) -> Option<Json> {
+    let (name, kind, children) = match statement {
+        Stmt::Var(v) => (
+            &v.name,
+            if v.constant { CONSTANT } else { VARIABLE },
+            vec![],
+        ),
+        Stmt::Function(f) => (&f.name, FUNCTION, vec![]),
+        Stmt::Class(c) => {
+            let methods = c
+                .methods
+                .iter()
+                .map(|m| json!({ "name": m.name.lexeme, "kind": METHOD, "range": range(&m.name), "selectionRange": range(&m.name) }))
+                .collect();
+            (&c.name, CLASS, methods)
+        }
+        _ => return None,
+    };
+
+    Some(json!({
+        "name": name.lexeme,
+        "kind": kind,
+        "range": range(name),
+        "selectionRange": range(name),
+        "children": children,
+    }))
+}
+
+// Ties variable references to their declarations, scoped the way the resolver
+// does it. Globals may be used before they are declared, so references that
+// miss every local scope are looked up among them at the end.
+#[derive(Default)]
+struct Symbols {
+    exprs: Exprs,
+    declarations: Vec<Declaration>,
+    references: Vec<(Token, usize)>,
+    scopes: Vec<HashMap<String, usize>>,
+    globals: HashMap<String, usize>,
+    unresolved: Vec<Token>,
+    top_level: Vec<Json>,
+}
+
+impl Symbols {
+    fn declare(&mut self, name: &Token, detail: String) {
+        let index = self.declarations.len();
+        self.declarations.push(Declaration {
+            name: name.to_owned(),
+            detail,
+        });
+        match self.scopes.last_mut() {
+            Some(scope) => scope.insert(name.lexeme.to_owned(), index),
+            None => self.globals.insert(name.lexeme.to_owned(), index),
+        };
+    }
+
+    fn reference(&mut self, name: &Token) {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme).copied());
+        match local {
+            Some(index) => self.references.push((name.to_owned(), index)),
+            None => self.unresolved.push(name.to_owned()),
+        }
+    }
+
+    fn function(&mut self, function: &Function, kind: &str) {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+        let detail = format!("{}{}({})", kind, function.name.lexeme, params.join(", "));
+        if kind == "fun " {
+            self.declare(&function.name, detail);
+        }
+
+        self.scopes.push(HashMap::new());
+        for param in &function.params {
+            self.declare(param, format!("(parameter) {}", param.lexeme));
+        }
+        self.statements(&function.body);
+        self.scopes.pop();
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.stmt(statement);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(s) => self.expr(s.expression),
+            Stmt::Print(s) => self.expr(s.expression),
+            Stmt::Var(s) => {
+                if let Some(initializer) = s.initializer {
+                    self.expr(initializer);
+                }
+                let keyword = if s.constant { "const" } else { "var" };
+                self.declare(&s.name, format!("{} {}", keyword, s.name.lexeme));
+            }
+            Stmt::Block(s) => {
+                self.scopes.push(HashMap::new());
+                self.statements(&s.statements);
+                self.scopes.pop();
+            }
+            Stmt::If(s) => {
+                self.expr(s.condition);
+                self.stmt(&s.then);
+                if let Some(r#else) = &s.r#else {
+                    self.stmt(r#else);
+                }
+            }
+            Stmt::While(s) => {
+                self.expr(s.condition);
+                self.stmt(&s.body);
+                if let Some(increment) = s.increment {
+                    self.expr(increment);
+                }
+            }
+            Stmt::ForIn(s) => {
+                self.expr(s.iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(&s.name, format!("var {}", s.name.lexeme));
+                self.stmt(&s.body);
+                self.scopes.pop();
+            }
+            Stmt::Function(f) => self.function(f, "fun "),
+            Stmt::Return(s) => {
+                if let Some(value) = s.value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Class(s) => {
+                self.declare(&s.name, format!("class {}", s.name.lexeme));
+                for method in &s.methods {
+                    self.function(method, "");
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => (),
+            Stmt::Match(s) => {
+                self.expr(s.subject);
+                for (pattern, body) in &s.arms {
+                    self.expr(*pattern);
+                    self.stmt(body);
+                }
+                if let Some(body) = &s.r#else {
+                    self.stmt(body);
+                }
+            }
+        }
+    }
+
+    // Only names matter here, everything under an expression is walked alike.
+    fn expr(&mut self, expr: ExprId) {
+        let expr = &self.exprs[expr];
+        let children = expr.children();
+        let name = match expr {
+            Expr::Assign(e) => Some(e.name.to_owned()),
+            Expr::Variable(e) => Some(e.name.to_owned()),
+            _ => None,
+        };
+
+        if let Some(name) = name {
+            self.reference(&name);
+        }
+        for child in children {
+            stack::guard(|| self.expr(child));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deep_expressions() {
+        let source = format!("var a = {};", vec!["a"; 100_000].join(" + "));
+        let analysis = Analysis::new(&source);
+        assert!(analysis.diagnostics.is_empty());
+        assert_eq!(analysis.declaration_at(0, 8).unwrap().detail, "var a");
+    }
+
+    #[test]
+    fn analysis() {
+        let source = "fun add(a, b) { return a + b; }\n{ var a = 1; print add(a, f()); }\nfun f() {}\nprint ;";
+        let analysis = Analysis::new(source);
+
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(analysis.diagnostics[0]["range"]["start"]["line"], 3);
+
+        // `a` inside the block is the local, not the parameter.
+        let local = analysis.declaration_at(1, 23).unwrap();
+        assert_eq!((local.detail.as_str(), local.name.line), ("var a", 2));
+        let add = analysis.declaration_at(1, 19).unwrap();
+        assert_eq!(add.detail, "fun add(a, b)");
+        // Globals declared later still resolve.
+        assert_eq!(analysis.declaration_at(1, 26).unwrap().name.line, 3);
+        assert!(analysis.declaration_at(1, 0).is_none());
+
+        let names: Vec<&Json> = analysis.symbols.iter().map(|s| &s["name"]).collect();
+        assert_eq!(names, vec!["add", "f"]);
+    }
+
+    #[test]
+    fn framing() {
+        let mut input = "Content-Length: 17\r\n\r\n{\"method\":\"exit\"}".as_bytes();
+        let message = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(message["method"], "exit");
+        assert!(read_message(&mut input).unwrap().is_none());
+    }
+}
@@ -1,74 +1,581 @@
 use std::{
-    //env,
+    env,
+    fmt::Display,
     fs::File,
-    io::{self, Read, Write},
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
     process::exit,
+    rc::Rc,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
 };
 
-mod tree_walker;
+use rustyline::{error::ReadlineError, Editor};
+
+mod completion;
+mod harness;
+mod lsp;
+
+use completion::Completion;
+use jlox::tree_walker;
+use tree_walker::debugger::Debugger;
+use tree_walker::dot::program_to_dot;
+use tree_walker::errors::{Diagnostics, Render, Severity};
+use tree_walker::formatter;
+use tree_walker::interpreter::{Capabilities, Interpreter, Options};
+use tree_walker::lint::{self, LintConfig, Rule};
+use tree_walker::optimizer::Optimizer;
+use tree_walker::syntax_tree::{Exprs, PrettyPrint, Stmt};
+use tree_walker::tree::program_to_tree;
+
+// What gets done with the parsed program.
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Run,
+    // Print the syntax tree instead of running anything.
+    PrintAst,
+    PrintAstTree,
+    PrintAstJson,
+    PrintAstDot,
+}
+
+// Everything set from the command line.
+#[derive(Clone, Copy)]
+struct Config {
+    options: Options,
+    action: Action,
+    // Whether diagnostics get colored.
+    color: bool,
+    // Report how well property and global lookups were cached once the
+    // script is done.
+    trace_cache: bool,
+}
+
+// Exit codes, from BSD's sysexits.h like the book's.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_SOFTWARE: i32 = 70;
+const EX_IOERR: i32 = 74;
+
+// Why running some source failed, each maps to its own exit code.
+enum Failure {
+    // Scanning, parsing or resolving errors.
+    Static,
+    Runtime,
+    // The script called exit(), with this code.
+    Exit(i32),
+}
 
 fn main() {
-    run_prompt()
+    let mut options = Options::default();
+    let mut action = Action::Run;
+    let mut trace_cache = false;
+    let mut script = None;
+    let mut snippet = None;
+    let mut script_args = vec![];
+    // Colors are only for terminals, --no-color goes anywhere on the command
+    // line to turn them off there too.
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let color = io::stderr().is_terminal() && !args.iter().any(|arg| arg == "--no-color");
+    args.retain(|arg| arg != "--no-color");
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("fmt") => {
+            args.next();
+            match (args.next(), args.next()) {
+                (Some(path), None) => format_file(path, color),
+                _ => usage(),
+            }
+        }
+        Some("lint") => {
+            args.next();
+            lint_file(args.collect(), color);
+        }
+        Some("debug") => {
+            args.next();
+            debug_file(args.collect(), color);
+        }
+        Some("bench") => {
+            args.next();
+            bench_file(args.collect(), color);
+        }
+        Some("test") => {
+            args.next();
+            match (args.next(), args.next()) {
+                (Some(dir), None) => exit(if harness::run(Path::new(&dir)) { 0 } else { 1 }),
+                _ => usage(),
+            }
+        }
+        Some("lsp") => {
+            args.next();
+            if args.next().is_some() {
+                usage();
+            }
+            match lsp::serve() {
+                Ok(()) => exit(0),
+                Err(e) => io_error(e),
+            }
+        }
+        _ => (),
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-e" | "--eval" if snippet.is_none() => match args.next() {
+                Some(source) => snippet = Some(source),
+                None => usage(),
+            },
+            "--ast" => action = Action::PrintAst,
+            "--ast-style=sexpr" => action = Action::PrintAst,
+            "--ast-style=tree" => action = Action::PrintAstTree,
+            "--ast-json" => action = Action::PrintAstJson,
+            "--ast-dot" => action = Action::PrintAstDot,
+            "--ieee-division" => options.ieee_division = true,
+            "--strict-truthiness" => options.strict_truthiness = true,
+            "--optimize" => options.optimize = true,
+            "--sandbox" => options.capabilities = Capabilities::none(),
+            "--trace-cache" => trace_cache = true,
+            _ if arg.starts_with("--max-steps=") => match arg["--max-steps=".len()..].parse() {
+                Ok(steps) => options.max_steps = Some(steps),
+                Err(_) => usage(),
+            },
+            _ if arg.starts_with("--seed=") => match arg["--seed=".len()..].parse() {
+                Ok(seed) => options.seed = Some(seed),
+                Err(_) => usage(),
+            },
+            _ if arg.starts_with("--timeout=") => {
+                match arg["--timeout=".len()..]
+                    .parse::<f64>()
+                    .map(Duration::try_from_secs_f64)
+                {
+                    Ok(Ok(timeout)) => options.timeout = Some(timeout),
+                    _ => usage(),
+                }
+            }
+            _ if arg.starts_with("--max-call-depth=") => {
+                match arg["--max-call-depth=".len()..].parse() {
+                    Ok(depth) => options.max_call_depth = depth,
+                    Err(_) => usage(),
+                }
+            }
+            // Whatever follows the script is for the script.
+            _ if script.is_none() && (arg == "-" || !arg.starts_with('-')) => {
+                script = Some(arg);
+                script_args = args.by_ref().collect();
+            }
+            _ => usage(),
+        }
+    }
+
+    let config = Config {
+        options,
+        action,
+        color,
+        trace_cache,
+    };
+    // Without a script the prompt only makes sense on a terminal, piped input
+    // is run as a whole program.
+    match (script, snippet) {
+        (Some(_), Some(_)) => usage(),
+        (None, Some(source)) => run_script(source, vec![], config),
+        (Some(path), None) => run_file(path, script_args, config),
+        (None, None) if !io::stdin().is_terminal() => run_file("-".to_string(), vec![], config),
+        (None, None) => run_prompt(config),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: jlox [--ieee-division] [--strict-truthiness] [--optimize] [--sandbox] [--trace-cache] [--max-call-depth=N] [--max-steps=N] [--timeout=SECONDS] [--seed=N] [--ast | --ast-style=sexpr|tree | --ast-json | --ast-dot] [--no-color] [script | - | -e source] [args...]");
+    eprintln!("       jlox fmt <script | ->");
+    eprintln!("       jlox test <dir>");
+    eprintln!("       jlox debug <script> [--break LINE]...");
+    eprintln!("       jlox bench [--iterations N] [--count] <script | ->");
+    eprintln!("       jlox lint [--allow=RULE] [--warn=RULE] [--deny=RULE] <script | ->");
+    eprintln!("       jlox lsp");
+    exit(EX_USAGE);
+}
+
+// Reading the script or talking to the terminal failed.
+fn io_error(error: impl Display) -> ! {
+    eprintln!("{}", error);
+    exit(EX_IOERR);
 }
 
-fn run_file(file_path: String) {
-    let source = match File::open(file_path) {
-        Err(error) => {
-            println!("{}", error);
-            exit(1)
+// Runs a whole script, `-` reads it from stdin.
+fn run_file(file_path: String, args: Vec<String>, config: Config) {
+    run_script(read_script(&file_path), args, config);
+}
+
+// Prints the script formatted, it has to parse to be formatted.
+fn format_file(file_path: String, color: bool) -> ! {
+    let source = read_script(&file_path);
+    match formatter::format(&source) {
+        Ok(formatted) => {
+            print!("{}", formatted);
+            exit(0);
+        }
+        Err(mut diagnostics) => {
+            diagnostics.report(
+                &mut io::stderr(),
+                &Render {
+                    source: &source,
+                    source_id: 0,
+                    color,
+                },
+            );
+            exit(EX_DATAERR);
         }
-        Ok(mut file) => {
-            let mut buf = String::new();
-            let _ = file.read_to_string(&mut buf).unwrap_or_else(|err| {
-                println!("{}", err);
-                exit(1);
-            });
+    }
+}
+
+// Reports whatever the lints find, rules can be turned off with --allow or
+// made errors with --deny.
+fn lint_file(args: Vec<String>, color: bool) -> ! {
+    let mut config = LintConfig::default();
+    let mut script = None;
+    for arg in args {
+        let level = match arg.split_once('=') {
+            Some(("--allow", rule)) => Some((rule.to_string(), None)),
+            Some(("--warn", rule)) => Some((rule.to_string(), Some(Severity::Warning))),
+            Some(("--deny", rule)) => Some((rule.to_string(), Some(Severity::Error))),
+            _ => None,
+        };
 
-            buf
+        match level {
+            Some((rule, level)) => match Rule::from_name(&rule) {
+                Some(rule) => config.set(rule, level),
+                None => usage(),
+            },
+            None if script.is_none() && (arg == "-" || !arg.starts_with('-')) => script = Some(arg),
+            None => usage(),
         }
+    }
+
+    let Some(path) = script else { usage() };
+    let source = read_script(&path);
+    let mut diagnostics = lint::lint(&source, &config);
+    let had_error = diagnostics.had_error();
+    diagnostics.report(
+        &mut io::stderr(),
+        &Render {
+            source: &source,
+            source_id: 0,
+            color,
+        },
+    );
+    exit(if had_error { EX_DATAERR } else { 0 });
+}
+
+// Runs the script over and over, each time in a fresh interpreter, and reports
+// how long the runs took. --count also reports the statements each one ran.
+fn bench_file(args: Vec<String>, color: bool) -> ! {
+    let mut iterations: u32 = 10;
+    let mut count = false;
+    let mut script = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--count" => count = true,
+            "--iterations" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => iterations = n,
+                _ => usage(),
+            },
+            _ if script.is_none() && (arg == "-" || !arg.starts_with('-')) => script = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let Some(path) = script else { usage() };
+    let source = read_script(&path);
+    let config = Config {
+        options: Options::default(),
+        action: Action::Run,
+        color,
+        trace_cache: false,
     };
 
-    if let Err(e) = run(source) {
-        tree_walker::errors::report(e);
-        exit(65);
+    let mut times = vec![];
+    let mut executed = 0;
+    for _ in 0..iterations {
+        let mut interpreter = Interpreter::new(config.options);
+        let start = Instant::now();
+        match run(source.to_owned(), &mut interpreter, config, false) {
+            // Exiting with 0 just ends the run early.
+            Ok(()) | Err(Failure::Exit(0)) => (),
+            Err(Failure::Static) => exit(EX_DATAERR),
+            Err(Failure::Runtime) => exit(EX_SOFTWARE),
+            Err(Failure::Exit(code)) => exit(code),
+        }
+        times.push(start.elapsed());
+        executed = interpreter.executed();
     }
+
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+    let mean = times.iter().sum::<Duration>() / iterations;
+    eprintln!(
+        "{} runs: min {:.3?}, mean {:.3?}, max {:.3?}",
+        iterations, min, mean, max
+    );
+    if count {
+        eprintln!("{} statements executed per run", executed);
+    }
+    exit(0);
 }
 
-fn run_prompt() {
-    print!("> ");
+// Runs the script under the debugger, which takes its commands from stdin so
+// the script has to come from a file.
+fn debug_file(args: Vec<String>, color: bool) -> ! {
+    let mut breakpoints = vec![];
+    let mut script = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--break" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(line) => breakpoints.push(line),
+                None => usage(),
+            },
+            _ if script.is_none() && !arg.starts_with('-') => script = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let Some(path) = script else { usage() };
+    let source = read_script(&path);
+    let debugger = Debugger::new(
+        &source,
+        breakpoints,
+        Box::new(io::stdin().lock()),
+        Box::new(io::stderr()),
+    );
+    let config = Config {
+        options: Options::default(),
+        action: Action::Run,
+        color,
+        trace_cache: false,
+    };
+
+    let mut interpreter = Interpreter::new(config.options).with_hook(Box::new(debugger));
+    match run(source, &mut interpreter, config, false) {
+        Ok(()) => exit(0),
+        Err(Failure::Static) => exit(EX_DATAERR),
+        Err(Failure::Runtime) => exit(EX_SOFTWARE),
+        Err(Failure::Exit(code)) => exit(code),
+    }
+}
+
+fn read_script(file_path: &str) -> String {
+    if file_path == "-" {
+        return read_source(io::stdin());
+    }
+
+    match File::open(file_path) {
+        Err(error) => io_error(format!("Could not open '{}': {}.", file_path, error)),
+        Ok(file) => read_source(file),
+    }
+}
+
+fn run_script(source: String, args: Vec<String>, config: Config) {
+    let mut interpreter = Interpreter::new(config.options).with_args(args);
+    let result = run(source, &mut interpreter, config, false);
+    if config.trace_cache {
+        report_caches(&interpreter);
+    }
+    match result {
+        Ok(()) => (),
+        Err(Failure::Static) => exit(EX_DATAERR),
+        Err(Failure::Runtime) => exit(EX_SOFTWARE),
+        Err(Failure::Exit(code)) => exit(code),
+    }
+}
+
+fn report_caches(interpreter: &Interpreter) {
+    let stats = interpreter.cache_stats();
+    for (lookups, hits, misses) in [
+        ("property", stats.property_hits, stats.property_misses),
+        ("global", stats.global_hits, stats.global_misses),
+    ] {
+        let rate = match hits + misses {
+            0 => 0.0,
+            total => 100.0 * hits as f64 / total as f64,
+        };
+        eprintln!(
+            "{} lookups: {} hits, {} misses ({:.1}% hit rate)",
+            lookups, hits, misses, rate
+        );
+    }
+}
+
+fn read_source(mut reader: impl Read) -> String {
     let mut buf = String::new();
-    loop {
-        io::stdout().flush().unwrap();
+    if let Err(error) = reader.read_to_string(&mut buf) {
+        io_error(format!("Could not read the script: {}.", error));
+    }
 
-        let num_bytes = io::stdin().read_line(&mut buf).unwrap_or_else(|err| {
-            println!("{}", err);
-            exit(1);
-        });
+    buf
+}
 
-        if num_bytes == 0 {
-            break;
-        }
+fn run_prompt(config: Config) {
+    let mut editor = Editor::new().unwrap_or_else(|error| io_error(error));
 
-        if let Err(e) = run(buf.to_string()) {
-            tree_walker::errors::report(e);
+    // History is best effort, a missing or unwritable file is not an error.
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".jlox_history"));
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    // One interpreter for the whole session, so definitions carry over from
+    // line to line.
+    let mut interpreter = Interpreter::new(config.options);
+    editor.set_helper(Some(Completion::default()));
+    let mut code = 0;
+    loop {
+        if let Some(completion) = editor.helper_mut() {
+            completion.globals = interpreter.global_names();
         }
-        buf.clear();
+        match editor.readline("> ") {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                // Errors were already reported, the prompt carries on regardless.
+                if let Err(Failure::Exit(exited)) = run(line, &mut interpreter, config, true) {
+                    code = exited;
+                    break;
+                }
+            }
+            // Ctrl+C drops the line being typed, Ctrl+D leaves.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => io_error(error),
+        }
+    }
 
-        print!("> ");
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
     }
+    exit(code);
 }
 
-fn run(source: String) -> Result<(), tree_walker::errors::Error> {
-    let mut scanner = tree_walker::scanner::Scanner::new(source);
-    let tokens = scanner.scan_tokens();
+// Every run numbers its source, so errors raised in code from an earlier one,
+// like a function defined on a previous line of the prompt, aren't quoted
+// against the current one.
+static SOURCES: AtomicU32 = AtomicU32::new(0);
+
+// In the prompt a bare expression gets its value printed and kept in `_`.
+fn run(
+    source: String,
+    interpreter: &mut Interpreter,
+    config: Config,
+    prompt: bool,
+) -> Result<(), Failure> {
+    let mut diagnostics = Diagnostics::new();
+
+    // Lexical errors come in-band, they get reported and the parser never
+    // sees them.
+    let source_id = SOURCES.fetch_add(1, Ordering::Relaxed) + 1;
+    let render = Render {
+        source: &source,
+        source_id,
+        color: config.color,
+    };
+    let scanner = tree_walker::scanner::Scanner::new(source.to_owned()).with_source(source_id);
+    let tokens = scanner
+        .scan_tokens()
+        .into_iter()
+        .filter(|token| {
+            if token.r#type == tree_walker::tokens::TokenType::Error {
+                diagnostics.error(token, &token.lexeme);
+                return false;
+            }
+            true
+        })
+        .collect();
 
     let mut parser = tree_walker::parser::Parser::new(tokens);
-    let expression = parser.parse();
+    let statements = if prompt {
+        parser.parse_repl()
+    } else {
+        parser.parse()
+    };
+    diagnostics.extend(parser.diagnostics);
+    let mut exprs = parser.exprs;
 
-    if expression.is_none() {
+    if config.options.optimize {
+        Optimizer::new(config.options).optimize(&statements, &mut exprs);
+    }
+
+    if config.action != Action::Run {
+        let had_error = diagnostics.had_error();
+        diagnostics.report(&mut io::stderr(), &render);
+        if had_error {
+            return Err(Failure::Static);
+        }
+        print_ast(&statements, &exprs, config.action);
         return Ok(());
     }
 
-    tree_walker::syntax_tree::interpret(expression.unwrap());
-    Ok(())
+    let mut resolver = tree_walker::resolver::Resolver::new(&exprs);
+    resolver.resolve(&statements);
+    diagnostics.extend(resolver.diagnostics);
+
+    // Warnings get reported too, but only errors stop the program from running.
+    let had_error = diagnostics.had_error();
+    diagnostics.report(&mut io::stderr(), &render);
+    if had_error {
+        return Err(Failure::Static);
+    }
+
+    let result = interpreter.interpret(&statements, &Rc::new(exprs));
+    interpreter.collect();
+    result.map_err(|e| {
+        if let Some(code) = e.exit {
+            return Failure::Exit(code);
+        }
+        e.report(&mut io::stderr(), &render);
+        Failure::Runtime
+    })
+}
+
+fn print_ast(statements: &[Stmt], exprs: &Exprs, action: Action) {
+    match action {
+        Action::PrintAst => {
+            for statement in statements {
+                println!("{}", statement.pretty_print(exprs));
+            }
+        }
+        Action::PrintAstTree => print!("{}", program_to_tree(statements, exprs)),
+        // Statements refer to expressions by their place in `exprs`.
+        Action::PrintAstJson => match serde_json::to_string_pretty(&serde_json::json!({
+            "statements": statements,
+            "exprs": exprs,
+        })) {
+            Ok(json) => println!("{}", json),
+            Err(error) => io_error(error),
+        },
+        Action::PrintAstDot => print!("{}", program_to_dot(statements, exprs)),
+        Action::Run => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prompt_keeps_constants() {
+        let config = Config {
+            options: Options::default(),
+            action: Action::Run,
+            color: false,
+            trace_cache: false,
+        };
+        let mut interpreter = Interpreter::new(config.options);
+        let mut line = |source: &str| run(source.to_string(), &mut interpreter, config, true);
+
+        assert!(line("const a = 1;").is_ok());
+        assert!(matches!(line("a = 2;"), Err(Failure::Runtime)));
+        assert!(matches!(line("a = 2"), Err(Failure::Runtime)));
+        assert!(line("var a = 3;").is_ok());
+        assert!(line("a = 4;").is_ok());
+        assert_eq!(interpreter.get_global("a").to_string(), "4");
+    }
 }
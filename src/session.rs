@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::tree_walker::errors::{Diagnostics, LoxError, Render};
+use crate::tree_walker::interpreter::{Interpreter, Options};
+use crate::tree_walker::optimizer::Optimizer;
+use crate::tree_walker::parser::Parser;
+use crate::tree_walker::resolver::Resolver;
+use crate::tree_walker::scanner::Scanner;
+use crate::tree_walker::syntax_tree::{Exprs, Stmt};
+use crate::tree_walker::tokens::TokenType;
+
+// What running a program printed and the errors it reported, as the jlox
+// binary would have shown them on stdout and stderr.
+#[derive(Debug, Default, PartialEq)]
+pub struct Run {
+    pub output: String,
+    pub errors: String,
+    // The code the program asked for with exit(), if it called it.
+    pub exit: Option<i32>,
+}
+
+// Runs a whole program in a fresh interpreter without touching the host:
+// output is captured and input always looks empty.
+pub fn run(source: &str) -> Run {
+    Session::new(Options::default()).run(source)
+}
+
+// An interpreter whose output gets captured, keeping its globals from one run
+// to the next.
+pub struct Session {
+    interpreter: Interpreter,
+    options: Options,
+    output: Capture,
+    // How many sources were compiled, each is numbered after it so errors
+    // are only quoted against the source they come from.
+    sources: u32,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Self {
+        let output = Capture::default();
+        let interpreter = Interpreter::new(options)
+            .with_input(Box::new(io::empty()))
+            .with_output(Box::new(output.clone()));
+
+        Session {
+            interpreter,
+            options,
+            output,
+            sources: 0,
+        }
+    }
+
+    pub fn run(&mut self, source: &str) -> Run {
+        let mut errors = vec![];
+        let (statements, exprs, mut diagnostics) = self.compile(source);
+        let render = Render {
+            source,
+            source_id: self.sources,
+            color: false,
+        };
+
+        let had_error = diagnostics.had_error();
+        diagnostics.report(&mut errors, &render);
+        let mut exit = None;
+        if !had_error {
+            match self.interpreter.interpret(&statements, &exprs) {
+                Err(e) if e.exit.is_some() => exit = e.exit,
+                Err(e) => e.report(&mut errors, &render),
+                Ok(()) => (),
+            }
+            // Sessions never hand out values, so cycles can go right away.
+            self.interpreter.collect();
+        }
+
+        Run {
+            output: self.take_output(),
+            errors: String::from_utf8_lossy(&errors).into_owned(),
+            exit,
+        }
+    }
+
+    // Like `run`, but fails with the first thing that went wrong instead of
+    // reporting it, warnings are dropped. Gives back the output.
+    pub fn try_run(&mut self, source: &str) -> Result<String, LoxError> {
+        let (statements, exprs, diagnostics) = self.compile(source);
+        if diagnostics.had_error() {
+            return Err(diagnostics.into());
+        }
+        let result = self.interpreter.interpret(&statements, &exprs);
+        self.interpreter.collect();
+        let output = self.take_output();
+        result?;
+        Ok(output)
+    }
+
+    // Scans, parses, folds when asked to and resolves the source.
+    fn compile(&mut self, source: &str) -> (Vec<Stmt>, Rc<Exprs>, Diagnostics) {
+        self.sources += 1;
+        let mut diagnostics = Diagnostics::new();
+        let tokens = Scanner::new(source.to_string())
+            .with_source(self.sources)
+            .scan_tokens()
+            .into_iter()
+            .filter(|token| {
+                if token.r#type == TokenType::Error {
+                    diagnostics.error(token, &token.lexeme);
+                    return false;
+                }
+                true
+            })
+            .collect();
+
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        diagnostics.extend(parser.diagnostics);
+        if self.options.optimize {
+            Optimizer::new(self.options).optimize(&statements, &mut parser.exprs);
+        }
+
+        let mut resolver = Resolver::new(&parser.exprs);
+        resolver.resolve(&statements);
+        diagnostics.extend(resolver.diagnostics);
+
+        (statements, Rc::new(parser.exprs), diagnostics)
+    }
+
+    fn take_output(&mut self) -> String {
+        let output = std::mem::take(&mut *self.output.0.borrow_mut());
+        String::from_utf8_lossy(&output).into_owned()
+    }
+}
+
+// Output shared with the interpreter, which keeps its own handle to it.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::syntax_tree::ast_print;
+
+    use super::*;
+
+    #[test]
+    fn runs_without_the_host() {
+        assert_eq!(
+            run("print 1 + 2;\nprint readLine();"),
+            Run {
+                output: "3\nnil\n".to_string(),
+                errors: String::new(),
+                exit: None,
+            }
+        );
+
+        let failed = run("print 1;\nprint nil();");
+        assert_eq!(failed.output, "1\n");
+        assert!(failed
+            .errors
+            .starts_with("Can only call functions and classes."));
+
+        let invalid = run("print 1;\nprint ;");
+        assert_eq!(invalid.output, "");
+        assert!(invalid.errors.starts_with("[line 2, col 7] Error"));
+    }
+
+    #[test]
+    fn exits() {
+        let exited = run("print 1;\nfun f() { exit(2); }\nf();\nprint 3;");
+        assert_eq!(exited.output, "1\n");
+        assert_eq!(exited.errors, "");
+        assert_eq!(exited.exit, Some(2));
+
+        let mut session = Session::new(Options::default());
+        let e = session.try_run("exit(0);").unwrap_err();
+        assert!(matches!(e, LoxError::Runtime(e) if e.exit == Some(0)));
+        assert!(run("exit(\"no\");").exit.is_none());
+    }
+
+    #[test]
+    fn sessions_keep_their_state() {
+        let mut session = Session::new(Options::default());
+        assert_eq!(session.run("var a = 1;").output, "");
+        assert_eq!(session.run("print a + 1;").output, "2\n");
+    }
+
+    #[test]
+    fn constants_across_runs() {
+        let mut session = Session::new(Options::default());
+        session.run("const a = 1;");
+        let assigned = session.run("a = 2;\nprint a;");
+        assert_eq!(assigned.output, "");
+        assert!(assigned.errors.starts_with("Can't assign to constant 'a'."));
+        assert_eq!(session.run("print a;").output, "1\n");
+
+        session.run("fun f() { a = 3; }");
+        assert!(session
+            .run("f();")
+            .errors
+            .starts_with("Can't assign to constant 'a'."));
+        session.run("var a = 4;");
+        assert_eq!(session.run("a = 5; print a;").output, "5\n");
+    }
+
+    #[test]
+    fn errors_quote_their_own_source() {
+        let mut session = Session::new(Options::default());
+        let failed = session.run("fun f() { return nil(); }\nf();");
+        assert!(failed.errors.contains("1 | fun f() { return nil(); }"));
+
+        // The call's ')' sits where the one of nil() in f did.
+        let later = "print f(             );";
+        assert_eq!(later.find(')'), Some(21));
+        let failed = session.run(later);
+        assert!(failed
+            .errors
+            .starts_with("Can only call functions and classes."));
+        assert!(!failed.errors.contains('|'), "{}", failed.errors);
+    }
+
+    #[test]
+    fn optimizes_when_asked() {
+        let options = Options {
+            optimize: true,
+            ..Options::default()
+        };
+        let (statements, exprs, _) = Session::new(options).compile("print 1 + 2 * 3;");
+        let [Stmt::Print(print)] = &statements[..] else {
+            unreachable!()
+        };
+        assert_eq!(ast_print(&exprs, print.expression), "7");
+
+        let (statements, exprs, _) = Session::new(Options::default()).compile("print 1 + 2;");
+        let [Stmt::Print(print)] = &statements[..] else {
+            unreachable!()
+        };
+        assert_eq!(ast_print(&exprs, print.expression), "(+ 1 2)");
+        assert_eq!(Session::new(options).run("print 1 + 2 * 3;").output, "7\n");
+    }
+
+    #[test]
+    fn errors_as_results() {
+        let mut session = Session::new(Options::default());
+        assert_eq!(session.try_run("print 1;").unwrap(), "1\n");
+
+        let e = session.try_run("print ;\nprint );").unwrap_err();
+        assert!(matches!(e, LoxError::Compile(_)));
+        assert_eq!(
+            e.to_string(),
+            "[line 1, col 7] Error:  at ';': expect expression\n\
+             [line 2, col 7] Error:  at ')': expect expression"
+        );
+
+        let e = session.try_run("print nil();").unwrap_err();
+        assert!(matches!(e, LoxError::Runtime(_)));
+        assert_eq!(
+            e.to_string(),
+            "[line 1, col 11] Error: Can only call functions and classes."
+        );
+        let source = std::error::Error::source(&e).unwrap();
+        assert_eq!(source.to_string(), e.to_string());
+    }
+}
@@ -0,0 +1,427 @@
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::environment::Environment;
+use super::errors::RuntimeError;
+use super::function::LoxFunction;
+use super::gc::Marks;
+use super::interpreter::{Capabilities, Interpreter};
+use super::map::LoxMap;
+use super::syntax_tree::Value;
+use super::tokens::Token;
+
+// Anything that can be called from Lox code, user functions and natives alike.
+// The arguments were already checked against the arity, `paren` is where the
+// call happened so errors can point at it.
+pub trait Callable: fmt::Debug + fmt::Display {
+    fn name(&self) -> &str;
+
+    fn arity(&self) -> Arity;
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, RuntimeError>;
+
+    // Hands whatever the callable holds on to over to the collector.
+    fn trace(&self, _marks: &mut Marks) {}
+
+    // Lox functions can run calls in tail position in their own frame.
+    fn as_function(&self) -> Option<&LoxFunction> {
+        None
+    }
+}
+
+// How many arguments a callable takes. Only natives can be variadic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Fixed(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Fixed(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::Fixed(n)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Fixed(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
+
+pub type NativeFn = fn(&mut Interpreter, Vec<Value>, &Token) -> Result<Value, RuntimeError>;
+
+// A function implemented by the host.
+pub struct NativeFunction {
+    name: String,
+    arity: Arity,
+    function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, arity: Arity, function: NativeFn) -> Self {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        }
+    }
+}
+
+impl Callable for NativeFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, RuntimeError> {
+        (self.function)(interpreter, arguments, paren)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+pub type HostFn = dyn Fn(Vec<Value>) -> Result<Value, String>;
+
+// A function registered by the application embedding the interpreter. It only
+// sees its arguments, the message it fails with becomes a runtime error at the
+// call.
+pub struct HostFunction {
+    name: String,
+    arity: Arity,
+    function: Box<HostFn>,
+}
+
+impl HostFunction {
+    pub fn new(name: &str, arity: Arity, function: Box<HostFn>) -> Self {
+        HostFunction {
+            name: name.to_string(),
+            arity,
+            function,
+        }
+    }
+}
+
+impl Callable for HostFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, RuntimeError> {
+        // The function may keep its arguments around.
+        for argument in &arguments {
+            interpreter.heap().pin(argument);
+        }
+        (self.function)(arguments).map_err(|message| RuntimeError::new(paren.to_owned(), message))
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// The standard library, defined in the globals of every interpreter as far
+// as its capabilities allow.
+pub fn define_natives(globals: &mut Environment, capabilities: Capabilities) {
+    let mut natives = vec![];
+    if capabilities.clock {
+        natives.push(NativeFunction::new("clock", Arity::Fixed(0), clock));
+    }
+    if capabilities.input {
+        natives.push(NativeFunction::new("readLine", Arity::Fixed(0), read_line));
+    }
+    if capabilities.environment {
+        natives.push(NativeFunction::new("getenv", Arity::Fixed(1), getenv));
+    }
+    natives.extend([
+        NativeFunction::new("push", Arity::AtLeast(2), push),
+        NativeFunction::new("pop", Arity::Fixed(1), pop),
+        NativeFunction::new("len", Arity::Fixed(1), len),
+        NativeFunction::new("join", Arity::Fixed(2), join),
+        NativeFunction::new("keys", Arity::Fixed(1), keys),
+        NativeFunction::new("values", Arity::Fixed(1), values),
+        NativeFunction::new("has", Arity::Fixed(2), has),
+        NativeFunction::new("random", Arity::Fixed(0), random),
+        NativeFunction::new("randomInt", Arity::Fixed(2), random_int),
+        NativeFunction::new("exit", Arity::Fixed(1), exit),
+    ]);
+
+    for native in natives {
+        globals.define(native.name.to_owned(), Value::Callable(Rc::new(native)));
+    }
+}
+
+// Seconds since the epoch, for timing code.
+#[cfg(not(target_arch = "wasm32"))]
+fn clock(_: &mut Interpreter, _: Vec<Value>, _: &Token) -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+// There's no system clock in wasm, the browser's is used instead.
+#[cfg(target_arch = "wasm32")]
+fn clock(_: &mut Interpreter, _: Vec<Value>, _: &Token) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(crate::wasm::now() / 1000.0))
+}
+
+// The value of an environment variable, nil when it isn't set or isn't
+// valid unicode.
+fn getenv(
+    _: &mut Interpreter,
+    arguments: Vec<Value>,
+    paren: &Token,
+) -> Result<Value, RuntimeError> {
+    match &arguments[0] {
+        Value::String(name) => Ok(std::env::var(&**name).map_or(Value::Nil, Value::from)),
+        _ => Err(RuntimeError::new(
+            paren.to_owned(),
+            "Can only look up environment variables by string.".to_string(),
+        )),
+    }
+}
+
+// The next line of input without its line ending, nil once input runs out.
+fn read_line(
+    interpreter: &mut Interpreter,
+    _: Vec<Value>,
+    paren: &Token,
+) -> Result<Value, RuntimeError> {
+    match interpreter.read_line() {
+        Ok(Some(line)) => {
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            Ok(Value::from(line))
+        }
+        Ok(None) => Ok(Value::Nil),
+        Err(e) => Err(RuntimeError::new(
+            paren.to_owned(),
+            format!("Could not read input: {}.", e),
+        )),
+    }
+}
+
+// Stops the program, unwinding all the way out to the host with the code.
+// Processes only get the low byte of it, so anything else is refused rather
+// than wrapped.
+fn exit(_: &mut Interpreter, arguments: Vec<Value>, paren: &Token) -> Result<Value, RuntimeError> {
+    match arguments[0] {
+        Value::Number(code) if code.fract() == 0.0 && (0.0..=255.0).contains(&code) => {
+            Err(RuntimeError::exit(paren.to_owned(), code as i32))
+        }
+        _ => Err(RuntimeError::new(
+            paren.to_owned(),
+            "Exit code must be an integer between 0 and 255.".to_string(),
+        )),
+    }
+}
+
+// Appends every value after the list to its end.
+fn push(_: &mut Interpreter, arguments: Vec<Value>, paren: &Token) -> Result<Value, RuntimeError> {
+    let mut arguments = arguments.into_iter();
+    let Some(Value::List(list)) = arguments.next() else {
+        return Err(RuntimeError::new(
+            paren.to_owned(),
+            "Can only push to lists.".to_string(),
+        ));
+    };
+
+    list.borrow_mut().extend(arguments);
+    Ok(Value::Nil)
+}
+
+// Removes and hands back the last element of a list.
+fn pop(_: &mut Interpreter, arguments: Vec<Value>, paren: &Token) -> Result<Value, RuntimeError> {
+    let Some(Value::List(list)) = arguments.into_iter().next() else {
+        return Err(RuntimeError::new(
+            paren.to_owned(),
+            "Can only pop from lists.".to_string(),
+        ));
+    };
+
+    let popped = list.borrow_mut().pop();
+    popped
+        .ok_or_else(|| RuntimeError::new(paren.to_owned(), "Can't pop an empty list.".to_string()))
+}
+
+// Number of elements of a list, entries of a map or chars of a string.
+fn len(_: &mut Interpreter, arguments: Vec<Value>, paren: &Token) -> Result<Value, RuntimeError> {
+    match arguments.first() {
+        Some(Value::List(list)) => Ok(Value::Number(list.borrow().len() as f64)),
+        Some(Value::String(s)) => Ok(Value::Number(s.chars().count() as f64)),
+        Some(Value::Map(map)) => Ok(Value::Number(map.borrow().len() as f64)),
+        _ => Err(RuntimeError::new(
+            paren.to_owned(),
+            "Can only take the length of lists, maps and strings.".to_string(),
+        )),
+    }
+}
+
+// The elements of a list as one string, with the separator between them.
+// Building a string out of parts pushed to a list and joined once copies
+// each part once, where adding them up one at a time copies it all again
+// on every step.
+fn join(_: &mut Interpreter, arguments: Vec<Value>, paren: &Token) -> Result<Value, RuntimeError> {
+    let (Value::List(list), Value::String(separator)) = (&arguments[0], &arguments[1]) else {
+        return Err(RuntimeError::new(
+            paren.to_owned(),
+            "Can only join a list with a string.".to_string(),
+        ));
+    };
+
+    let mut joined = String::new();
+    for (i, element) in list.borrow().iter().enumerate() {
+        if i > 0 {
+            joined.push_str(separator);
+        }
+        match element {
+            Value::String(s) => joined.push_str(s),
+            _ => joined.push_str(&element.to_string()),
+        }
+    }
+    Ok(Value::from(joined))
+}
+
+fn map_argument(arguments: &[Value], paren: &Token) -> Result<Rc<RefCell<LoxMap>>, RuntimeError> {
+    match arguments.first() {
+        Some(Value::Map(map)) => Ok(Rc::clone(map)),
+        _ => Err(RuntimeError::new(
+            paren.to_owned(),
+            "Expected a map.".to_string(),
+        )),
+    }
+}
+
+// The keys of a map, in insertion order.
+fn keys(
+    interpreter: &mut Interpreter,
+    arguments: Vec<Value>,
+    paren: &Token,
+) -> Result<Value, RuntimeError> {
+    let keys = map_argument(&arguments, paren)?.borrow().keys();
+    Ok(interpreter.new_list(keys))
+}
+
+fn values(
+    interpreter: &mut Interpreter,
+    arguments: Vec<Value>,
+    paren: &Token,
+) -> Result<Value, RuntimeError> {
+    let values = map_argument(&arguments, paren)?.borrow().values();
+    Ok(interpreter.new_list(values))
+}
+
+fn has(_: &mut Interpreter, arguments: Vec<Value>, paren: &Token) -> Result<Value, RuntimeError> {
+    let map = map_argument(&arguments, paren)?;
+    let has = map.borrow().has(&arguments[1]);
+    Ok(Value::Boolean(has))
+}
+
+// What random() and randomInt() draw from, a splitmix64 generator. Seeded it
+// gives the same numbers on every run, otherwise it starts somewhere new.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: Option<u64>) -> Self {
+        let state = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Random { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), from the top 53 bits so every value is exact.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// A number in [0, 1).
+fn random(interpreter: &mut Interpreter, _: Vec<Value>, _: &Token) -> Result<Value, RuntimeError> {
+    Ok(Value::Number(interpreter.random().next_f64()))
+}
+
+// An integer between both bounds, them included.
+fn random_int(
+    interpreter: &mut Interpreter,
+    arguments: Vec<Value>,
+    paren: &Token,
+) -> Result<Value, RuntimeError> {
+    match (&arguments[0], &arguments[1]) {
+        (Value::Number(lo), Value::Number(hi))
+            if lo.fract() == 0.0 && hi.fract() == 0.0 && lo <= hi =>
+        {
+            let range = hi - lo + 1.0;
+            let offset = (interpreter.random().next_f64() * range).floor();
+            Ok(Value::Number(lo + offset.min(range - 1.0)))
+        }
+        _ => Err(RuntimeError::new(
+            paren.to_owned(),
+            "randomInt takes two integers, the lower bound first.".to_string(),
+        )),
+    }
+}
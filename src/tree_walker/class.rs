@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::callable::{Arity, Callable};
+use super::errors::RuntimeError;
+use super::function::LoxFunction;
+use super::gc::Marks;
+use super::interpreter::Interpreter;
+use super::syntax_tree::{InlineCache, Value};
+use super::tokens::Token;
+
+#[derive(Debug)]
+pub struct LoxClass {
+    name: String,
+    methods: HashMap<String, Rc<LoxFunction>>,
+    // The shape of its instances before they get any fields.
+    shape: Rc<Shape>,
+}
+
+impl LoxClass {
+    pub fn new(name: String, methods: HashMap<String, Rc<LoxFunction>>) -> Self {
+        LoxClass {
+            name,
+            methods,
+            shape: Rc::new(Shape::new(HashMap::new())),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        self.methods.get(name).cloned()
+    }
+
+    pub fn trace(&self, marks: &mut Marks) {
+        for method in self.methods.values() {
+            method.trace(marks);
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.find_method("init")
+            .map_or(Arity::Fixed(0), |init| init.arity())
+    }
+
+    pub fn call(
+        class: &Rc<LoxClass>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(Rc::clone(class))));
+        interpreter.heap().track_instance(&instance);
+
+        if let Some(init) = class.find_method("init") {
+            init.bind(Rc::clone(&instance))
+                .call(interpreter, arguments, paren)?;
+        }
+
+        Ok(Value::Instance(instance))
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// Which fields an instance has and where each one is kept. Instances of a
+// class that got the same fields in the same order share their shape, so a
+// place in the fields found once holds for all of them.
+#[derive(Debug)]
+pub struct Shape {
+    // Unique among every shape ever made, unlike its address.
+    id: u64,
+    index: HashMap<String, usize>,
+    // The shapes reached from this one by adding a field.
+    transitions: RefCell<HashMap<String, Rc<Shape>>>,
+}
+
+static SHAPES: AtomicU64 = AtomicU64::new(0);
+
+impl Shape {
+    fn new(index: HashMap<String, usize>) -> Self {
+        Shape {
+            id: SHAPES.fetch_add(1, Ordering::Relaxed),
+            index,
+            transitions: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn with_field(&self, name: &str) -> Rc<Shape> {
+        let mut transitions = self.transitions.borrow_mut();
+        let shape = transitions.entry(name.to_string()).or_insert_with(|| {
+            let mut index = self.index.clone();
+            index.insert(name.to_string(), index.len());
+            Rc::new(Shape::new(index))
+        });
+        Rc::clone(shape)
+    }
+}
+
+#[derive(Debug)]
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    shape: Rc<Shape>,
+    fields: Vec<Value>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        LoxInstance {
+            shape: Rc::clone(&class.shape),
+            class,
+            fields: vec![],
+        }
+    }
+
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = instance.borrow().field(&name.lexeme) {
+            return Ok(value);
+        }
+
+        let method = instance.borrow().class.find_method(&name.lexeme);
+        if let Some(method) = method {
+            let bound = method.bind(Rc::clone(instance));
+            return Ok(Value::Callable(Rc::new(bound)));
+        }
+
+        Err(RuntimeError::new(
+            name.to_owned(),
+            format!("Undefined property '{}'.", name.lexeme),
+        ))
+    }
+
+    pub fn trace(&self, marks: &mut Marks) {
+        if marks.visit(&self.class) {
+            self.class.trace(marks);
+        }
+        for value in &self.fields {
+            marks.value(value);
+        }
+    }
+
+    pub fn clear(&mut self) -> Vec<Value> {
+        self.shape = Rc::clone(&self.class.shape);
+        std::mem::take(&mut self.fields)
+    }
+
+    pub fn set(&mut self, name: &Token, value: Value) {
+        match self.shape.index.get(&name.lexeme) {
+            Some(&index) => self.fields[index] = value,
+            None => {
+                self.shape = self.shape.with_field(&name.lexeme);
+                self.fields.push(value);
+            }
+        }
+    }
+
+    fn field(&self, name: &str) -> Option<Value> {
+        self.shape
+            .index
+            .get(name)
+            .map(|&index| self.fields[index].to_owned())
+    }
+
+    // Where the field is kept in instances shaped like this one.
+    pub fn cache(&self, name: &str) -> Option<InlineCache> {
+        self.shape.index.get(name).map(|&index| InlineCache {
+            shape: self.shape.id,
+            index,
+        })
+    }
+
+    // The field a lookup cached earlier found, unless this instance is shaped
+    // differently.
+    pub fn cached(&self, cache: InlineCache) -> Option<&Value> {
+        if self.shape.id != cache.shape {
+            return None;
+        }
+        self.fields.get(cache.index)
+    }
+
+    pub fn cached_mut(&mut self, cache: InlineCache) -> Option<&mut Value> {
+        if self.shape.id != cache.shape {
+            return None;
+        }
+        self.fields.get_mut(cache.index)
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class)
+    }
+}
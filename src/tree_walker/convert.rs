@@ -0,0 +1,381 @@
+use std::any::{self, Any};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use super::list::LoxList;
+use super::map::LoxMap;
+use super::syntax_tree::Value;
+
+// Conversions between Lox values and Rust types, for host code passing values
+// in and out of scripts:
+//   let n = f64::try_from(args[0].to_owned())?;
+//   Ok(Value::from(n * 2.0))
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Number(n.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+// None is nil.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+// A value that isn't of the type it was converted to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Expected {}, got {}.", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+// Host functions fail with a message, so conversions can use `?` in them.
+impl From<ConversionError> for String {
+    fn from(e: ConversionError) -> Self {
+        e.to_string()
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Callable(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Foreign(_) => "foreign object",
+        }
+    }
+
+    // Wraps a host object for a script to hold on to.
+    pub fn foreign<T: Any>(object: T) -> Self {
+        Value::Foreign(Rc::new(object))
+    }
+
+    // The host object in a foreign value, if it is a `T`:
+    //   let counter = args[0].downcast::<Counter>()?;
+    pub fn downcast<T: Any>(&self) -> Result<Rc<T>, ConversionError> {
+        match self {
+            Value::Foreign(object) => Rc::clone(object)
+                .downcast()
+                .map_err(|_| self.mismatch(any::type_name::<T>())),
+            _ => Err(self.mismatch(any::type_name::<T>())),
+        }
+    }
+
+    fn mismatch(&self, expected: &'static str) -> ConversionError {
+        ConversionError {
+            expected,
+            found: self.type_name(),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(n),
+            _ => Err(value.mismatch("number")),
+        }
+    }
+}
+
+// Only numbers without a fraction that fit convert.
+impl TryFrom<Value> for i64 {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(n as i64),
+            _ => Err(value.mismatch("integer")),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(value.mismatch("boolean")),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s.to_string()),
+            _ => Err(value.mismatch("string")),
+        }
+    }
+}
+
+// The elements of a list, copied out of it.
+impl TryFrom<Value> for Vec<Value> {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::List(list) => Ok(list.borrow().clone()),
+            _ => Err(value.mismatch("list")),
+        }
+    }
+}
+
+// Values serialize as the data they hold: nil as a unit, then booleans,
+// numbers and strings, lists as sequences and maps as maps in insertion
+// order. So in JSON nil, true, 1.5 and "a" round-trip as null, true, 1.5 and
+// "a". Functions, classes, instances and foreign objects have no data form and
+// fail to serialize, as do lists and maps that contain themselves.
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Nested {
+            value: self,
+            depth: 0,
+        }
+        .serialize(serializer)
+    }
+}
+
+// Deeper than any real data, a list or map this nested is taken to contain
+// itself.
+const MAX_DEPTH: usize = 256;
+
+struct Nested<'a> {
+    value: &'a Value,
+    depth: usize,
+}
+
+impl Nested<'_> {
+    fn inner<'b>(&self, value: &'b Value) -> Nested<'b> {
+        Nested {
+            value,
+            depth: self.depth + 1,
+        }
+    }
+}
+
+impl Serialize for Nested<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.depth > MAX_DEPTH {
+            return Err(ser::Error::custom(
+                "Value is nested too deeply to serialize, it may contain itself.",
+            ));
+        }
+
+        match self.value {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Boolean(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => serializer.serialize_f64(*n),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::List(list) => {
+                let list = list.borrow();
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for element in list.iter() {
+                    seq.serialize_element(&self.inner(element))?;
+                }
+                seq.end()
+            }
+            Value::Map(map) => {
+                let map = map.borrow();
+                let mut entries = serializer.serialize_map(Some(map.len()))?;
+                for (key, value) in map.entries() {
+                    entries.serialize_entry(&self.inner(key), &self.inner(value))?;
+                }
+                entries.end()
+            }
+            other => Err(ser::Error::custom(format!(
+                "Can't serialize a {}.",
+                other.type_name()
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nil, a boolean, number, string, list or map")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Boolean(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::Number(n as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        Ok(Value::Number(n as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::Number(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::from(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut elements = vec![];
+        while let Some(element) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Value::List(Rc::new(RefCell::new(LoxList::from(elements)))))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut entries: A) -> Result<Value, A::Error> {
+        let mut map = LoxMap::new();
+        while let Some((key, value)) = entries.next_entry::<Value, Value>()? {
+            let kind = key.type_name();
+            if !map.set(key, value) {
+                return Err(de::Error::custom(format!("A {} can't be a map key.", kind)));
+            }
+        }
+        Ok(Value::Map(Rc::new(RefCell::new(map))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(f64::try_from(Value::from(1.5)), Ok(1.5));
+        assert_eq!(i64::try_from(Value::from(-3)), Ok(-3));
+        assert_eq!(bool::try_from(Value::from(true)), Ok(true));
+        assert_eq!(String::try_from(Value::from("hi")), Ok("hi".to_string()));
+        assert!(matches!(Value::from(None::<f64>), Value::Nil));
+        assert!(matches!(Value::from(()), Value::Nil));
+    }
+
+    #[test]
+    fn mismatches() {
+        let error = f64::try_from(Value::from("1")).unwrap_err();
+        assert_eq!(error.to_string(), "Expected number, got string.");
+        assert_eq!(
+            i64::try_from(Value::from(0.5)).unwrap_err().expected,
+            "integer"
+        );
+        assert_eq!(String::try_from(Value::Nil).unwrap_err().found, "nil");
+    }
+
+    #[test]
+    fn foreign() {
+        #[derive(Debug)]
+        struct Handle(u32);
+
+        let value = Value::foreign(Handle(7));
+        assert_eq!(value.downcast::<Handle>().unwrap().0, 7);
+        assert!(Value::is_equal(&value, &value.clone()));
+        assert!(!Value::is_equal(&value, &Value::foreign(Handle(7))));
+
+        let error = value.downcast::<String>().unwrap_err();
+        assert_eq!(error.found, "foreign object");
+        assert_eq!(error.expected, "alloc::string::String");
+        assert_eq!(Value::Nil.downcast::<Handle>().unwrap_err().found, "nil");
+        assert!(serde_json::to_string(&value).is_err());
+    }
+
+    #[test]
+    fn serde() {
+        for json in [
+            "null",
+            "true",
+            "1.5",
+            "\"a\"",
+            "[1.0,null,[\"b\"]]",
+            "{\"k\":{\"n\":2.0}}",
+        ] {
+            let value: Value = serde_json::from_str(json).unwrap();
+            assert_eq!(serde_json::to_string(&value).unwrap(), json);
+        }
+
+        let list = Rc::new(RefCell::new(LoxList::default()));
+        list.borrow_mut().push(Value::List(Rc::clone(&list)));
+        let error = serde_json::to_string(&Value::List(Rc::clone(&list))).unwrap_err();
+        assert!(error.to_string().contains("nested too deeply"));
+        list.borrow_mut().clear();
+
+        assert!(serde_json::from_str::<Value>("{\"k\": 1}").is_ok());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use super::interpreter::{Hook, Interpreter};
+use super::syntax_tree::Stmt;
+
+const HELP: &str = "\
+step, s          run to the next statement
+next, n          run to the next statement outside of calls made from here
+continue, c      run to the next breakpoint
+break, b LINE    add a breakpoint
+delete, d LINE   remove a breakpoint
+locals, l        show the variables in scope
+print, p NAME    show a variable
+help, h          show this help";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    // Stops once back at this call depth or above it.
+    Next(usize),
+    Continue,
+    // Input ran out, the rest of the program runs without stopping.
+    Detached,
+}
+
+// Pauses the program at breakpoints and while stepping, taking commands from
+// the input. Without breakpoints it pauses on the first statement.
+pub struct Debugger {
+    source: String,
+    // Offset where each line starts, to find the line statements are on.
+    line_starts: Vec<usize>,
+    breakpoints: BTreeSet<u64>,
+    mode: Mode,
+    // Line of the last statement seen, a breakpoint only stops once per line
+    // even if several statements start on it.
+    line: u64,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+}
+
+impl Debugger {
+    pub fn new(
+        source: &str,
+        breakpoints: impl IntoIterator<Item = u64>,
+        input: Box<dyn BufRead>,
+        output: Box<dyn Write>,
+    ) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let breakpoints: BTreeSet<u64> = breakpoints.into_iter().collect();
+        let mode = if breakpoints.is_empty() {
+            Mode::Step
+        } else {
+            Mode::Continue
+        };
+
+        Debugger {
+            source: source.to_string(),
+            line_starts,
+            breakpoints,
+            mode,
+            line: 0,
+            input,
+            output,
+        }
+    }
+
+    fn line_of(&self, offset: usize) -> u64 {
+        self.line_starts.partition_point(|&start| start <= offset) as u64
+    }
+
+    fn source_line(&self, line: u64) -> &str {
+        self.source
+            .lines()
+            .nth(line as usize - 1)
+            .unwrap_or_default()
+    }
+
+    // Writes failing means nobody is watching, the program carries on.
+    fn show(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+
+    // Takes commands until one resumes the program.
+    fn pause(&mut self, line: u64, interpreter: &Interpreter) {
+        let text = format!("{:>4} | {}", line, self.source_line(line).trim());
+        self.show(&text);
+
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+
+            let mut command = String::new();
+            if !matches!(self.input.read_line(&mut command), Ok(n) if n > 0) {
+                self.mode = Mode::Detached;
+                return;
+            }
+
+            let mut words = command.split_whitespace();
+            let (command, argument) = (words.next().unwrap_or_default(), words.next());
+            match (command, argument) {
+                ("step" | "s", None) => self.mode = Mode::Step,
+                ("next" | "n", None) => self.mode = Mode::Next(interpreter.call_depth()),
+                ("continue" | "c", None) => self.mode = Mode::Continue,
+                ("break" | "b" | "delete" | "d", Some(argument)) => {
+                    match argument.parse() {
+                        Ok(line) if command.starts_with('b') => {
+                            self.breakpoints.insert(line);
+                        }
+                        Ok(line) => {
+                            self.breakpoints.remove(&line);
+                        }
+                        Err(_) => self.show("Expect a line number."),
+                    }
+                    continue;
+                }
+                ("locals" | "l", None) => {
+                    self.locals(interpreter);
+                    continue;
+                }
+                ("print" | "p", Some(name)) => {
+                    self.print(name, interpreter);
+                    continue;
+                }
+                ("", None) => continue,
+                _ => {
+                    self.show(HELP);
+                    continue;
+                }
+            }
+            return;
+        }
+    }
+
+    fn locals(&mut self, interpreter: &Interpreter) {
+        let scopes = interpreter.locals();
+        if scopes.iter().all(|scope| scope.is_empty()) {
+            self.show("No local variables.");
+        }
+        for (depth, scope) in scopes.iter().enumerate() {
+            for (name, value) in scope {
+                let value = value
+                    .as_ref()
+                    .map_or("<uninitialized>".to_string(), |v| v.to_string());
+                let text = format!("{}{} = {}", "  ".repeat(depth), name, value);
+                self.show(&text);
+            }
+        }
+    }
+
+    fn print(&mut self, name: &str, interpreter: &Interpreter) {
+        let local = interpreter
+            .locals()
+            .into_iter()
+            .flatten()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value);
+        let text = match local.or_else(|| interpreter.global(name).map(Some)) {
+            Some(Some(value)) => format!("{} = {}", name, value),
+            Some(None) => format!("{} = <uninitialized>", name),
+            None => format!("No variable '{}' in scope.", name),
+        };
+        self.show(&text);
+    }
+}
+
+impl Hook for Debugger {
+    fn before(&mut self, stmt: &Stmt, interpreter: &Interpreter) {
+        // Blocks only hold statements, those are where the program stops.
+        if matches!(stmt, Stmt::Block(_)) {
+            return;
+        }
+
+        let line = self.line_of(stmt.span().start);
+        let breakpoint = self.breakpoints.contains(&line) && line != self.line;
+        let stop = match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => interpreter.call_depth() <= depth || breakpoint,
+            Mode::Continue => breakpoint,
+            Mode::Detached => false,
+        };
+        self.line = line;
+
+        if stop {
+            self.pause(line, interpreter);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use crate::tree_walker::interpreter::Options;
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::resolver::Resolver;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs the program under the debugger, giving back what it showed.
+    fn debug(source: &str, breakpoints: Vec<u64>, commands: &str) -> String {
+        let output = Output::default();
+        let debugger = Debugger::new(
+            source,
+            breakpoints,
+            Box::new(io::Cursor::new(commands.to_string())),
+            Box::new(output.clone()),
+        );
+
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        Resolver::new(&parser.exprs).resolve(&statements);
+        let mut interpreter = Interpreter::new(Options::default()).with_hook(Box::new(debugger));
+        interpreter
+            .interpret(&statements, &Rc::new(parser.exprs))
+            .unwrap();
+
+        let shown = output.0.borrow().clone();
+        String::from_utf8(shown).unwrap().replace("(debug) ", "")
+    }
+
+    #[test]
+    fn breakpoints_and_locals() {
+        let source = "fun f(a) {\n  var b = a + 1;\n  return b;\n}\nvar x = f(1);\nvar y = f(x);";
+        assert_eq!(
+            debug(source, vec![3], "l\np x\nc\np b\nc\n"),
+            "   3 | return b;\na = 1\nb = 2\nNo variable 'x' in scope.\n\
+             \x20  3 | return b;\nb = 3\n"
+        );
+    }
+
+    #[test]
+    fn nested_scopes() {
+        let source = "var g = 1;\n{\n  var a;\n  {\n    var b = 2;\n    print b;\n  }\n}";
+        assert_eq!(
+            debug(source, vec![6], "l\np g\n"),
+            "   6 | print b;\nb = 2\n  a = <uninitialized>\ng = 1\n"
+        );
+    }
+
+    #[test]
+    fn stepping() {
+        let source = "fun f() {\n  return 1;\n}\nvar x = f();\nprint x;";
+        // Step goes into the call, next stays at the same depth.
+        assert_eq!(
+            debug(source, vec![], "n\ns\ns\nn\n"),
+            "   1 | fun f() {\n   4 | var x = f();\n   2 | return 1;\n   5 | print x;\n"
+        );
+        assert_eq!(
+            debug(source, vec![], "n\nn\nc\n"),
+            "   1 | fun f() {\n   4 | var x = f();\n   5 | print x;\n"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use super::stack;
+use super::syntax_tree::{Expr, ExprId, Exprs, Stmt};
+use super::tokens::LiteralType;
+
+// Renders the syntax tree as a Graphviz graph, one node per AST node labeled
+// by its operator or value, children in source order.
+#[allow(dead_code)]
+pub fn ast_to_dot(exprs: &Exprs, expr: ExprId) -> String {
+    let mut dot = Dot::new(exprs);
+    dot.expr(expr);
+    dot.finish()
+}
+
+pub fn program_to_dot(statements: &[Stmt], exprs: &Exprs) -> String {
+    let mut dot = Dot::new(exprs);
+    let root = dot.node("program");
+    for statement in statements {
+        let child = dot.stmt(statement);
+        dot.edge(root, child);
+    }
+    dot.finish()
+}
+
+struct Dot<'a> {
+    exprs: &'a Exprs,
+    out: String,
+    nodes: usize,
+}
+
+impl<'a> Dot<'a> {
+    fn new(exprs: &'a Exprs) -> Self {
+        Dot {
+            exprs,
+            out: "digraph ast {\n  node [shape=box];\n".to_string(),
+            nodes: 0,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.out.push_str("}\n");
+        self.out
+    }
+
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+        let _ = writeln!(self.out, "  n{} [label=\"{}\"];", id, label);
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        let _ = writeln!(self.out, "  n{} -> n{};", from, to);
+    }
+
+    // A node with the given children hanging from it.
+    fn parent<'b>(&mut self, label: &str, children: impl IntoIterator<Item = &'b ExprId>) -> usize {
+        let id = self.node(label);
+        for child in children {
+            let child = stack::guard(|| self.expr(*child));
+            self.edge(id, child);
+        }
+        id
+    }
+
+    fn expr(&mut self, expr: ExprId) -> usize {
+        let exprs = self.exprs;
+        match &exprs[expr] {
+            Expr::Assign(e) => self.parent(&format!("= {}", e.name.lexeme), [&e.value]),
+            Expr::Ternary(e) => self.parent("?:", [&e.condition, &e.then, &e.r#else]),
+            Expr::Logical(e) => self.parent(&e.operator.lexeme, [&e.left, &e.right]),
+            Expr::Binary(e) => self.parent(&e.operator.lexeme, [&e.left, &e.right]),
+            Expr::Unary(e) => self.parent(&e.operator.lexeme, [&e.right]),
+            Expr::Grouping(e) => self.parent("group", [&e.expression]),
+            Expr::Literal(e) => match &e.value {
+                Some(LiteralType::String(s)) => self.node(&format!("\"{}\"", s)),
+                Some(LiteralType::Nil) | None => self.node("nil"),
+                Some(v) => self.node(&v.to_string()),
+            },
+            Expr::Variable(e) => self.node(&e.name.lexeme),
+            Expr::Call(e) => self.parent("call", std::iter::once(&e.callee).chain(&e.arguments)),
+            Expr::Get(e) => self.parent(&format!(".{}", e.name.lexeme), [&e.object]),
+            Expr::Set(e) => self.parent(&format!(".{} =", e.name.lexeme), [&e.object, &e.value]),
+            Expr::This(_) => self.node("this"),
+            Expr::List(e) => self.parent("list", &e.elements),
+            Expr::Map(e) => self.parent("map", e.entries.iter().flat_map(|(k, v)| [k, v])),
+            Expr::Index(e) => self.parent("[]", [&e.object, &e.index]),
+            Expr::SetIndex(e) => self.parent("[] =", [&e.object, &e.index, &e.value]),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> usize {
+        let (id, children): (usize, Vec<&Stmt>) = match stmt {
+            Stmt::Expression(s) => (self.parent(";", [&s.expression]), vec![]),
+            Stmt::Print(s) => (self.parent("print", [&s.expression]), vec![]),
+            Stmt::Var(s) => {
+                let keyword = if s.constant { "const" } else { "var" };
+                let label = format!("{} {}", keyword, s.name.lexeme);
+                (self.parent(&label, &s.initializer), vec![])
+            }
+            Stmt::Block(s) => (self.node("block"), s.statements.iter().collect()),
+            Stmt::If(s) => (
+                self.parent("if", [&s.condition]),
+                std::iter::once(&s.then).chain(&s.r#else).collect(),
+            ),
+            Stmt::While(s) => (
+                self.parent("while", std::iter::once(&s.condition).chain(&s.increment)),
+                vec![&s.body],
+            ),
+            Stmt::ForIn(s) => (
+                self.parent(&format!("for {} in", s.name.lexeme), [&s.iterable]),
+                vec![&s.body],
+            ),
+            Stmt::Function(s) => {
+                let params: Vec<&str> = s.params.iter().map(|p| p.lexeme.as_str()).collect();
+                let label = format!("fun {}({})", s.name.lexeme, params.join(", "));
+                (self.node(&label), s.body.iter().collect())
+            }
+            Stmt::Return(s) => (self.parent("return", &s.value), vec![]),
+            Stmt::Class(s) => {
+                let id = self.node(&format!("class {}", s.name.lexeme));
+                for method in &s.methods {
+                    let child = self.stmt(&Stmt::Function(Rc::clone(method)));
+                    self.edge(id, child);
+                }
+                (id, vec![])
+            }
+            Stmt::Break(_) => (self.node("break"), vec![]),
+            Stmt::Continue(_) => (self.node("continue"), vec![]),
+            Stmt::Match(s) => {
+                let id = self.parent("match", [&s.subject]);
+                for (value, body) in &s.arms {
+                    let arm = self.parent("arm", [value]);
+                    let child = self.stmt(body);
+                    self.edge(arm, child);
+                    self.edge(id, arm);
+                }
+                (id, s.r#else.iter().collect())
+            }
+        };
+
+        for child in children {
+            let child = self.stmt(child);
+            self.edge(id, child);
+        }
+        id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    #[test]
+    fn one_node_per_ast_node() {
+        let tokens = Scanner::new("-a * \"b\";".to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        let Stmt::Expression(ref e) = statements[0] else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            ast_to_dot(&parser.exprs, e.expression),
+            "digraph ast {\n  node [shape=box];\n\
+             \x20 n0 [label=\"*\"];\n\
+             \x20 n1 [label=\"-\"];\n\
+             \x20 n2 [label=\"a\"];\n\
+             \x20 n1 -> n2;\n\
+             \x20 n0 -> n1;\n\
+             \x20 n3 [label=\"\\\"b\\\"\"];\n\
+             \x20 n0 -> n3;\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn deep_expressions() {
+        let source = format!("print {};", vec!["1"; 100_000].join(" + "));
+        let mut parser = Parser::new(Scanner::new(source).scan_tokens());
+        let statements = parser.parse();
+
+        let dot = program_to_dot(&statements, &parser.exprs);
+        assert_eq!(dot.matches(" -> ").count(), 200_000);
+    }
+}
@@ -0,0 +1,370 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use super::errors::RuntimeError;
+use super::gc::Marks;
+use super::syntax_tree::{Slot, Value};
+use super::tokens::Token;
+
+// Scopes are shared, closures and blocks keep their enclosing environment
+// alive through the Rc for as long as they need it. Variables declared
+// without an initializer hold None until they are first assigned.
+//
+// Locals are read by the slot the resolver gave them, their names are only
+// kept for the debugger. Globals are looked up by name and may be redefined,
+// so the outermost environment also keeps an index of its names, and which of
+// them are constants: the resolver only sees one run at a time, a constant
+// from an earlier one is caught here.
+#[derive(Debug, Default)]
+pub struct Environment {
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    names: Vec<String>,
+    values: Vec<Option<Value>>,
+    index: HashMap<String, usize>,
+    constants: HashSet<String>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            enclosing: Some(enclosing),
+            ..Environment::default()
+        }
+    }
+
+    pub fn define(&mut self, name: String, value: Value) {
+        self.constants.remove(&name);
+        self.bind(name, Some(value));
+    }
+
+    // Globals stay constant until they are declared again.
+    pub fn define_constant(&mut self, name: String, value: Value) {
+        if self.enclosing.is_none() {
+            self.constants.insert(name.to_owned());
+        }
+        self.bind(name, Some(value));
+    }
+
+    // Adds a variable that can't be read until something is assigned to it.
+    pub fn declare(&mut self, name: String) {
+        self.constants.remove(&name);
+        self.bind(name, None);
+    }
+
+    // Takes the next slot, or the one of the global already there.
+    fn bind(&mut self, name: String, value: Option<Value>) {
+        if self.enclosing.is_none() {
+            if let Some(&slot) = self.index.get(&name) {
+                self.values[slot] = value;
+                return;
+            }
+            self.index.insert(name.to_owned(), self.values.len());
+        }
+        self.names.push(name);
+        self.values.push(value);
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        match self.enclosing {
+            None => self.index.get(name).copied(),
+            Some(_) => self.names.iter().rposition(|n| n == name),
+        }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        match self.position(&name.lexeme) {
+            Some(slot) => read(&self.values[slot], name),
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow().get(name),
+                None => Err(undefined(name)),
+            },
+        }
+    }
+
+    // Where a global is kept, for reading it again through `get_global`.
+    pub fn global_index(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    // Reads a global by where it was found before, None if that's now
+    // something else.
+    pub fn get_global(&self, index: usize, name: &Token) -> Option<Result<Value, RuntimeError>> {
+        match self.names.get(index) {
+            Some(n) if *n == name.lexeme => Some(read(&self.values[index], name)),
+            _ => None,
+        }
+    }
+
+    // Reads the slot of the environment `distance` hops up the chain, as
+    // found by the resolver.
+    pub fn get_at(&self, slot: Slot, name: &Token) -> Result<Value, RuntimeError> {
+        if slot.distance == 0 {
+            return match self.values.get(slot.index) {
+                Some(value) => read(value, name),
+                None => Err(undefined(name)),
+            };
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get_at(
+                Slot {
+                    distance: slot.distance - 1,
+                    ..slot
+                },
+                name,
+            ),
+            None => Err(undefined(name)),
+        }
+    }
+
+    pub fn trace(&self, marks: &mut Marks) {
+        for value in self.values.iter().flatten() {
+            marks.value(value);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            marks.environment(enclosing);
+        }
+    }
+
+    // Drops every variable, handing them back so the caller decides when.
+    pub fn clear(&mut self) -> Vec<Option<Value>> {
+        self.names.clear();
+        self.index.clear();
+        self.constants.clear();
+        std::mem::take(&mut self.values)
+    }
+
+    pub fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    // The variables declared right here, by name.
+    pub fn variables(&self) -> Vec<(String, Option<Value>)> {
+        let mut variables: Vec<_> = self
+            .names
+            .iter()
+            .cloned()
+            .zip(self.values.iter().cloned())
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    pub fn get_local(&self, name: &str) -> Option<Value> {
+        self.position(name)
+            .and_then(|slot| self.values[slot].to_owned())
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if self.constants.contains(&name.lexeme) {
+            return Err(RuntimeError::new(
+                name.to_owned(),
+                format!("Can't assign to constant '{}'.", name.lexeme),
+            ));
+        }
+        match self.position(&name.lexeme) {
+            Some(slot) => {
+                self.values[slot] = Some(value);
+                Ok(())
+            }
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+                None => Err(undefined(name)),
+            },
+        }
+    }
+
+    pub fn assign_at(
+        &mut self,
+        slot: Slot,
+        name: &Token,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        if slot.distance == 0 {
+            return match self.values.get_mut(slot.index) {
+                Some(v) => {
+                    *v = Some(value);
+                    Ok(())
+                }
+                None => Err(undefined(name)),
+            };
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(
+                Slot {
+                    distance: slot.distance - 1,
+                    ..slot
+                },
+                name,
+                value,
+            ),
+            None => Err(undefined(name)),
+        }
+    }
+}
+
+fn read(value: &Option<Value>, name: &Token) -> Result<Value, RuntimeError> {
+    match value {
+        Some(value) => Ok(value.to_owned()),
+        None => Err(uninitialized(name)),
+    }
+}
+
+fn undefined(name: &Token) -> RuntimeError {
+    RuntimeError::new(
+        name.to_owned(),
+        format!("Undefined variable '{}'.", name.lexeme),
+    )
+}
+
+fn uninitialized(name: &Token) -> RuntimeError {
+    RuntimeError::new(
+        name.to_owned(),
+        format!("Variable '{}' used before initialization.", name.lexeme),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::tokens::{Span, TokenType};
+
+    use super::*;
+
+    fn name(lexeme: &str) -> Token {
+        Token {
+            r#type: TokenType::Identifier,
+            lexeme: lexeme.to_string(),
+            literal: None,
+            line: 1,
+            column: 1,
+            length: lexeme.len() as u64,
+            span: Span::default(),
+        }
+    }
+
+    fn slot(distance: usize, index: usize) -> Slot {
+        Slot { distance, index }
+    }
+
+    fn read(env: &Rc<RefCell<Environment>>, lexeme: &str) -> Option<String> {
+        env.borrow().get(&name(lexeme)).ok().map(|v| v.to_string())
+    }
+
+    #[test]
+    fn shadowing() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals
+            .borrow_mut()
+            .define("a".to_string(), Value::Number(1.0));
+
+        let inner = Rc::new(RefCell::new(Environment::new_enclosed(globals.clone())));
+        inner
+            .borrow_mut()
+            .define("a".to_string(), Value::Number(2.0));
+
+        assert_eq!(read(&inner, "a"), Some("2".to_string()));
+        assert_eq!(read(&globals, "a"), Some("1".to_string()));
+
+        // Assigning goes to the innermost binding only.
+        inner
+            .borrow_mut()
+            .assign(&name("a"), Value::Number(3.0))
+            .unwrap();
+        assert_eq!(read(&inner, "a"), Some("3".to_string()));
+        assert_eq!(read(&globals, "a"), Some("1".to_string()));
+
+        // Resolved distances skip over the shadowing binding.
+        inner
+            .borrow_mut()
+            .assign_at(slot(1, 0), &name("a"), Value::Number(4.0))
+            .unwrap();
+        let outer = inner.borrow().get_at(slot(1, 0), &name("a")).unwrap();
+        assert_eq!(outer.to_string(), "4");
+        assert_eq!(read(&inner, "a"), Some("3".to_string()));
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(read(&env, "a"), None);
+        assert!(env.borrow_mut().assign(&name("a"), Value::Nil).is_err());
+        assert!(env.borrow().get_at(slot(1, 0), &name("a")).is_err());
+    }
+
+    #[test]
+    fn uninitialized_variables() {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        env.borrow_mut().declare("a".to_string());
+        assert_eq!(read(&env, "a"), None);
+        assert!(env.borrow().get_at(slot(0, 0), &name("a")).is_err());
+
+        env.borrow_mut().assign(&name("a"), Value::Nil).unwrap();
+        assert_eq!(read(&env, "a"), Some("nil".to_string()));
+    }
+
+    #[test]
+    fn closure_capture() {
+        // A closure holds on to its scope after the block creating it is gone,
+        // and sees later writes to it.
+        let closure = {
+            let globals = Rc::new(RefCell::new(Environment::new()));
+            let block = Rc::new(RefCell::new(Environment::new_enclosed(globals)));
+            block
+                .borrow_mut()
+                .define("count".to_string(), Value::Number(0.0));
+            Rc::new(RefCell::new(Environment::new_enclosed(block)))
+        };
+
+        for _ in 0..3 {
+            let count = closure.borrow().get_at(slot(1, 0), &name("count")).unwrap();
+            let Value::Number(n) = count else {
+                unreachable!()
+            };
+            closure
+                .borrow_mut()
+                .assign_at(slot(1, 0), &name("count"), Value::Number(n + 1.0))
+                .unwrap();
+        }
+
+        assert_eq!(read(&closure, "count"), Some("3".to_string()));
+    }
+
+    #[test]
+    fn slots() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("a".to_string(), Value::Nil);
+        globals.borrow_mut().define("b".to_string(), Value::Nil);
+        // Globals can be defined again, keeping their slot.
+        globals
+            .borrow_mut()
+            .define("a".to_string(), Value::Number(1.0));
+        assert_eq!(globals.borrow().variables().len(), 2);
+
+        let inner = Rc::new(RefCell::new(Environment::new_enclosed(globals.clone())));
+        inner.borrow_mut().declare("c".to_string());
+        inner
+            .borrow_mut()
+            .define("d".to_string(), Value::Number(2.0));
+
+        let get = |slot: Slot| {
+            inner
+                .borrow()
+                .get_at(slot, &name("x"))
+                .map(|v| v.to_string())
+        };
+        assert_eq!(get(slot(0, 1)).ok(), Some("2".to_string()));
+        assert_eq!(get(slot(1, 0)).ok(), Some("1".to_string()));
+        assert!(get(slot(0, 0)).is_err());
+        assert!(get(slot(0, 2)).is_err());
+        assert!(inner
+            .borrow_mut()
+            .assign_at(slot(2, 0), &name("x"), Value::Nil)
+            .is_err());
+    }
+}
@@ -1,47 +1,524 @@
+use std::fmt;
+use std::io::Write;
+
+use super::parser::ParseError;
+use super::syntax_tree::Value;
 use super::tokens::{Token, TokenType};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Error {
     pub line: u64,
+    pub column: u64,
+    // Chars the offending token spans on its line.
+    pub length: u64,
     pub message: String,
     pub place: String,
+    pub severity: Severity,
 }
 
-pub fn report(e: Error) {
-    eprintln!("[line {}] Error: {}: {}", e.line, e.place, e.message);
+// Plain, without the source to quote.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let render = Render {
+            source: "",
+            source_id: 0,
+            color: false,
+        };
+        write!(f, "{}", render.error(self))
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Severity {
+    // Red for errors and yellow for warnings, both bold.
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+// How errors get rendered: the source they were found in, so the offending
+// line can be quoted under the message, and whether to color them for a
+// terminal.
+#[derive(Debug, Clone, Copy)]
+pub struct Render<'a> {
+    pub source: &'a str,
+    // The number the source was scanned with, see `Scanner::with_source`.
+    pub source_id: u32,
+    pub color: bool,
 }
 
-pub fn error(token: &Token, message: &String) {
-    if token.r#type == TokenType::Eof {
-        let e = Error {
-            line: token.line.to_owned(),
-            place: " at the end".to_string(),
-            message: message.to_owned(),
+impl Render<'_> {
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn error(&self, e: &Error) -> String {
+        let mut out = format!(
+            "[line {}, col {}] {}: {}: {}",
+            e.line,
+            e.column,
+            self.paint(&e.severity.to_string(), e.severity.color()),
+            e.place,
+            e.message
+        );
+        out.push_str(&self.quote(e.line, e.column, e.length, e.severity.color()));
+        out
+    }
+
+    // The source line with the span underlined, each on a line of its own
+    // after what came before. Nothing if the line isn't in the source.
+    fn quote(&self, line: u64, column: u64, length: u64, color: &str) -> String {
+        let Some(text) = self.source.lines().nth((line as usize).wrapping_sub(1)) else {
+            return String::new();
         };
-        report(e)
+
+        // Tabs are kept so the carets line up however wide they show.
+        let padding: String = text
+            .chars()
+            .take(column.saturating_sub(1) as usize)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(length.max(1) as usize);
+        let number = line.to_string();
+
+        format!(
+            "\n{} {}\n{} {}{}",
+            self.paint(&format!("{} |", number), BLUE),
+            text,
+            self.paint(&format!("{} |", " ".repeat(number.len())), BLUE),
+            padding,
+            self.paint(&carets, color)
+        )
     }
+}
+
+// Reporting goes wherever the host wants it, stderr for the jlox binary. A
+// failed write has nowhere better to be reported, it's dropped.
+pub fn report(e: Error, out: &mut dyn Write, render: &Render) {
+    let _ = writeln!(out, "{}", render.error(&e));
+}
+
+// Collects the errors found while scanning, parsing and resolving so they can
+// all be reported at once, in source order. Warnings are reported along with
+// them but never make a program fail.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    errors: Vec<Error>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics { errors: vec![] }
+    }
+
+    pub fn error(&mut self, token: &Token, message: &str) {
+        self.push(token, message, Severity::Error);
+    }
+
+    pub fn warning(&mut self, token: &Token, message: &str) {
+        self.push(token, message, Severity::Warning);
+    }
+
+    pub fn push(&mut self, token: &Token, message: &str, severity: Severity) {
+        let place = match token.r#type {
+            TokenType::Error => String::new(),
+            TokenType::Eof => " at the end".to_string(),
+            _ => " at '".to_string() + &token.lexeme + "'",
+        };
+
+        self.errors.push(Error {
+            line: token.line,
+            column: token.column,
+            length: token.length,
+            message: message.to_string(),
+            place,
+            severity,
+        });
+    }
+
+    pub fn had_error(&self) -> bool {
+        self.errors.iter().any(|e| e.severity == Severity::Error)
+    }
+
+    pub fn extend(&mut self, other: Diagnostics) {
+        self.errors.extend(other.errors);
+    }
+
+    // Reports everything collected so far, diagnostics found on the same spot
+    // keep the order they were found in.
+    pub fn report(&mut self, out: &mut dyn Write, render: &Render) {
+        self.sort();
+        for e in self.errors.drain(..) {
+            report(e, out, render);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Error> {
+        self.errors.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Error> {
+        self.errors.iter_mut()
+    }
+
+    fn sort(&mut self) {
+        self.errors.sort_by_key(|e| (e.line, e.column));
+    }
+
+    #[cfg(test)]
+    pub fn messages(&self) -> Vec<&str> {
+        self.errors.iter().map(|e| e.message.as_str()).collect()
+    }
+}
+
+// One diagnostic per line, in source order.
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut errors: Vec<&Error> = self.errors.iter().collect();
+        errors.sort_by_key(|e| (e.line, e.column));
+        for (i, e) in errors.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+// A call being run, `line` is where it was called from. Calls made by the
+// host have no line to point at, it's 0 for them.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub line: u64,
+}
 
-    let e = Error {
-        line: token.line.to_owned(),
-        message: message.to_owned(),
-        place: " at '".to_string() + &token.lexeme.to_owned() + "'",
-    };
-    report(e)
+// The bound on execution a runtime error comes from hitting, if any.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    CallDepth,
+    Steps,
+    Time,
 }
 
+#[derive(Debug)]
 pub struct RuntimeError {
-    token: Token,
-    message: String,
+    // Boxed to keep results carrying errors small.
+    pub token: Box<Token>,
+    pub message: String,
+    // The calls the error escaped from, innermost first. Empty for errors
+    // raised outside of any call.
+    pub trace: Vec<Frame>,
+    pub limit: Option<Limit>,
+    // Set when the script called exit(), it unwinds like an error so the
+    // host gets to decide what to do with the code.
+    pub exit: Option<i32>,
 }
 
 impl RuntimeError {
     pub fn new(token: Token, message: String) -> Self {
-        Self { token, message }
+        Self {
+            token: Box::new(token),
+            message,
+            trace: vec![],
+            limit: None,
+            exit: None,
+        }
+    }
+
+    pub fn limit(token: Token, message: String, limit: Limit) -> Self {
+        Self {
+            limit: Some(limit),
+            ..Self::new(token, message)
+        }
+    }
+
+    pub fn exit(token: Token, code: i32) -> Self {
+        Self {
+            exit: Some(code),
+            ..Self::new(token, format!("Exited with code {}.", code))
+        }
+    }
+
+    // Errors raised by a call the host made, before any of the callee ran,
+    // have no place in the source. Their token is on line 0, as source lines
+    // start at 1.
+    pub fn located(&self) -> bool {
+        self.token.line > 0
     }
 
-    pub fn report(&self) {
-        println!(
-            "{} \n[token {}]\n[line {}]",
-            self.message, self.token.lexeme, self.token.line
+    pub fn report(&self, out: &mut dyn Write, render: &Render) {
+        let message = render.paint(&self.message, Severity::Error.color());
+        if self.located() {
+            let _ = writeln!(
+                out,
+                "{} \n[token {}]\n[line {}, col {}]",
+                message, self.token.lexeme, self.token.line, self.token.column
+            );
+        } else {
+            let _ = writeln!(out, "{}", message);
+        }
+        // The token may come from source run earlier, like a function defined
+        // on a previous line of the prompt, then there's nothing to quote.
+        let span = self.token.span;
+        if span.source == render.source_id
+            && render.source.get(span.start..span.end) == Some(self.token.lexeme.as_str())
+        {
+            let token = &self.token;
+            let quote = render.quote(
+                token.line,
+                token.column,
+                token.length,
+                Severity::Error.color(),
+            );
+            let _ = writeln!(out, "{}", quote.trim_start_matches('\n'));
+        }
+        // Runaway recursion would bury the error under its own trace.
+        const SHOWN: usize = 10;
+        for frame in self.trace.iter().take(SHOWN) {
+            let _ = match frame.line {
+                0 => writeln!(out, "  in {}(), called by the host", frame.name),
+                line => writeln!(out, "  in {}(), called at line {}", frame.name, line),
+            };
+        }
+        if self.trace.len() > SHOWN {
+            let _ = writeln!(out, "  ... {} more calls", self.trace.len() - SHOWN);
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.located() {
+            return write!(f, "Error: {}", self.message);
+        }
+        write!(
+            f,
+            "[line {}, col {}] Error: {}",
+            self.token.line, self.token.column, self.message
         )
     }
 }
+
+impl std::error::Error for RuntimeError {}
+
+// Anything that can go wrong running a program, for hosts that want a single
+// error type to pass along with `?`. Diagnostics only end up here when one of
+// them is an error.
+#[derive(Debug)]
+pub enum LoxError {
+    Compile(Diagnostics),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoxError::Compile(e) => write!(f, "{}", e),
+            LoxError::Parse(e) => write!(f, "{}", e),
+            LoxError::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoxError::Compile(e) => Some(e),
+            LoxError::Parse(e) => Some(e),
+            LoxError::Runtime(e) => Some(e),
+        }
+    }
+}
+
+impl From<Diagnostics> for LoxError {
+    fn from(e: Diagnostics) -> Self {
+        LoxError::Compile(e)
+    }
+}
+
+impl From<ParseError> for LoxError {
+    fn from(e: ParseError) -> Self {
+        LoxError::Parse(e)
+    }
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(e: RuntimeError) -> Self {
+        LoxError::Runtime(e)
+    }
+}
+
+// Unwinds the statements being executed, either because of an error or
+// because control flow jumps out of them (return, break and continue). Loop
+// jumps carry the label they target, if any.
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(Value),
+    // Returning a call, it's made once the function returning it is done so
+    // calls in tail position don't nest.
+    TailCall(Box<TailCall>),
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+pub struct TailCall {
+    pub callee: Value,
+    pub arguments: Vec<Value>,
+    pub paren: Token,
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(e: RuntimeError) -> Self {
+        Unwind::Error(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    #[test]
+    fn diagnostics_keep_source_order() {
+        let tokens = Scanner::new("var = 1;\nprint (2;".to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        parser.parse();
+
+        let mut diagnostics = Diagnostics::new();
+        let tokens = Scanner::new("@".to_string()).scan_tokens();
+        diagnostics.error(&tokens[0], "Unexpected character: @");
+        diagnostics.extend(parser.diagnostics);
+
+        diagnostics.sort();
+        assert_eq!(
+            diagnostics.messages(),
+            vec![
+                "Unexpected character: @",
+                "Expect variable name.",
+                "Expect ')' after expression"
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_the_source() {
+        let source = "var a = 1;\n\tprint a +   ;";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.error(&tokens[8], "Expect expression.");
+
+        let render = Render {
+            source,
+            source_id: 0,
+            color: false,
+        };
+        assert_eq!(
+            render.error(&diagnostics.errors[0]),
+            "[line 2, col 14] Error:  at ';': Expect expression.\n\
+             2 | \tprint a +   ;\n\
+             \x20\x20| \t            ^"
+        );
+
+        let colored = Render {
+            source,
+            source_id: 0,
+            color: true,
+        };
+        assert!(colored
+            .error(&diagnostics.errors[0])
+            .contains("\x1b[1;31mError\x1b[0m"));
+        // Lines outside of the source aren't quoted.
+        let render = Render {
+            source: "",
+            source_id: 0,
+            color: false,
+        };
+        assert!(!render.error(&diagnostics.errors[0]).contains('|'));
+    }
+
+    #[test]
+    fn reports_to_any_writer() {
+        let tokens = Scanner::new("nil();".to_string()).scan_tokens();
+        let mut error = RuntimeError::new(tokens[2].to_owned(), "Can't call nil.".to_string());
+        error.trace.push(Frame {
+            name: "f".to_string(),
+            line: 3,
+        });
+
+        let mut out = vec![];
+        let render = Render {
+            source: "nil();",
+            source_id: 0,
+            color: false,
+        };
+        error.report(&mut out, &render);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Can't call nil. \n[token )]\n[line 1, col 5]\n1 | nil();\n  |     ^\n  in f(), called at line 3\n"
+        );
+    }
+
+    #[test]
+    fn errors_without_a_location() {
+        let site = Token {
+            r#type: TokenType::Identifier,
+            lexeme: "<fn f>".to_string(),
+            literal: None,
+            line: 0,
+            column: 0,
+            length: 0,
+            span: Default::default(),
+        };
+        let mut error = RuntimeError::new(site, "Expected 1 arguments but got 0.".to_string());
+        error.trace.push(Frame {
+            name: "f".to_string(),
+            line: 0,
+        });
+        assert_eq!(error.to_string(), "Error: Expected 1 arguments but got 0.");
+
+        let mut out = vec![];
+        let render = Render {
+            source: "",
+            source_id: 0,
+            color: false,
+        };
+        error.report(&mut out, &render);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Expected 1 arguments but got 0.\n  in f(), called by the host\n"
+        );
+    }
+}
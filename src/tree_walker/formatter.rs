@@ -0,0 +1,319 @@
+use super::errors::Diagnostics;
+use super::parser::Parser;
+use super::scanner::Scanner;
+use super::tokens::{Token, TokenType};
+
+const INDENT: &str = "  ";
+
+// Reprints a program in the canonical layout: one statement per line, blocks
+// indented, single spaces around operators and comments kept where they were.
+// Only programs that parse get formatted, otherwise their errors come back.
+pub fn format(source: &str) -> Result<String, Diagnostics> {
+    let tokens = Scanner::new(source.to_string())
+        .with_comments()
+        .scan_tokens();
+
+    let mut diagnostics = Diagnostics::new();
+    for token in tokens.iter().filter(|t| t.r#type == TokenType::Error) {
+        diagnostics.error(token, &token.lexeme);
+    }
+
+    let code = tokens
+        .iter()
+        .filter(|t| !matches!(t.r#type, TokenType::Comment | TokenType::Error))
+        .cloned()
+        .collect();
+    let mut parser = Parser::new(code);
+    parser.parse();
+    diagnostics.extend(parser.diagnostics);
+    if diagnostics.had_error() {
+        return Err(diagnostics);
+    }
+
+    let mut printer = Printer::new();
+    if let Some(shebang) = source.lines().next().filter(|l| l.starts_with("#!")) {
+        printer.out.push_str(shebang);
+        printer.end_line();
+    }
+    printer.print(&tokens);
+
+    let formatted = printer.out.trim_end();
+    if formatted.is_empty() {
+        return Ok(String::new());
+    }
+    Ok(formatted.to_string() + "\n")
+}
+
+// Lays tokens out one at a time, the parser already vouched for them so the
+// printer only looks at the tokens right around the one it writes.
+struct Printer<'a> {
+    out: String,
+    depth: usize,
+    // Open parens and brackets, semicolons inside them don't end lines.
+    parens: usize,
+    // Open braces, true for blocks and false for map literals.
+    braces: Vec<bool>,
+    // `?` still waiting for their `:`.
+    ternaries: usize,
+    // The last token written, comments aside.
+    previous: Option<&'a Token>,
+    previous_unary: bool,
+    // Source line the last token written ended on.
+    line: u64,
+    at_line_start: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn new() -> Self {
+        Printer {
+            out: String::new(),
+            depth: 0,
+            parens: 0,
+            braces: vec![],
+            ternaries: 0,
+            previous: None,
+            previous_unary: false,
+            line: 1,
+            at_line_start: true,
+        }
+    }
+
+    fn print(&mut self, tokens: &'a [Token]) {
+        for (i, token) in tokens.iter().enumerate() {
+            match token.r#type {
+                TokenType::Eof => break,
+                TokenType::Comment => self.comment(token, tokens.get(i + 1)),
+                _ => self.code(token, tokens.get(i + 1)),
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    // Moves to a fresh line for the token, keeping at most one of the blank
+    // lines the source had before it.
+    fn start_line(&mut self, token: &Token) {
+        if !self.at_line_start {
+            self.end_line();
+        }
+
+        let after_open_brace = self
+            .previous
+            .is_some_and(|p| p.r#type == TokenType::LeftBrace);
+        if !self.out.is_empty()
+            && token.line > self.line + 1
+            && token.r#type != TokenType::RightBrace
+            && !after_open_brace
+        {
+            self.out.push('\n');
+        }
+
+        self.out.push_str(&INDENT.repeat(self.depth));
+        self.at_line_start = false;
+    }
+
+    // Comments on the same line as code stay there, the rest get their own.
+    // Block comments keep what comes on later lines off theirs, like line
+    // comments do.
+    fn comment(&mut self, comment: &Token, next: Option<&Token>) {
+        if !self.at_line_start && comment.line == self.line {
+            self.out.push(' ');
+        } else {
+            self.start_line(comment);
+        }
+        self.out.push_str(&comment.lexeme);
+        self.line = comment.line + comment.lexeme.matches('\n').count() as u64;
+
+        let next_line = next.is_some_and(|n| n.line > self.line);
+        if comment.lexeme.starts_with("//") || next_line {
+            self.end_line();
+        }
+    }
+
+    fn code(&mut self, token: &'a Token, next: Option<&Token>) {
+        use TokenType::*;
+
+        let closes_block = token.r#type == RightBrace && self.braces.last() == Some(&true);
+        let ternary_colon = token.r#type == Colon && self.ternaries > 0;
+
+        if closes_block {
+            self.depth -= 1;
+            if self.previous.is_some_and(|p| p.r#type != LeftBrace) {
+                self.start_line(token);
+            }
+        } else if self.at_line_start {
+            self.start_line(token);
+        } else if self.space_before(token, ternary_colon) {
+            self.out.push(' ');
+        }
+        self.out.push_str(&normalize(token));
+
+        // A comment right after the token ends its line for it.
+        let trailing_comment = next.is_some_and(|n| n.r#type == Comment && n.line == token.line);
+        match token.r#type {
+            LeftParen | LeftBracket => self.parens += 1,
+            RightParen | RightBracket => self.parens = self.parens.saturating_sub(1),
+            Question => self.ternaries += 1,
+            Colon if ternary_colon => self.ternaries -= 1,
+            Semicolon if self.parens == 0 && !trailing_comment => self.end_line(),
+            LeftBrace => {
+                let block = self.opens_block();
+                self.braces.push(block);
+                if block {
+                    self.depth += 1;
+                    let empty = next.is_some_and(|n| n.r#type == RightBrace);
+                    if !empty && !trailing_comment {
+                        self.end_line();
+                    }
+                }
+            }
+            RightBrace => {
+                self.braces.pop();
+                let joined = next.is_some_and(|n| {
+                    matches!(n.r#type, Else | Semicolon | Comma | RightParen | Eof)
+                });
+                if closes_block && !joined && !trailing_comment {
+                    self.end_line();
+                }
+            }
+            _ => (),
+        }
+
+        self.previous_unary =
+            matches!(token.r#type, Minus | Bang | Tilde) && !self.previous.is_some_and(ends_value);
+        self.previous = Some(token);
+        self.line = token.line + token.lexeme.matches('\n').count() as u64;
+    }
+
+    fn space_before(&self, token: &Token, ternary_colon: bool) -> bool {
+        use TokenType::*;
+
+        let Some(previous) = self.previous else {
+            return false;
+        };
+
+        match token.r#type {
+            Semicolon | Comma | RightParen | RightBracket | Dot => return false,
+            Colon => return ternary_colon,
+            RightBrace => return false,
+            _ => (),
+        }
+
+        if self.previous_unary {
+            return false;
+        }
+
+        match token.r#type {
+            // Calls and indexing hug what they apply to.
+            LeftParen | LeftBracket => !ends_value(previous),
+            _ => match previous.r#type {
+                LeftParen | LeftBracket | Dot => false,
+                LeftBrace => self.braces.last() != Some(&false),
+                _ => true,
+            },
+        }
+    }
+
+    // Whether the brace about to be written starts a block rather than a map
+    // literal, maps only show up where an expression is expected.
+    fn opens_block(&self) -> bool {
+        use TokenType::*;
+
+        match self.previous {
+            None => true,
+            Some(previous) => matches!(
+                previous.r#type,
+                RightParen | Else | Identifier | Semicolon | LeftBrace | RightBrace | Arrow
+            ),
+        }
+    }
+}
+
+fn ends_value(token: &Token) -> bool {
+    use TokenType::*;
+
+    matches!(
+        token.r#type,
+        Identifier
+            | Number
+            | String
+            | True
+            | False
+            | Nil
+            | This
+            | Super
+            | RightParen
+            | RightBracket
+            | RightBrace
+    )
+}
+
+// Raw strings that read the same as regular ones are written as regular ones.
+fn normalize(token: &Token) -> std::string::String {
+    if token.r#type == TokenType::String && token.lexeme.starts_with('`') {
+        let contents = &token.lexeme[1..token.lexeme.len() - 1];
+        if !contents.contains(['"', '\\']) {
+            return format!("\"{}\"", contents);
+        }
+    }
+    token.lexeme.to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn formatted(source: &str) -> String {
+        format(source).unwrap_or_else(|_| panic!("{} did not parse", source))
+    }
+
+    #[test]
+    fn canonical_layout() {
+        let source = "fun  f(a,b){if(a>-b)return a;else{print `x`;}}\n\n\n\nvar m={\"k\":[1,2][0]}; var t=a?b:c;";
+        assert_eq!(
+            formatted(source),
+            "fun f(a, b) {\n  if (a > -b) return a;\n  else {\n    print \"x\";\n  }\n}\n\n\
+             var m = {\"k\": [1, 2][0]};\nvar t = a ? b : c;\n"
+        );
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let source = "// header\nvar a = 1; // trailing\n{\n// inside\nfor (var i = 0; i < 1; i = i + 1) {}\n}";
+        assert_eq!(
+            formatted(source),
+            "// header\nvar a = 1; // trailing\n{\n  // inside\n  for (var i = 0; i < 1; i = i + 1) {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn block_comments_on_their_own_line() {
+        assert_eq!(
+            formatted("/* header */\nprint 1;"),
+            "/* header */\nprint 1;\n"
+        );
+        assert_eq!(
+            formatted("{\n/* one\n   two */\nprint 1; /* after */\n}"),
+            "{\n  /* one\n   two */\n  print 1; /* after */\n}\n"
+        );
+        assert_eq!(
+            formatted("print /* inline */ 1;"),
+            "print /* inline */ 1;\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let source = "class A { m() { return this.x; } }\nouter: while (true) { match (1) { 1 -> break outer; else -> {} } }";
+        let once = formatted(source);
+        assert_eq!(formatted(&once), once);
+    }
+
+    #[test]
+    fn refuses_bad_programs() {
+        assert!(format("print ;").is_err());
+    }
+}
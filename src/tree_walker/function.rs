@@ -0,0 +1,137 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use super::callable::{Arity, Callable};
+use super::class::LoxInstance;
+use super::environment::Environment;
+use super::errors::{RuntimeError, TailCall, Unwind};
+use super::gc::Marks;
+use super::interpreter::Interpreter;
+use super::syntax_tree::{Exprs, Function, Value};
+use super::tokens::Token;
+
+pub struct LoxFunction {
+    declaration: Rc<Function>,
+    // The expressions of the program it was declared in.
+    exprs: Rc<Exprs>,
+    closure: Rc<RefCell<Environment>>,
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<Function>,
+        exprs: Rc<Exprs>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        LoxFunction {
+            declaration,
+            exprs,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut env = Environment::new_enclosed(Rc::clone(&self.closure));
+        env.define("this".to_string(), Value::Instance(instance));
+
+        LoxFunction::new(
+            Rc::clone(&self.declaration),
+            Rc::clone(&self.exprs),
+            Rc::new(RefCell::new(env)),
+            self.is_initializer,
+        )
+    }
+
+    fn run(&self, interpreter: &mut Interpreter, arguments: Vec<Value>) -> Result<(), Unwind> {
+        let env = Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(
+            &self.closure,
+        ))));
+
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            env.borrow_mut().define(param.lexeme.to_owned(), argument);
+        }
+
+        interpreter.using_exprs(&self.exprs, |interpreter| {
+            interpreter.execute_block(&self.declaration.body, env)
+        })
+    }
+}
+
+impl Callable for LoxFunction {
+    fn name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::Fixed(self.declaration.params.len())
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        _paren: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let mut result = self.run(interpreter, arguments);
+
+        // Initializers always hand back the instance, even on an early return.
+        if self.is_initializer {
+            if let Err(Unwind::Error(e)) = result {
+                return Err(e);
+            }
+            return Ok(self
+                .closure
+                .borrow()
+                .get_local("this")
+                .unwrap_or(Value::Nil));
+        }
+
+        // Tail calls to other Lox functions run here, in this call's frame,
+        // anything else is called as usual.
+        while let Err(Unwind::TailCall(call)) = result {
+            let TailCall {
+                callee,
+                arguments,
+                paren,
+            } = *call;
+            match &callee {
+                Value::Callable(f) if f.as_function().is_some_and(|f| !f.is_initializer) => {
+                    interpreter.tail_call(&callee, arguments.len(), &paren)?;
+                    result = f.as_function().unwrap().run(interpreter, arguments);
+                }
+                _ => return interpreter.call_value(callee, arguments, &paren),
+            }
+        }
+
+        // The resolver keeps loop jumps from escaping a function body.
+        match result {
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(e)) => Err(e),
+            _ => Ok(Value::Nil),
+        }
+    }
+
+    fn trace(&self, marks: &mut Marks) {
+        marks.environment(&self.closure);
+    }
+
+    fn as_function(&self) -> Option<&LoxFunction> {
+        Some(self)
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
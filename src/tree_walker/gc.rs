@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use super::callable::Callable;
+use super::class::{LoxClass, LoxInstance};
+use super::environment::Environment;
+use super::list::LoxList;
+use super::map::LoxMap;
+use super::syntax_tree::Value;
+
+// Environments, instances, lists and maps are the only mutable objects, so
+// every reference cycle goes through at least one of them. The heap keeps a
+// weak handle on each, and collecting empties the ones no root reaches
+// anymore, which breaks the cycles keeping them alive.
+#[derive(Default)]
+pub struct Heap {
+    environments: Vec<Weak<RefCell<Environment>>>,
+    instances: Vec<Weak<RefCell<LoxInstance>>>,
+    lists: Vec<Weak<RefCell<LoxList>>>,
+    maps: Vec<Weak<RefCell<LoxMap>>>,
+    // Values handed over to the host, by address so pinning one again is
+    // free. Only pinned from `&self` methods, so it's behind a RefCell.
+    pinned: RefCell<HashMap<*const (), Pin>>,
+    // Dead handles are dropped once this many objects are tracked.
+    next_prune: usize,
+}
+
+// A value the host got hold of. It may keep it anywhere, in a closure it
+// registers or inside a foreign object, where the collector can't look. So
+// for as long as it's alive, or until the host releases it, it counts as a
+// root.
+enum Pin {
+    Callable(Weak<dyn Callable>),
+    Class(Weak<LoxClass>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<LoxList>>),
+    Map(Weak<RefCell<LoxMap>>),
+}
+
+impl Pin {
+    fn new(value: &Value) -> Option<Pin> {
+        match value {
+            Value::Callable(f) => Some(Pin::Callable(Rc::downgrade(f))),
+            Value::Class(c) => Some(Pin::Class(Rc::downgrade(c))),
+            Value::Instance(i) => Some(Pin::Instance(Rc::downgrade(i))),
+            Value::List(l) => Some(Pin::List(Rc::downgrade(l))),
+            Value::Map(m) => Some(Pin::Map(Rc::downgrade(m))),
+            _ => None,
+        }
+    }
+
+    fn upgrade(&self) -> Option<Value> {
+        match self {
+            Pin::Callable(f) => f.upgrade().map(Value::Callable),
+            Pin::Class(c) => c.upgrade().map(Value::Class),
+            Pin::Instance(i) => i.upgrade().map(Value::Instance),
+            Pin::List(l) => l.upgrade().map(Value::List),
+            Pin::Map(m) => m.upgrade().map(Value::Map),
+        }
+    }
+
+    // The weak handle keeps the allocation, so no other object can take the
+    // address while the pin is around.
+    fn address(&self) -> *const () {
+        match self {
+            Pin::Callable(f) => f.as_ptr() as *const (),
+            Pin::Class(c) => c.as_ptr() as *const (),
+            Pin::Instance(i) => i.as_ptr() as *const (),
+            Pin::List(l) => l.as_ptr() as *const (),
+            Pin::Map(m) => m.as_ptr() as *const (),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Pin::Callable(f) => f.strong_count() > 0,
+            Pin::Class(c) => c.strong_count() > 0,
+            Pin::Instance(i) => i.strong_count() > 0,
+            Pin::List(l) => l.strong_count() > 0,
+            Pin::Map(m) => m.strong_count() > 0,
+        }
+    }
+}
+
+const MIN_PRUNE: usize = 1024;
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            next_prune: MIN_PRUNE,
+            ..Heap::default()
+        }
+    }
+
+    pub fn track_environment(&mut self, env: &Rc<RefCell<Environment>>) {
+        self.environments.push(Rc::downgrade(env));
+        self.maybe_prune();
+    }
+
+    pub fn track_instance(&mut self, instance: &Rc<RefCell<LoxInstance>>) {
+        self.instances.push(Rc::downgrade(instance));
+        self.maybe_prune();
+    }
+
+    pub fn track_list(&mut self, list: &Rc<RefCell<LoxList>>) {
+        self.lists.push(Rc::downgrade(list));
+        self.maybe_prune();
+    }
+
+    pub fn track_map(&mut self, map: &Rc<RefCell<LoxMap>>) {
+        self.maps.push(Rc::downgrade(map));
+        self.maybe_prune();
+    }
+
+    // Keeps what the value reaches from being collected while it's alive.
+    // Cycles it's part of are then only freed once it's released, or along
+    // with the interpreter.
+    pub fn pin(&self, value: &Value) {
+        if let Some(pin) = Pin::new(value) {
+            self.pinned.borrow_mut().entry(pin.address()).or_insert(pin);
+        }
+    }
+
+    // Lets go of a pinned value, for when the host is done with it.
+    pub fn release(&mut self, value: &Value) {
+        if let Some(pin) = Pin::new(value) {
+            self.pinned.get_mut().remove(&pin.address());
+        }
+    }
+
+    // Lets go of every pinned value, for when the host can't use them anymore.
+    pub fn unpin(&mut self) {
+        self.pinned.get_mut().clear();
+    }
+
+    #[cfg(test)]
+    pub fn pinned(&self) -> usize {
+        self.pinned.borrow().len()
+    }
+
+    // Tracked objects still alive, reachable or not.
+    #[cfg(test)]
+    pub fn live(&self) -> usize {
+        fn count<T>(handles: &[Weak<T>]) -> usize {
+            handles.iter().filter(|h| h.strong_count() > 0).count()
+        }
+
+        count(&self.environments) + count(&self.instances) + count(&self.lists) + count(&self.maps)
+    }
+
+    fn tracked(&self) -> usize {
+        self.environments.len()
+            + self.instances.len()
+            + self.lists.len()
+            + self.maps.len()
+            + self.pinned.borrow().len()
+    }
+
+    fn maybe_prune(&mut self) {
+        if self.tracked() < self.next_prune {
+            return;
+        }
+
+        self.prune();
+        self.next_prune = (self.tracked() * 2).max(MIN_PRUNE);
+    }
+
+    fn prune(&mut self) {
+        self.environments.retain(|h| h.strong_count() > 0);
+        self.instances.retain(|h| h.strong_count() > 0);
+        self.lists.retain(|h| h.strong_count() > 0);
+        self.maps.retain(|h| h.strong_count() > 0);
+        self.pinned.get_mut().retain(|_, pin| pin.is_alive());
+    }
+
+    // Marks everything reachable from the roots and the pinned values, and
+    // empties the rest.
+    pub fn collect(&mut self, roots: &[Rc<RefCell<Environment>>]) {
+        let mut marks = Marks::default();
+        for root in roots {
+            marks.environment(root);
+        }
+        for value in self.pinned.get_mut().values().filter_map(Pin::upgrade) {
+            marks.value(&value);
+        }
+        marks.trace();
+
+        // Contents are moved out before being dropped, dropping them may free
+        // other tracked objects.
+        for env in self.environments.iter().filter_map(Weak::upgrade) {
+            if !marks.is_marked(&env) {
+                let values = env.borrow_mut().clear();
+                drop(values);
+            }
+        }
+        for instance in self.instances.iter().filter_map(Weak::upgrade) {
+            if !marks.is_marked(&instance) {
+                let fields = instance.borrow_mut().clear();
+                drop(fields);
+            }
+        }
+        for list in self.lists.iter().filter_map(Weak::upgrade) {
+            if !marks.is_marked(&list) {
+                let elements = std::mem::take(&mut *list.borrow_mut());
+                drop(elements);
+            }
+        }
+        for map in self.maps.iter().filter_map(Weak::upgrade) {
+            if !marks.is_marked(&map) {
+                let entries = std::mem::take(&mut *map.borrow_mut());
+                drop(entries);
+            }
+        }
+
+        self.prune();
+    }
+}
+
+// The objects found reachable so far, keyed by address, and the ones still
+// to be looked into. Kept as work lists so deep structures can't overflow the
+// stack.
+#[derive(Default)]
+pub struct Marks {
+    seen: HashSet<*const ()>,
+    environments: Vec<Rc<RefCell<Environment>>>,
+    values: Vec<Value>,
+}
+
+impl Marks {
+    pub fn environment(&mut self, env: &Rc<RefCell<Environment>>) {
+        self.environments.push(Rc::clone(env));
+    }
+
+    pub fn value(&mut self, value: &Value) {
+        self.values.push(value.to_owned());
+    }
+
+    // Tells whether this is the first time the object is reached.
+    pub fn visit<T: ?Sized>(&mut self, object: &Rc<T>) -> bool {
+        self.seen.insert(Rc::as_ptr(object) as *const ())
+    }
+
+    fn is_marked<T>(&self, object: &Rc<T>) -> bool {
+        self.seen.contains(&(Rc::as_ptr(object) as *const ()))
+    }
+
+    fn trace(&mut self) {
+        loop {
+            if let Some(env) = self.environments.pop() {
+                if self.visit(&env) {
+                    env.borrow().trace(self);
+                }
+            } else if let Some(value) = self.values.pop() {
+                value.trace(self);
+            } else {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,544 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::errors::{Diagnostics, Error};
+use super::parser::Parser;
+use super::scanner::Scanner;
+use super::syntax_tree::{Expr, Exprs, Function, Stmt};
+use super::tokens::{Span, Token, TokenType};
+
+// A parsed source that is kept up to date through edits, for editors changing
+// a few characters of a large file at a time. Only the top level declarations
+// an edit touches get scanned and parsed again, the ones after them are moved
+// to where the edit leaves them.
+pub struct Document {
+    pub source: String,
+    // Everything scanned, errors included, ending with Eof.
+    pub tokens: Vec<Token>,
+    // In source order, their token ranges cover every token but Eof.
+    pub declarations: Vec<Declaration>,
+}
+
+pub struct Declaration {
+    pub tokens: Range<usize>,
+    // None for a declaration that failed to parse.
+    pub statement: Option<Stmt>,
+    // The expressions of the statement, every declaration has its own.
+    pub exprs: Exprs,
+    // Errors scanning and parsing it.
+    pub diagnostics: Diagnostics,
+}
+
+impl Document {
+    pub fn new(source: &str) -> Self {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let declarations = parse(&tokens, 0..tokens.len() - 1, true).unwrap_or_default();
+
+        Document {
+            source: source.to_string(),
+            tokens,
+            declarations,
+        }
+    }
+
+    pub fn statements(&self) -> impl Iterator<Item = (&Stmt, &Exprs)> {
+        self.declarations.iter().filter_map(|declaration| {
+            let statement = declaration.statement.as_ref()?;
+            Some((statement, &declaration.exprs))
+        })
+    }
+
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Error> {
+        self.declarations
+            .iter()
+            .flat_map(|declaration| declaration.diagnostics.iter())
+    }
+
+    // Replaces the bytes in `range` with `text`, which must leave the source
+    // valid UTF-8.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) {
+        let mut source = self.source.clone();
+        source.replace_range(range.clone(), text);
+        let end = range.start + text.len();
+
+        let line = self.line_at(range.end);
+        let shift = Shift {
+            delta: end as isize - range.end as isize,
+            lines: text.matches('\n').count() as i64
+                - self.source[range.clone()].matches('\n').count() as i64,
+            line,
+            columns: column_at(&source, end) as i64 - column_at(&self.source, range.end) as i64,
+        };
+
+        // The declarations touching the edit, and the one before them: where
+        // it ends may depend on what follows it, like an if statement taking
+        // an else or a bad declaration skipping tokens.
+        let extent = |d: &Declaration| {
+            let start = self.tokens[d.tokens.start].span.start;
+            start..self.tokens[d.tokens.end - 1].span.end
+        };
+        let mut first = self
+            .declarations
+            .partition_point(|d| extent(d).end < range.start);
+        let mut last = self
+            .declarations
+            .partition_point(|d| extent(d).start <= range.end)
+            .max(first);
+        first = first.saturating_sub(1);
+
+        // Widens the region until scanning and parsing it ends where the
+        // declarations after it start, taking in twice as many each time.
+        let mut grow = 1;
+        let (scanned, parsed) = loop {
+            if let Some(region) = self.reparse(&source, &shift, first, last) {
+                break region;
+            }
+            last = (last + grow).min(self.declarations.len());
+            grow *= 2;
+        };
+
+        let old = self.token_range(first, last);
+        let delta = scanned.len() as isize - old.len() as isize;
+        for token in &mut self.tokens[old.end..] {
+            shift.token(token);
+        }
+        self.tokens.splice(old.clone(), scanned);
+
+        for declaration in &mut self.declarations[last..] {
+            let tokens = &declaration.tokens;
+            declaration.tokens =
+                (tokens.start as isize + delta) as usize..(tokens.end as isize + delta) as usize;
+            for e in declaration.diagnostics.iter_mut() {
+                shift.position(&mut e.line, &mut e.column);
+            }
+            declaration.exprs.iter_mut().for_each(|e| shift.expr(e));
+            // Functions shared with something else, like an interpreter that
+            // ran them, can't be moved and get parsed again.
+            let moved = declaration
+                .statement
+                .as_mut()
+                .is_none_or(|statement| shift.stmt(statement));
+            if !moved {
+                let tokens = &self.tokens[declaration.tokens.clone()];
+                let mut parser = Parser::new(tokens.iter().cloned().chain([eof(tokens)]).collect());
+                declaration.statement = parser.parse_declaration();
+                declaration.exprs = parser.exprs;
+            }
+        }
+        let parsed = parsed.into_iter().map(|mut declaration| {
+            let tokens = &declaration.tokens;
+            declaration.tokens = tokens.start + old.start..tokens.end + old.start;
+            declaration
+        });
+        self.declarations.splice(first..last, parsed);
+        self.source = source;
+    }
+
+    // Scans and parses the declarations from `first` up to `last` again, in
+    // the edited source. None if they don't end where the next one starts.
+    fn reparse(
+        &self,
+        source: &str,
+        shift: &Shift,
+        first: usize,
+        last: usize,
+    ) -> Option<(Vec<Token>, Vec<Declaration>)> {
+        let old = self.token_range(first, last);
+        let (offset, line) = match old.start.checked_sub(1).map(|i| &self.tokens[i]) {
+            Some(before) => {
+                let lines = self.source[before.span.start..before.span.end].matches('\n');
+                (before.span.end, before.line + lines.count() as u64)
+            }
+            None => (0, 1),
+        };
+
+        let mut next = self.tokens[old.end].clone();
+        shift.token(&mut next);
+        let mut scanner = Scanner::new(source.to_string()).starting_at(offset, line);
+        let mut scanned = vec![];
+        loop {
+            let token = scanner.next()?;
+            if token.span.start >= next.span.start {
+                // Past the region the source scans the same as it did before,
+                // as long as a token starts right where it ends.
+                if token.span.start != next.span.start {
+                    return None;
+                }
+                break;
+            }
+            scanned.push(token);
+        }
+
+        let at_end = next.r#type == TokenType::Eof;
+        let len = scanned.len();
+        scanned.push(next);
+        let parsed = parse(&scanned, 0..len, at_end)?;
+        scanned.pop();
+        Some((scanned, parsed))
+    }
+
+    // The tokens of the declarations from `first` up to `last`. Between
+    // declarations, or after the last one, that's the empty range there.
+    fn token_range(&self, first: usize, last: usize) -> Range<usize> {
+        let eof = self.tokens.len() - 1;
+        let start = self.declarations.get(first).map_or(eof, |d| d.tokens.start);
+        let end = match last.checked_sub(1).filter(|&i| i >= first) {
+            Some(i) => self.declarations[i].tokens.end,
+            None => start,
+        };
+        start..end
+    }
+
+    fn line_at(&self, offset: usize) -> u64 {
+        let i = self.tokens.partition_point(|t| t.span.start <= offset);
+        match i.checked_sub(1).map(|i| &self.tokens[i]) {
+            Some(token) => {
+                token.line + self.source[token.span.start..offset].matches('\n').count() as u64
+            }
+            None => 1 + self.source[..offset].matches('\n').count() as u64,
+        }
+    }
+}
+
+// Chars from the start of the line to `offset`, plus one.
+fn column_at(source: &str, offset: usize) -> u64 {
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    source[start..offset].chars().count() as u64 + 1
+}
+
+// Where parsing `tokens` ends, right after the last of them.
+fn eof(tokens: &[Token]) -> Token {
+    let last = tokens.last();
+    let end = last.map_or(0, |t| t.span.end);
+    Token {
+        r#type: TokenType::Eof,
+        lexeme: String::new(),
+        literal: None,
+        line: last.map_or(1, |t| t.line),
+        column: last.map_or(1, |t| t.column + t.length),
+        length: 0,
+        span: Span::new(end, end),
+    }
+}
+
+// Parses the tokens in `range` as top level declarations, `tokens[range.end]`
+// being the one after them. Unless the range goes to the end of the source,
+// None if the last declaration may go on past it.
+fn parse(tokens: &[Token], range: Range<usize>, at_end: bool) -> Option<Vec<Declaration>> {
+    // The parser never sees scan errors, they're reported as they are.
+    let mut indices: Vec<usize> = range
+        .clone()
+        .filter(|&i| tokens[i].r#type != TokenType::Error)
+        .collect();
+    let mut parsed: Vec<Token> = indices.iter().map(|&i| tokens[i].clone()).collect();
+    let next = &tokens[range.end];
+    parsed.push(Token {
+        r#type: TokenType::Eof,
+        lexeme: String::new(),
+        literal: None,
+        length: 0,
+        span: Span::new(next.span.start, next.span.start),
+        ..next.clone()
+    });
+    indices.push(range.end);
+
+    let mut parser = Parser::new(parsed);
+    let mut declarations = vec![];
+    let mut start = range.start;
+    while !parser.is_at_end() {
+        let statement = parser.parse_declaration();
+        let end = indices[parser.position()];
+        declarations.push(Declaration {
+            tokens: start..end,
+            statement,
+            exprs: std::mem::take(&mut parser.exprs),
+            diagnostics: std::mem::take(&mut parser.diagnostics),
+        });
+        start = end;
+    }
+    // Scan errors with no declaration after them to go with.
+    let leftover = start < range.end;
+    if leftover {
+        declarations.push(Declaration {
+            tokens: start..range.end,
+            statement: None,
+            exprs: Exprs::default(),
+            diagnostics: Diagnostics::new(),
+        });
+    }
+    for declaration in &mut declarations {
+        for token in &tokens[declaration.tokens.clone()] {
+            if token.r#type == TokenType::Error {
+                declaration.diagnostics.error(token, &token.lexeme);
+            }
+        }
+    }
+
+    // A declaration that ran into the end of the range would have gone on,
+    // as would a bad one skipping tokens up to the next semicolon. Scan errors
+    // go with the declaration before them, or the one after if there's none,
+    // and an `else` with an if statement before it.
+    let cut = declarations.last().is_some_and(|last| {
+        let ran_out = last.diagnostics.iter().any(|e| e.place == " at the end");
+        let skipping =
+            last.statement.is_none() && tokens[last.tokens.end - 1].r#type != TokenType::Semicolon;
+        ran_out || skipping
+    });
+    let joined = matches!(next.r#type, TokenType::Error | TokenType::Else);
+    (at_end || !(cut || leftover || joined)).then_some(declarations)
+}
+
+// Moves what comes after an edit to where the edit left it.
+struct Shift {
+    delta: isize,
+    lines: i64,
+    // The line the edit ended on, what follows on it moves across by
+    // `columns`.
+    line: u64,
+    columns: i64,
+}
+
+impl Shift {
+    fn span(&self, span: &mut Span) {
+        span.start = (span.start as isize + self.delta) as usize;
+        span.end = (span.end as isize + self.delta) as usize;
+    }
+
+    fn position(&self, line: &mut u64, column: &mut u64) {
+        if *line == self.line {
+            *column = (*column as i64 + self.columns) as u64;
+        }
+        *line = (*line as i64 + self.lines) as u64;
+    }
+
+    fn token(&self, token: &mut Token) {
+        self.span(&mut token.span);
+        self.position(&mut token.line, &mut token.column);
+    }
+
+    // False if part of the statement couldn't be moved.
+    fn stmt(&self, stmt: &mut Stmt) -> bool {
+        match stmt {
+            Stmt::Expression(s) => self.span(&mut s.span),
+            Stmt::Print(s) => self.span(&mut s.span),
+            Stmt::Var(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.name);
+            }
+            Stmt::Block(s) => {
+                self.span(&mut s.span);
+                return s.statements.iter_mut().all(|s| self.stmt(s));
+            }
+            Stmt::If(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.keyword);
+                return self.stmt(&mut s.then) && s.r#else.iter_mut().all(|s| self.stmt(s));
+            }
+            Stmt::While(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.keyword);
+                s.label.iter_mut().for_each(|t| self.token(t));
+                return self.stmt(&mut s.body);
+            }
+            Stmt::ForIn(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.keyword);
+                self.token(&mut s.name);
+                s.label.iter_mut().for_each(|t| self.token(t));
+                return self.stmt(&mut s.body);
+            }
+            Stmt::Function(f) => return self.function(f),
+            Stmt::Return(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.keyword);
+            }
+            Stmt::Class(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.name);
+                return s.methods.iter_mut().all(|m| self.function(m));
+            }
+            Stmt::Break(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.keyword);
+                s.label.iter_mut().for_each(|t| self.token(t));
+            }
+            Stmt::Continue(s) => {
+                self.span(&mut s.span);
+                self.token(&mut s.keyword);
+                s.label.iter_mut().for_each(|t| self.token(t));
+            }
+            Stmt::Match(s) => {
+                self.span(&mut s.span);
+                let arms = s.arms.iter_mut().all(|(_, body)| self.stmt(body));
+                return arms && s.r#else.iter_mut().all(|s| self.stmt(s));
+            }
+        }
+        true
+    }
+
+    fn function(&self, function: &mut Rc<Function>) -> bool {
+        let Some(function) = Rc::get_mut(function) else {
+            return false;
+        };
+        self.span(&mut function.span);
+        self.token(&mut function.name);
+        function.params.iter_mut().for_each(|t| self.token(t));
+        function.body.iter_mut().all(|s| self.stmt(s))
+    }
+
+    // Just the node, the ones under it sit elsewhere in the arena.
+    fn expr(&self, expr: &mut Expr) {
+        match expr {
+            Expr::Assign(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.name);
+            }
+            Expr::Ternary(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.operator);
+            }
+            Expr::Logical(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.operator);
+            }
+            Expr::Binary(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.operator);
+            }
+            Expr::Unary(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.operator);
+            }
+            Expr::Grouping(e) => self.span(&mut e.span),
+            Expr::Literal(e) => self.span(&mut e.span),
+            Expr::Variable(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.name);
+            }
+            Expr::Call(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.paren);
+            }
+            Expr::Get(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.name);
+            }
+            Expr::Set(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.name);
+            }
+            Expr::This(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.keyword);
+            }
+            Expr::List(e) => self.span(&mut e.span),
+            Expr::Map(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.brace);
+            }
+            Expr::Index(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.bracket);
+            }
+            Expr::SetIndex(e) => {
+                self.span(&mut e.span);
+                self.token(&mut e.bracket);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Edits the document and checks it ends up the same as parsing the edited
+    // source from scratch.
+    fn check(document: &mut Document, range: Range<usize>, text: &str) {
+        document.edit(range, text);
+        let fresh = Document::new(&document.source);
+
+        assert_eq!(document.tokens, fresh.tokens, "{}", document.source);
+        let ranges = |d: &Document| -> Vec<Range<usize>> {
+            d.declarations.iter().map(|d| d.tokens.clone()).collect()
+        };
+        assert_eq!(ranges(document), ranges(&fresh), "{}", document.source);
+        assert!(
+            document.statements().eq(fresh.statements()),
+            "{}",
+            document.source
+        );
+        let errors =
+            |d: &Document| -> Vec<String> { d.diagnostics().map(|e| e.to_string()).collect() };
+        assert_eq!(errors(document), errors(&fresh), "{}", document.source);
+    }
+
+    #[test]
+    fn edits_match_a_fresh_parse() {
+        let source = "var a = 1;\nfun f(x) {\n  return x + a;\n}\nprint f(2); print a;\n";
+        let edits: &[(&str, &str)] = &[
+            // Within a statement, on a line with others after it.
+            ("f(2)", "f(20)"),
+            // New lines moving everything after them down.
+            ("var a = 1;\n", "var a = 1;\n\n// one\nvar b = \"é\";\n"),
+            // Breaking a statement, then fixing it.
+            ("return x + a;", "return x +"),
+            ("return x +", "return x + a;"),
+            // Opening a string and a comment that swallow the rest.
+            ("print a;", "print \"a;"),
+            ("print \"a;", "print a;"),
+            ("var b", "/* var b"),
+            ("/* var b", "var b"),
+            // Dropping a semicolon merges statements.
+            ("f(20);", "f(20)"),
+            ("f(20)", "f(20);"),
+            // An else after an if.
+            ("print a;", "if (a) print a;"),
+            ("print a;\n", "print a;\nprint 1;\n"),
+            ("print 1;", "else print 1;"),
+            // Between statements and at the end.
+            ("\n\n", "\n@\n"),
+            (
+                "print a;\n",
+                "print a;\nclass C { m() { this.x = [1, {2: 3}]; } }",
+            ),
+        ];
+
+        let mut document = Document::new(source);
+        for (from, to) in edits {
+            let start = document.source.rfind(from).unwrap();
+            check(&mut document, start..start + from.len(), to);
+        }
+        let end = document.source.len();
+        check(&mut document, 0..end, "");
+        check(&mut document, 0..0, "print 1;");
+
+        // Scan errors go with the declaration before them.
+        for source in [" @)", "/*a*/\"\n"] {
+            check(&mut Document::new(source), 0..0, "var a = 1;");
+        }
+    }
+
+    #[test]
+    fn moves_what_follows() {
+        let mut document = Document::new("var a = 1;\nvar b = 2;\nfun f() { return a; }\n");
+        let function = |document: &Document| match document.statements().nth(2) {
+            Some((Stmt::Function(f), _)) => Rc::clone(f),
+            _ => unreachable!(),
+        };
+
+        // Shared, it has to be parsed again.
+        let shared = function(&document);
+        document.edit(4..5, "abc");
+        let moved = function(&document);
+        assert!(!Rc::ptr_eq(&shared, &moved));
+        assert_eq!(moved.name.span, Span::new(28, 29));
+        let address = Rc::as_ptr(&moved);
+        drop((shared, moved));
+
+        document.edit(4..7, "c\n");
+        let again = function(&document);
+        assert_eq!(Rc::as_ptr(&again), address);
+        assert_eq!((again.name.span, again.name.line), (Span::new(27, 28), 4));
+    }
+}
@@ -0,0 +1,2084 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+use super::callable::{define_natives, Arity, HostFunction, Random};
+use super::class::{LoxClass, LoxInstance};
+use super::environment::Environment;
+use super::errors::{Frame, Limit, LoxError, RuntimeError, TailCall, Unwind};
+use super::function::LoxFunction;
+use super::gc::Heap;
+use super::list::LoxList;
+use super::map::LoxMap;
+use super::stack;
+use super::syntax_tree::{
+    Assign, Binary, Block, Break, Call, Class, Continue, Expr, ExprId, Expression, Exprs, ForIn,
+    Function, Get, Grouping, If, Index, List, Literal, Logical, Map, Match, Print, Return, Set,
+    SetIndex, Slot, Stmt, Ternary, This, Unary, Value, Var, Variable, While,
+};
+use super::tokens::{LiteralType, Span, Token, TokenType};
+use super::visitor::{ExprVisitor, StmtVisitor};
+
+// Switches for how programs are evaluated.
+#[derive(Clone, Copy)]
+pub struct Options {
+    // Division by zero yields inf or NaN instead of a runtime error.
+    pub ieee_division: bool,
+    // Conditions and `!` only accept booleans instead of any truthy value.
+    pub strict_truthiness: bool,
+    // Calls nested deeper than this fail with a runtime error, well before
+    // the interpreter itself runs out of stack.
+    pub max_call_depth: usize,
+    // Folds constant expressions before running.
+    pub optimize: bool,
+    // Bounds on the statements a run may execute and the time it may take,
+    // for running code that can't be trusted to finish.
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub capabilities: Capabilities,
+    // Where random() and randomInt() start from, runs with the same seed
+    // draw the same numbers. Unseeded they differ every time.
+    pub seed: Option<u64>,
+}
+
+// What scripts may reach of the host through natives. Natives needing a
+// capability that's off aren't defined at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    // clock()
+    pub clock: bool,
+    // readLine(), from stdin unless the host gave other input.
+    pub input: bool,
+    // getenv()
+    pub environment: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            clock: true,
+            input: true,
+            environment: true,
+        }
+    }
+
+    // Nothing outside of the interpreter, for running untrusted code.
+    pub fn none() -> Self {
+        Capabilities {
+            clock: false,
+            input: false,
+            environment: false,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::all()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            ieee_division: false,
+            strict_truthiness: false,
+            max_call_depth: 1000,
+            optimize: false,
+            max_steps: None,
+            timeout: None,
+            capabilities: Capabilities::all(),
+            seed: None,
+        }
+    }
+}
+
+// How often the lookups cached at each property or global site were still
+// good, for --trace-cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub property_hits: u64,
+    pub property_misses: u64,
+    pub global_hits: u64,
+    pub global_misses: u64,
+}
+
+// Gets a look at every statement right before it runs, debuggers pause there.
+pub trait Hook {
+    fn before(&mut self, stmt: &Stmt, interpreter: &Interpreter);
+}
+
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    // The expressions of the code running, functions bring their own along.
+    exprs: Rc<Exprs>,
+    options: Options,
+    // Where natives read from and print writes to, stdin and stdout when none
+    // were given.
+    input: Option<Box<dyn BufRead>>,
+    output: Option<Box<dyn Write>>,
+    // The calls being run, innermost last.
+    frames: Vec<Frame>,
+    heap: Heap,
+    // Statements run so far, a rough measure of work done for benchmarks.
+    executed: u64,
+    hook: Option<Box<dyn Hook>>,
+    // Where the limits of the current run lie, set when it starts.
+    step_budget: Option<u64>,
+    // On the clock `now` reads.
+    deadline: Option<Duration>,
+    checks: u64,
+    random: Random,
+    // The text of every string literal evaluated so far. Evaluating one again
+    // hands out the same text instead of a copy.
+    strings: HashSet<Rc<str>>,
+    caches: CacheStats,
+}
+
+impl Interpreter {
+    pub fn new(options: Options) -> Self {
+        let mut globals = Environment::new();
+        define_natives(&mut globals, options.capabilities);
+        let globals = Rc::new(RefCell::new(globals));
+
+        let interpreter = Interpreter {
+            environment: Rc::clone(&globals),
+            globals,
+            exprs: Rc::default(),
+            options,
+            input: None,
+            output: None,
+            frames: vec![],
+            heap: Heap::new(),
+            executed: 0,
+            hook: None,
+            step_budget: None,
+            deadline: None,
+            checks: 0,
+            random: Random::new(options.seed),
+            strings: HashSet::new(),
+            caches: CacheStats::default(),
+        };
+        interpreter.with_args(vec![])
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.caches
+    }
+
+    // Hands the script its command line arguments, as the `args` list of
+    // strings. It's empty unless given.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        let args = args.into_iter().map(Value::from).collect();
+        let args = self.new_list(args);
+        self.globals.borrow_mut().define("args".to_string(), args);
+        self
+    }
+
+    pub fn with_hook(mut self, hook: Box<dyn Hook>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    // How many calls deep the statement being run is.
+    pub fn call_depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    // The variables in scope where the interpreter is, innermost scope first.
+    // Globals are left out, they are mostly natives.
+    pub fn locals(&self) -> Vec<Vec<(String, Option<Value>)>> {
+        let mut scopes = vec![];
+        let mut environment = Some(Rc::clone(&self.environment));
+        while let Some(env) = environment {
+            if Rc::ptr_eq(&env, &self.globals) {
+                break;
+            }
+            scopes.push(env.borrow().variables());
+            environment = env.borrow().enclosing();
+        }
+        scopes
+    }
+
+    // Exposes a host function to scripts as a global, replacing anything
+    // defined under that name. Failing with a message raises a runtime error
+    // at the call.
+    //   interpreter.register_native("double", 1, |args| {
+    //       let n = f64::try_from(args[0].to_owned())?;
+    //       Ok(Value::from(n * 2.0))
+    //   });
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        arity: impl Into<Arity>,
+        function: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    ) {
+        let native = HostFunction::new(name, arity.into(), Box::new(function));
+        self.define_global(name, Value::Callable(Rc::new(native)));
+    }
+
+    // Hands a value to scripts as a global, host objects wrapped with
+    // `Value::foreign` among them.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().define(name.to_string(), value);
+    }
+
+    // Everything defined at the top level, natives included.
+    pub fn global_names(&self) -> Vec<String> {
+        let globals = self.globals.borrow().variables();
+        globals.into_iter().map(|(name, _)| name).collect()
+    }
+
+    // Whatever the host gets a hold of is pinned, so collecting leaves it
+    // usable.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let value = self.globals.borrow().get_local(name);
+        if let Some(value) = &value {
+            self.heap.pin(value);
+        }
+        value
+    }
+
+    // A global defined by the scripts run so far, nil if there's none.
+    pub fn get_global(&self, name: &str) -> Value {
+        self.global(name).unwrap_or(Value::Nil)
+    }
+
+    // Calls a function or class from the host, usually one a script defined:
+    //   let on_event = interpreter.get_global("onEvent");
+    //   interpreter.call(&on_event, vec![Value::from("start")])?;
+    // The step and time limits apply to each call on its own.
+    pub fn call(&mut self, callable: &Value, arguments: Vec<Value>) -> Result<Value, LoxError> {
+        self.step_budget = self.options.max_steps.map(|steps| self.executed + steps);
+        self.deadline = self
+            .options
+            .timeout
+            .map(|timeout| now().saturating_add(timeout));
+
+        // There's no call site in the source to point at, errors raised making
+        // the call itself come out without a location.
+        let site = Token {
+            r#type: TokenType::Identifier,
+            lexeme: callable.to_string(),
+            literal: None,
+            line: 0,
+            column: 0,
+            length: 0,
+            span: Span::default(),
+        };
+        let result = self.call_value(callable.to_owned(), arguments, &site)?;
+        self.heap.pin(&result);
+        Ok(result)
+    }
+
+    pub fn with_input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn with_output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    // Reads a line, newline included, giving back None at the end of input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line)?,
+            None => io::stdin().read_line(&mut line)?,
+        };
+
+        Ok((read > 0).then_some(line))
+    }
+
+    // Runs the statements, stopping at the first runtime error. Cycles left
+    // behind stay until the host calls `collect`.
+    pub fn interpret(
+        &mut self,
+        statements: &[Stmt],
+        exprs: &Rc<Exprs>,
+    ) -> Result<(), RuntimeError> {
+        self.exprs = Rc::clone(exprs);
+        self.step_budget = self.options.max_steps.map(|steps| self.executed + steps);
+        self.deadline = self
+            .options
+            .timeout
+            .map(|timeout| now().saturating_add(timeout));
+
+        let result = statements.iter().try_for_each(|s| match self.execute(s) {
+            Ok(()) => Ok(()),
+            Err(Unwind::Error(e)) => Err(e),
+            // The resolver rejects jumps outside of functions and loops.
+            Err(_) => Ok(()),
+        });
+
+        result
+    }
+
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn new_list(&mut self, elements: Vec<Value>) -> Value {
+        let list = Rc::new(RefCell::new(LoxList::from(elements)));
+        self.heap.track_list(&list);
+        Value::List(list)
+    }
+
+    // Tells the collector the host no longer keeps the value, it's pinned
+    // again if it's handed over again.
+    pub fn release(&mut self, value: &Value) {
+        self.heap.release(value);
+    }
+
+    // Frees the objects only kept alive by reference cycles. Anything not
+    // reachable from the interpreter's variables or the values handed to the
+    // host is emptied, so it's only safe while nothing is running, which is
+    // why collecting is left to the host.
+    pub fn collect(&mut self) {
+        let roots = [Rc::clone(&self.globals), Rc::clone(&self.environment)];
+        self.heap.collect(&roots);
+    }
+
+    // Runs `f` with the expressions of another program, like the one a
+    // function was declared in, going back to the current ones afterwards.
+    pub fn using_exprs<T>(
+        &mut self,
+        exprs: &Rc<Exprs>,
+        f: impl FnOnce(&mut Interpreter) -> T,
+    ) -> T {
+        let previous = std::mem::replace(&mut self.exprs, Rc::clone(exprs));
+        let result = f(self);
+        self.exprs = previous;
+        result
+    }
+
+    // Runs the statements inside the given environment, restoring the current
+    // one afterwards whatever way the block is left.
+    pub fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), Unwind> {
+        self.heap.track_environment(&env);
+        let previous = std::mem::replace(&mut self.environment, env);
+
+        let result = statements.iter().try_for_each(|s| self.execute(s));
+
+        self.environment = previous;
+        result
+    }
+
+    // Only loops and calls can keep a program going, they check the limits
+    // each time around. The clock is only read every so often.
+    fn check_limits(&mut self, token: &Token) -> Result<(), RuntimeError> {
+        if self
+            .step_budget
+            .is_some_and(|budget| self.executed > budget)
+        {
+            let steps = self.options.max_steps.unwrap_or_default();
+            let message = format!("Step limit exceeded (max steps {}).", steps);
+            return Err(RuntimeError::limit(token.to_owned(), message, Limit::Steps));
+        }
+
+        self.checks += 1;
+        let deadline = self.deadline.filter(|_| self.checks.is_multiple_of(1024));
+        if deadline.is_some_and(|deadline| now() >= deadline) {
+            let timeout = self.options.timeout.unwrap_or_default();
+            let message = format!("Timed out (timeout {:?}).", timeout);
+            return Err(RuntimeError::limit(token.to_owned(), message, Limit::Time));
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        self.executed += 1;
+        if let Some(mut hook) = self.hook.take() {
+            hook.before(stmt, self);
+            self.hook = Some(hook);
+        }
+        stmt.accept(self)
+    }
+
+    pub fn evaluate(&mut self, expr: ExprId) -> Result<Value, RuntimeError> {
+        let exprs = Rc::clone(&self.exprs);
+        stack::guard(|| exprs[expr].accept(self))
+    }
+
+    fn intern(&mut self, text: &str) -> Rc<str> {
+        if let Some(interned) = self.strings.get(text) {
+            return Rc::clone(interned);
+        }
+        let interned: Rc<str> = Rc::from(text);
+        self.strings.insert(Rc::clone(&interned));
+        interned
+    }
+
+    fn look_up_variable(&self, name: &Token, slot: Option<Slot>) -> Result<Value, RuntimeError> {
+        match slot {
+            Some(slot) => self.environment.borrow().get_at(slot, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    // Truthiness as seen by conditions and `!`, in strict mode anything but a
+    // boolean is an error reported at the given token.
+    fn truthiness(&self, value: &Value, token: &Token) -> Result<bool, RuntimeError> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            _ if self.options.strict_truthiness => Err(RuntimeError::new(
+                token.to_owned(),
+                format!("Expected a boolean but got '{}'.", value),
+            )),
+            _ => Ok(value.is_truthy()),
+        }
+    }
+
+    fn call_operands(&mut self, call: &Call) -> Result<(Value, Vec<Value>), RuntimeError> {
+        let callee = self.evaluate(call.callee)?;
+
+        let mut arguments = vec![];
+        for argument in &call.arguments {
+            arguments.push(self.evaluate(*argument)?);
+        }
+        Ok((callee, arguments))
+    }
+
+    // Calls are reported at `paren`, the closing paren where the arguments end.
+    pub fn call_value(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, RuntimeError> {
+        let name = Interpreter::check_call(&callee, arguments.len(), paren)?;
+        if self.frames.len() >= self.options.max_call_depth {
+            return Err(RuntimeError::limit(
+                paren.to_owned(),
+                format!(
+                    "Stack overflow (max call depth {}).",
+                    self.options.max_call_depth
+                ),
+                Limit::CallDepth,
+            ));
+        }
+        self.check_limits(paren)?;
+
+        self.frames.push(Frame {
+            name,
+            line: paren.line,
+        });
+        let mut result = match callee {
+            Value::Callable(f) => f.call(self, arguments, paren),
+            Value::Class(c) => LoxClass::call(&c, self, arguments, paren),
+            _ => unreachable!(),
+        };
+
+        // The innermost call an error escapes from sees the whole stack.
+        if let Err(e) = &mut result {
+            if e.trace.is_empty() {
+                e.trace = self.frames.iter().rev().cloned().collect();
+            }
+        }
+        self.frames.pop();
+
+        result
+    }
+
+    // A call in tail position takes over the frame of the function that made
+    // it, so the call depth stays the same.
+    pub fn tail_call(
+        &mut self,
+        callee: &Value,
+        count: usize,
+        paren: &Token,
+    ) -> Result<(), RuntimeError> {
+        let name = Interpreter::check_call(callee, count, paren)?;
+        self.check_limits(paren)?;
+
+        if let Some(frame) = self.frames.last_mut() {
+            *frame = Frame {
+                name,
+                line: paren.line,
+            };
+        }
+        Ok(())
+    }
+
+    // Whether the callee takes that many arguments, giving back its name.
+    fn check_call(callee: &Value, count: usize, paren: &Token) -> Result<String, RuntimeError> {
+        let (name, arity) = match callee {
+            Value::Callable(f) => (f.name().to_string(), f.arity()),
+            Value::Class(c) => (c.name().to_string(), c.arity()),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.to_owned(),
+                    "Can only call functions and classes.".to_string(),
+                ))
+            }
+        };
+
+        if !arity.accepts(count) {
+            return Err(RuntimeError::new(
+                paren.to_owned(),
+                format!("Expected {} arguments but got {}.", arity, count),
+            ));
+        }
+        Ok(name)
+    }
+
+    fn not_indexable(bracket: &Token) -> RuntimeError {
+        RuntimeError::new(
+            bracket.to_owned(),
+            "Only lists and maps can be indexed.".to_string(),
+        )
+    }
+
+    fn invalid_key(token: &Token) -> RuntimeError {
+        RuntimeError::new(
+            token.to_owned(),
+            "Map keys must be strings, numbers, booleans or nil.".to_string(),
+        )
+    }
+
+    // Indexes must be whole numbers within the list's bounds.
+    fn list_index(list: &[Value], index: &Value, bracket: &Token) -> Result<usize, RuntimeError> {
+        let Value::Number(n) = *index else {
+            return Err(RuntimeError::new(
+                bracket.to_owned(),
+                "List index must be a number.".to_string(),
+            ));
+        };
+
+        if n.fract() != 0.0 || n < 0.0 || n >= list.len() as f64 {
+            return Err(RuntimeError::new(
+                bracket.to_owned(),
+                format!("List index {} out of range.", Value::Number(n)),
+            ));
+        }
+
+        Ok(n as usize)
+    }
+}
+
+impl StmtVisitor<Result<(), Unwind>> for Interpreter {
+    fn visit_expression(&mut self, stmt: &Expression) -> Result<(), Unwind> {
+        self.evaluate(stmt.expression)?;
+        Ok(())
+    }
+
+    fn visit_print(&mut self, stmt: &Print) -> Result<(), Unwind> {
+        let value = self.evaluate(stmt.expression)?;
+        // Output nobody can take anymore, like a pipe whose reader is gone,
+        // is dropped rather than stopping the program.
+        let _ = match &mut self.output {
+            Some(output) => writeln!(output, "{}", value),
+            None => writeln!(io::stdout(), "{}", value),
+        };
+        Ok(())
+    }
+
+    fn visit_var(&mut self, stmt: &Var) -> Result<(), Unwind> {
+        let name = stmt.name.lexeme.to_owned();
+        match &stmt.initializer {
+            Some(initializer) => {
+                let value = self.evaluate(*initializer)?;
+                let mut environment = self.environment.borrow_mut();
+                if stmt.constant {
+                    environment.define_constant(name, value);
+                } else {
+                    environment.define(name, value);
+                }
+            }
+            None => self.environment.borrow_mut().declare(name),
+        }
+        Ok(())
+    }
+
+    fn visit_block(&mut self, stmt: &Block) -> Result<(), Unwind> {
+        let scope = Environment::new_enclosed(Rc::clone(&self.environment));
+        self.execute_block(&stmt.statements, Rc::new(RefCell::new(scope)))
+    }
+
+    fn visit_if(&mut self, stmt: &If) -> Result<(), Unwind> {
+        let condition = self.evaluate(stmt.condition)?;
+        if self.truthiness(&condition, &stmt.keyword)? {
+            return self.execute(&stmt.then);
+        }
+
+        if let Some(r#else) = &stmt.r#else {
+            return self.execute(r#else);
+        }
+
+        Ok(())
+    }
+
+    fn visit_while(&mut self, stmt: &While) -> Result<(), Unwind> {
+        loop {
+            let condition = self.evaluate(stmt.condition)?;
+            if !self.truthiness(&condition, &stmt.keyword)? {
+                break;
+            }
+            self.check_limits(&stmt.keyword)?;
+
+            match self.execute(&stmt.body) {
+                Ok(()) => (),
+                Err(Unwind::Break(label)) if stmt.is_target(&label) => break,
+                Err(Unwind::Continue(label)) if stmt.is_target(&label) => (),
+                Err(e) => return Err(e),
+            }
+
+            if let Some(increment) = &stmt.increment {
+                self.evaluate(*increment)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_for_in(&mut self, stmt: &ForIn) -> Result<(), Unwind> {
+        // Iterates over a snapshot, changing the collection from the body
+        // doesn't affect the loop.
+        let items = match self.evaluate(stmt.iterable)? {
+            Value::List(list) => list.borrow().clone(),
+            Value::Map(map) => map.borrow().keys(),
+            Value::String(s) => s.chars().map(|c| Value::from(c.to_string())).collect(),
+            _ => {
+                return Err(RuntimeError::new(
+                    stmt.keyword.to_owned(),
+                    "Can only iterate over lists, maps and strings.".to_string(),
+                )
+                .into())
+            }
+        };
+
+        for item in items {
+            self.check_limits(&stmt.keyword)?;
+            // A fresh scope per iteration, closures capture that item only.
+            let mut env = Environment::new_enclosed(Rc::clone(&self.environment));
+            env.define(stmt.name.lexeme.to_owned(), item);
+
+            let body = std::slice::from_ref(&stmt.body);
+            match self.execute_block(body, Rc::new(RefCell::new(env))) {
+                Ok(()) => (),
+                Err(Unwind::Break(label)) if stmt.is_target(&label) => break,
+                Err(Unwind::Continue(label)) if stmt.is_target(&label) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_break(&mut self, stmt: &Break) -> Result<(), Unwind> {
+        Err(Unwind::Break(
+            stmt.label.as_ref().map(|l| l.lexeme.to_owned()),
+        ))
+    }
+
+    fn visit_continue(&mut self, stmt: &Continue) -> Result<(), Unwind> {
+        Err(Unwind::Continue(
+            stmt.label.as_ref().map(|l| l.lexeme.to_owned()),
+        ))
+    }
+
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> Result<(), Unwind> {
+        let function = LoxFunction::new(
+            Rc::clone(stmt),
+            Rc::clone(&self.exprs),
+            Rc::clone(&self.environment),
+            false,
+        );
+        self.environment.borrow_mut().define(
+            stmt.name.lexeme.to_owned(),
+            Value::Callable(Rc::new(function)),
+        );
+        Ok(())
+    }
+
+    fn visit_return(&mut self, stmt: &Return) -> Result<(), Unwind> {
+        let exprs = Rc::clone(&self.exprs);
+        if let (true, Some(Expr::Call(call))) =
+            (stmt.tail_call.get(), stmt.value.map(|v| &exprs[v]))
+        {
+            let (callee, arguments) = self.call_operands(call)?;
+            return Err(Unwind::TailCall(Box::new(TailCall {
+                callee,
+                arguments,
+                paren: call.paren.to_owned(),
+            })));
+        }
+
+        let value = match &stmt.value {
+            Some(value) => self.evaluate(*value)?,
+            None => Value::Nil,
+        };
+
+        Err(Unwind::Return(value))
+    }
+
+    fn visit_class(&mut self, stmt: &Class) -> Result<(), Unwind> {
+        let methods = stmt
+            .methods
+            .iter()
+            .map(|method| {
+                let is_initializer = method.name.lexeme == "init";
+                let function = LoxFunction::new(
+                    Rc::clone(method),
+                    Rc::clone(&self.exprs),
+                    Rc::clone(&self.environment),
+                    is_initializer,
+                );
+                (method.name.lexeme.to_owned(), Rc::new(function))
+            })
+            .collect();
+
+        let class = LoxClass::new(stmt.name.lexeme.to_owned(), methods);
+        self.environment
+            .borrow_mut()
+            .define(stmt.name.lexeme.to_owned(), Value::Class(Rc::new(class)));
+        Ok(())
+    }
+
+    fn visit_match(&mut self, stmt: &Match) -> Result<(), Unwind> {
+        let subject = self.evaluate(stmt.subject)?;
+
+        for (pattern, body) in &stmt.arms {
+            if Value::is_equal(&subject, &self.evaluate(*pattern)?) {
+                return self.execute(body);
+            }
+        }
+
+        match &stmt.r#else {
+            Some(body) => self.execute(body),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ExprVisitor<Result<Value, RuntimeError>> for Interpreter {
+    fn visit_assign(&mut self, expr: &Assign) -> Result<Value, RuntimeError> {
+        let value = self.evaluate(expr.value)?;
+
+        match expr.slot.get() {
+            Some(slot) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(slot, &expr.name, value.to_owned())?;
+            }
+            None => {
+                self.globals
+                    .borrow_mut()
+                    .assign(&expr.name, value.to_owned())?;
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn visit_ternary(&mut self, expr: &Ternary) -> Result<Value, RuntimeError> {
+        let condition = self.evaluate(expr.condition)?;
+        if self.truthiness(&condition, &expr.operator)? {
+            return self.evaluate(expr.then);
+        }
+
+        self.evaluate(expr.r#else)
+    }
+
+    fn visit_logical(&mut self, expr: &Logical) -> Result<Value, RuntimeError> {
+        let left = self.evaluate(expr.left)?;
+
+        // Short-circuits returning the operand itself, not a coerced boolean.
+        match expr.operator.r#type {
+            TokenType::Or if left.is_truthy() => Ok(left),
+            TokenType::And if !left.is_truthy() => Ok(left),
+            TokenType::QuestionQuestion if !matches!(left, Value::Nil) => Ok(left),
+            _ => self.evaluate(expr.right),
+        }
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) -> Result<Value, RuntimeError> {
+        let left = self.evaluate(expr.left)?;
+        let right = self.evaluate(expr.right)?;
+
+        // The comma operator keeps the left value only for its side effects.
+        // Values of different types are never equal, comparing them isn't an error.
+        match expr.operator.r#type {
+            TokenType::Comma => return Ok(right),
+            TokenType::EqualEqual => return Ok(Value::Boolean(Value::is_equal(&left, &right))),
+            TokenType::BangEqual => return Ok(Value::Boolean(!Value::is_equal(&left, &right))),
+            _ => (),
+        }
+
+        // `+` turns a number into a string when the other operand is one.
+        if expr.operator.r#type == TokenType::Plus {
+            match (&left, &right) {
+                (Value::String(l), Value::Number(r)) => {
+                    return Ok(Value::from(format!("{}{}", l, r)))
+                }
+                (Value::Number(l), Value::String(r)) => {
+                    return Ok(Value::from(format!("{}{}", l, r)))
+                }
+                _ => (),
+            }
+        }
+
+        if !Value::variant_eq(&left, &right) {
+            let message = match expr.operator.r#type {
+                TokenType::Greater
+                | TokenType::GreaterEqual
+                | TokenType::Less
+                | TokenType::LessEqual => "Operands must be two numbers or two strings.",
+                _ => "Types don't match in binary expression.",
+            };
+            return Err(RuntimeError::new(
+                expr.operator.to_owned(),
+                message.to_string(),
+            ));
+        }
+
+        if let (Value::Number(l), Value::Number(r)) = (&left, &right) {
+            match expr.operator.r#type {
+                TokenType::Plus => return Ok(Value::Number(l + r)),
+                TokenType::Minus => return Ok(Value::Number(l - r)),
+                TokenType::Slash => {
+                    if *r == 0.0 && !self.options.ieee_division {
+                        return Err(RuntimeError::new(
+                            expr.operator.to_owned(),
+                            "Division by zero.".to_string(),
+                        ));
+                    }
+                    return Ok(Value::Number(l / r));
+                }
+                TokenType::Star => return Ok(Value::Number(l * r)),
+                TokenType::Greater => return Ok(Value::Boolean(l > r)),
+                TokenType::GreaterEqual => return Ok(Value::Boolean(l >= r)),
+                TokenType::Less => return Ok(Value::Boolean(l < r)),
+                TokenType::LessEqual => return Ok(Value::Boolean(l <= r)),
+                TokenType::Ampersand | TokenType::Pipe | TokenType::Caret => {
+                    let l = Value::to_integer(*l, &expr.operator)?;
+                    let r = Value::to_integer(*r, &expr.operator)?;
+                    let result = match expr.operator.r#type {
+                        TokenType::Ampersand => l & r,
+                        TokenType::Pipe => l | r,
+                        _ => l ^ r,
+                    };
+                    return Ok(Value::Number(result as f64));
+                }
+                _ => {
+                    return Err(RuntimeError::new(
+                        expr.operator.to_owned(),
+                        "Invalid binary expression operator.".to_string(),
+                    ));
+                }
+            }
+        }
+
+        if let (Value::String(l), Value::String(r)) = (&left, &right) {
+            match expr.operator.r#type {
+                TokenType::Plus => return Ok(Value::String([&**l, &**r].concat().into())),
+                TokenType::Greater => return Ok(Value::Boolean(l > r)),
+                TokenType::GreaterEqual => return Ok(Value::Boolean(l >= r)),
+                TokenType::Less => return Ok(Value::Boolean(l < r)),
+                TokenType::LessEqual => return Ok(Value::Boolean(l <= r)),
+                _ => {
+                    return Err(RuntimeError::new(
+                        expr.operator.to_owned(),
+                        "Invalid binary expression operator.".to_string(),
+                    ));
+                }
+            }
+        }
+
+        Err(RuntimeError::new(
+            expr.operator.to_owned(),
+            "Invalid binary expression operator.".to_string(),
+        ))
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) -> Result<Value, RuntimeError> {
+        let right = self.evaluate(expr.right)?;
+
+        match expr.operator.r#type {
+            TokenType::Bang => Ok(Value::Boolean(!self.truthiness(&right, &expr.operator)?)),
+            TokenType::Minus => match right {
+                Value::Number(n) => Ok(Value::Number(-n)),
+                _ => Err(RuntimeError::new(
+                    expr.operator.to_owned(),
+                    "Minus should only be used with the number type.".to_string(),
+                )),
+            },
+            TokenType::Tilde => match right {
+                Value::Number(n) => {
+                    Ok(Value::Number(!Value::to_integer(n, &expr.operator)? as f64))
+                }
+                _ => Err(RuntimeError::new(
+                    expr.operator.to_owned(),
+                    "Tilde should only be used with the number type.".to_string(),
+                )),
+            },
+            _ => Err(RuntimeError::new(
+                expr.operator.to_owned(),
+                "Invalid operator in unary expression.".to_string(),
+            )),
+        }
+    }
+
+    fn visit_grouping(&mut self, expr: &Grouping) -> Result<Value, RuntimeError> {
+        self.evaluate(expr.expression)
+    }
+
+    fn visit_literal(&mut self, expr: &Literal) -> Result<Value, RuntimeError> {
+        Ok(match &expr.value {
+            Some(LiteralType::Number(v)) => Value::Number(*v),
+            Some(LiteralType::String(v)) => Value::String(self.intern(v)),
+            Some(LiteralType::Bool(v)) => Value::Boolean(*v),
+            Some(LiteralType::Nil) | None => Value::Nil,
+        })
+    }
+
+    fn visit_variable(&mut self, expr: &Variable) -> Result<Value, RuntimeError> {
+        if expr.slot.get().is_some() {
+            return self.look_up_variable(&expr.name, expr.slot.get());
+        }
+
+        let globals = self.globals.borrow();
+        if let Some(value) = expr
+            .global
+            .get()
+            .and_then(|index| globals.get_global(index, &expr.name))
+        {
+            self.caches.global_hits += 1;
+            return value;
+        }
+        self.caches.global_misses += 1;
+        expr.global.set(globals.global_index(&expr.name.lexeme));
+        globals.get(&expr.name)
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> Result<Value, RuntimeError> {
+        let (callee, arguments) = self.call_operands(expr)?;
+        self.call_value(callee, arguments, &expr.paren)
+    }
+
+    fn visit_get(&mut self, expr: &Get) -> Result<Value, RuntimeError> {
+        match self.evaluate(expr.object)? {
+            Value::Instance(instance) => {
+                let cached = expr.cache.get().and_then(|cache| {
+                    let instance = instance.borrow();
+                    instance.cached(cache).cloned()
+                });
+                if let Some(value) = cached {
+                    self.caches.property_hits += 1;
+                    return Ok(value);
+                }
+
+                // Methods aren't cached, only fields are.
+                self.caches.property_misses += 1;
+                expr.cache.set(instance.borrow().cache(&expr.name.lexeme));
+                LoxInstance::get(&instance, &expr.name)
+            }
+            _ => Err(RuntimeError::new(
+                expr.name.to_owned(),
+                "Only instances have properties.".to_string(),
+            )),
+        }
+    }
+
+    fn visit_set(&mut self, expr: &Set) -> Result<Value, RuntimeError> {
+        let Value::Instance(instance) = self.evaluate(expr.object)? else {
+            return Err(RuntimeError::new(
+                expr.name.to_owned(),
+                "Only instances have fields.".to_string(),
+            ));
+        };
+
+        let value = self.evaluate(expr.value)?;
+        let mut instance = instance.borrow_mut();
+        if let Some(field) = expr
+            .cache
+            .get()
+            .and_then(|cache| instance.cached_mut(cache))
+        {
+            self.caches.property_hits += 1;
+            *field = value.to_owned();
+            return Ok(value);
+        }
+
+        // Adding a field changes the shape, so the cache is made for the new
+        // one.
+        self.caches.property_misses += 1;
+        instance.set(&expr.name, value.to_owned());
+        expr.cache.set(instance.cache(&expr.name.lexeme));
+        Ok(value)
+    }
+
+    fn visit_this(&mut self, expr: &This) -> Result<Value, RuntimeError> {
+        self.look_up_variable(&expr.keyword, expr.slot.get())
+    }
+
+    fn visit_list(&mut self, expr: &List) -> Result<Value, RuntimeError> {
+        let mut elements = vec![];
+        for element in &expr.elements {
+            elements.push(self.evaluate(*element)?);
+        }
+        Ok(self.new_list(elements))
+    }
+
+    fn visit_map(&mut self, expr: &Map) -> Result<Value, RuntimeError> {
+        let mut map = LoxMap::new();
+        for (key, value) in &expr.entries {
+            let key = self.evaluate(*key)?;
+            let value = self.evaluate(*value)?;
+            if !map.set(key, value) {
+                return Err(Interpreter::invalid_key(&expr.brace));
+            }
+        }
+        let map = Rc::new(RefCell::new(map));
+        self.heap.track_map(&map);
+        Ok(Value::Map(map))
+    }
+
+    fn visit_index(&mut self, expr: &Index) -> Result<Value, RuntimeError> {
+        let object = self.evaluate(expr.object)?;
+        let index = self.evaluate(expr.index)?;
+
+        match object {
+            Value::List(list) => {
+                let i = Interpreter::list_index(&list.borrow(), &index, &expr.bracket)?;
+                let element = list.borrow()[i].to_owned();
+                Ok(element)
+            }
+            // Missing keys read as nil, `has` tells them apart.
+            Value::Map(map) => {
+                if !LoxMap::is_valid_key(&index) {
+                    return Err(Interpreter::invalid_key(&expr.bracket));
+                }
+                Ok(map.borrow().get(&index).unwrap_or(Value::Nil))
+            }
+            _ => Err(Interpreter::not_indexable(&expr.bracket)),
+        }
+    }
+
+    fn visit_set_index(&mut self, expr: &SetIndex) -> Result<Value, RuntimeError> {
+        let object = self.evaluate(expr.object)?;
+        let index = self.evaluate(expr.index)?;
+        let value = self.evaluate(expr.value)?;
+
+        match object {
+            Value::List(list) => {
+                let i = Interpreter::list_index(&list.borrow(), &index, &expr.bracket)?;
+                list.borrow_mut()[i] = value.to_owned();
+            }
+            Value::Map(map) => {
+                if !map.borrow_mut().set(index, value.to_owned()) {
+                    return Err(Interpreter::invalid_key(&expr.bracket));
+                }
+            }
+            _ => return Err(Interpreter::not_indexable(&expr.bracket)),
+        }
+        Ok(value)
+    }
+}
+
+// Globals usually sit in a cycle with the functions declared there, so they
+// are emptied along with everything else.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        let globals = self.globals.borrow_mut().clear();
+        drop(globals);
+        self.heap.unpin();
+        self.heap.collect(&[]);
+    }
+}
+
+// Time since some fixed point, for timeouts. Instant panics in wasm, where
+// the browser's clock is read instead, the same one clock() uses.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> Duration {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> Duration {
+    Duration::from_secs_f64(crate::wasm::now() / 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::resolver::Resolver;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    // Scans, parses and resolves the source for a fresh interpreter.
+    // The interpreter starts out on the program's expressions.
+    fn prepare(options: Options, source: &str) -> (Interpreter, Vec<Stmt>, Rc<Exprs>) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+
+        let mut resolver = Resolver::new(&parser.exprs);
+        resolver.resolve(&statements);
+        assert!(
+            !resolver.diagnostics.had_error(),
+            "resolution failed: {}",
+            source
+        );
+
+        let exprs = Rc::new(parser.exprs);
+        let mut interpreter = Interpreter::new(options);
+        interpreter.exprs = Rc::clone(&exprs);
+        (interpreter, statements, exprs)
+    }
+
+    // Runs the program and evaluates its trailing expression, giving back the
+    // printed value or None on a runtime error.
+    fn eval_with(options: Options, source: &str) -> Option<String> {
+        let (mut interpreter, statements, _) = prepare(options, &format!("{};", source));
+        let Some((Stmt::Expression(last), rest)) = statements.split_last() else {
+            panic!("not an expression: {}", source);
+        };
+
+        for statement in rest {
+            interpreter.execute(statement).ok()?;
+        }
+        interpreter
+            .evaluate(last.expression)
+            .ok()
+            .map(|v| v.to_string())
+    }
+
+    fn eval(source: &str) -> Option<String> {
+        eval_with(Options::default(), source)
+    }
+
+    // Runs a whole program, telling whether it finished without errors.
+    fn run_with(options: Options, source: &str) -> bool {
+        let (mut interpreter, statements, _) = prepare(options, source);
+        statements.iter().all(|s| interpreter.execute(s).is_ok())
+    }
+
+    #[test]
+    fn plus_coerces_numbers_to_strings() {
+        assert_eq!(eval("\"count: \" + 3"), Some("count: 3".to_string()));
+        assert_eq!(eval("2.5 + \" apples\""), Some("2.5 apples".to_string()));
+        assert_eq!(eval("\"a\" - 1"), None);
+        assert_eq!(eval("\"a\" + true"), None);
+    }
+
+    #[test]
+    fn string_literals_share_their_text() {
+        let source = "var a = \"hi\"; var b = \"hi\"; var c = a + \"\";";
+        let (mut interpreter, statements, exprs) = prepare(Options::default(), source);
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+
+        let text = |name| match interpreter.global(name) {
+            Some(Value::String(s)) => s,
+            _ => panic!("not a string: {}", name),
+        };
+        assert!(Rc::ptr_eq(&text("a"), &text("b")));
+        assert!(!Rc::ptr_eq(&text("a"), &text("c")));
+        assert!(Value::is_equal(
+            &Value::String(text("a")),
+            &Value::String(text("c"))
+        ));
+    }
+
+    #[test]
+    fn deep_expressions() {
+        // Left-associative, so the chain is a tree a hundred thousand deep.
+        let chain = vec!["1"; 100_000].join(" + ");
+        assert_eq!(eval(&chain), Some("100000".to_string()));
+
+        let nested = format!("{}1{}", "(".repeat(5_000), ")".repeat(5_000));
+        assert_eq!(eval(&nested), Some("1".to_string()));
+    }
+
+    #[test]
+    fn string_ordering() {
+        assert_eq!(eval("\"a\" < \"b\""), Some("true".to_string()));
+        assert_eq!(eval("\"b\" <= \"a\""), Some("false".to_string()));
+        assert_eq!(eval("\"apple\" > \"app\""), Some("true".to_string()));
+        assert_eq!(eval("\"a\" >= \"a\""), Some("true".to_string()));
+        assert_eq!(eval("1 < \"2\""), None);
+        assert_eq!(eval("\"1\" >= 2"), None);
+    }
+
+    #[test]
+    fn equality_across_types() {
+        assert_eq!(eval("1 == \"1\""), Some("false".to_string()));
+        assert_eq!(eval("1 != \"1\""), Some("true".to_string()));
+        assert_eq!(eval("nil == nil"), Some("true".to_string()));
+        assert_eq!(eval("nil == false"), Some("false".to_string()));
+        assert_eq!(eval("true != true"), Some("false".to_string()));
+        assert_eq!(eval("\"a\" == \"a\""), Some("true".to_string()));
+        assert_eq!(eval("1 - \"1\""), None);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), None);
+        assert_eq!(eval("1 / 2"), Some("0.5".to_string()));
+
+        let ieee = Options {
+            ieee_division: true,
+            ..Options::default()
+        };
+        assert_eq!(eval_with(ieee, "1 / 0"), Some("Infinity".to_string()));
+        assert_eq!(eval_with(ieee, "-1 / 0"), Some("-Infinity".to_string()));
+    }
+
+    #[test]
+    fn bitwise_operands() {
+        assert_eq!(eval("6 & 3"), Some("2".to_string()));
+        assert_eq!(eval("6 | 3"), Some("7".to_string()));
+        assert_eq!(eval("6 ^ 3"), Some("5".to_string()));
+        assert_eq!(
+            eval("-9223372036854775808 & -1"),
+            Some("-9223372036854776000".to_string())
+        );
+        assert_eq!(eval("1.5 & 1"), None);
+        assert_eq!(eval("1 | 10000000000000000000"), None);
+        assert_eq!(eval("9223372036854775808 ^ 1"), None);
+        assert_eq!(eval("-99999999999999999999 & 1"), None);
+        assert_eq!(eval("1 & (1 / 0)"), None);
+    }
+
+    #[test]
+    fn strict_truthiness() {
+        assert!(run_with(
+            Options::default(),
+            "if (1) print 1; else print 2;"
+        ));
+        assert_eq!(eval("1 ? \"a\" : \"b\""), Some("a".to_string()));
+        assert_eq!(eval("!nil"), Some("true".to_string()));
+
+        let strict = Options {
+            strict_truthiness: true,
+            ..Options::default()
+        };
+        assert!(!run_with(strict, "if (1) print 1;"));
+        assert!(!run_with(strict, "while (nil) print 1;"));
+        assert!(run_with(strict, "if (1 == 1) print 1;"));
+        assert_eq!(eval_with(strict, "1 ? \"a\" : \"b\""), None);
+        assert_eq!(eval_with(strict, "!nil"), None);
+        assert_eq!(eval_with(strict, "!false"), Some("true".to_string()));
+    }
+
+    #[test]
+    fn value_stringification() {
+        assert_eq!(eval("2 + 1"), Some("3".to_string()));
+        assert_eq!(eval("2.5 * 2"), Some("5".to_string()));
+        assert_eq!(eval("1 / 4"), Some("0.25".to_string()));
+        assert_eq!(eval("-0"), Some("-0".to_string()));
+        assert_eq!(eval("nil"), Some("nil".to_string()));
+        assert_eq!(eval("true"), Some("true".to_string()));
+        assert_eq!(eval("\"plain\""), Some("plain".to_string()));
+
+        let ieee = Options {
+            ieee_division: true,
+            ..Options::default()
+        };
+        assert_eq!(eval_with(ieee, "0 / 0"), Some("NaN".to_string()));
+    }
+
+    #[test]
+    fn nil_coalescing() {
+        assert_eq!(eval("nil ?? 2"), Some("2".to_string()));
+        assert_eq!(eval("false ?? 2"), Some("false".to_string()));
+        assert_eq!(eval("nil ?? nil ?? \"c\""), Some("c".to_string()));
+        // The right side is never evaluated when the left isn't nil.
+        assert_eq!(eval("1 ?? 1 / 0"), Some("1".to_string()));
+    }
+
+    #[test]
+    fn comma_operator() {
+        assert_eq!(eval("(1, 2)"), Some("2".to_string()));
+        assert_eq!(eval("1, \"a\", nil"), Some("nil".to_string()));
+        // The left operand still runs, errors included.
+        assert_eq!(eval("(1 / 0, 2)"), None);
+    }
+
+    #[test]
+    fn host_natives() {
+        let tokens =
+            Scanner::new("print double(21); half(1, 2); double(nil);".to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        let mut interpreter = Interpreter::new(Options::default());
+        interpreter.register_native("double", 1, |args| {
+            let n = f64::try_from(args[0].to_owned())?;
+            Ok(Value::from(n * 2.0))
+        });
+        interpreter.register_native("half", Arity::AtLeast(1), |args| Ok(args[0].to_owned()));
+        Resolver::new(&parser.exprs).resolve(&statements);
+        let exprs = Rc::new(parser.exprs);
+
+        let e = interpreter.interpret(&statements, &exprs).unwrap_err();
+        assert_eq!(
+            (e.message.as_str(), e.token.column),
+            ("Expected number, got nil.", 41)
+        );
+        assert_eq!(
+            interpreter.global("double").map(|f| f.to_string()),
+            Some("<native fn double>".to_string())
+        );
+    }
+
+    #[test]
+    fn foreign_values() {
+        struct Counter(Cell<u32>);
+
+        let tokens =
+            Scanner::new("bump(counter); bump(counter); print counter; bump(1);".to_string())
+                .scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        let mut interpreter = Interpreter::new(Options::default());
+        let counter = Rc::new(Counter(Cell::new(0)));
+        interpreter.define_global("counter", Value::Foreign(counter.clone()));
+        interpreter.register_native("bump", 1, |args| {
+            let counter = args[0].downcast::<Counter>()?;
+            counter.0.set(counter.0.get() + 1);
+            Ok(Value::Nil)
+        });
+        Resolver::new(&parser.exprs).resolve(&statements);
+        let exprs = Rc::new(parser.exprs);
+
+        let e = interpreter.interpret(&statements, &exprs).unwrap_err();
+        assert!(e.message.ends_with("Counter, got number."));
+        assert_eq!(counter.0.get(), 2);
+    }
+
+    #[test]
+    fn host_calls() {
+        let source = "var total = 0; fun add(n) { total = total + n; return total; }";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        let mut interpreter = Interpreter::new(Options::default());
+        Resolver::new(&parser.exprs).resolve(&statements);
+        let exprs = Rc::new(parser.exprs);
+        interpreter.interpret(&statements, &exprs).unwrap();
+
+        let add = interpreter.get_global("add");
+        for n in 1..=3 {
+            interpreter.call(&add, vec![Value::from(n)]).unwrap();
+        }
+        assert_eq!(interpreter.get_global("total").to_string(), "6");
+        assert!(matches!(interpreter.get_global("missing"), Value::Nil));
+
+        let e = interpreter.call(&add, vec![]).unwrap_err();
+        assert_eq!(e.to_string(), "Error: Expected 1 arguments but got 0.");
+        let e = interpreter.call(&add, vec![Value::Nil]).unwrap_err();
+        let LoxError::Runtime(e) = e else {
+            unreachable!()
+        };
+        assert_eq!(e.message, "Types don't match in binary expression.");
+        assert!(e.to_string().starts_with("[line 1, col 43] Error:"));
+        assert_eq!(e.trace[0].name, "add");
+    }
+
+    #[test]
+    fn pins_are_bounded() {
+        let (mut interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "var xs = [1]; push(xs, xs); fun f(n) { return xs; } \
+             for (var i = 0; i < 1000; i = i + 1) { keep(xs); }",
+        );
+        interpreter.register_native("keep", 1, |_| Ok(Value::Nil));
+        interpreter.interpret(&statements, &exprs).unwrap();
+        assert_eq!(interpreter.heap.pinned(), 1);
+
+        let f = interpreter.get_global("f");
+        for n in 0..1000 {
+            interpreter.call(&f, vec![Value::from(n)]).unwrap();
+            interpreter.get_global("f");
+        }
+        assert_eq!(interpreter.heap.pinned(), 2);
+
+        // Released, the cycle goes once nothing else reaches it.
+        let xs = interpreter.get_global("xs");
+        interpreter.release(&xs);
+        interpreter.release(&f);
+        drop((xs, f));
+        let live = interpreter.heap.live();
+        interpreter.define_global("xs", Value::Nil);
+        interpreter.collect();
+        assert_eq!(interpreter.heap.live(), live - 1);
+        assert_eq!(interpreter.heap.pinned(), 0);
+    }
+
+    #[test]
+    fn host_held_values_are_kept() {
+        struct Holder(Value);
+
+        let (mut interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "var boxed; var kept; { fun f() { return f; } boxed = box(f); } \
+             { var xs = [1]; push(xs, xs); keep(xs); }",
+        );
+        let stash = Rc::new(RefCell::new(vec![]));
+        let kept = Rc::clone(&stash);
+        interpreter.register_native("keep", 1, move |args| {
+            kept.borrow_mut().extend(args);
+            Ok(Value::Nil)
+        });
+        interpreter.register_native("box", 1, |args| Ok(Value::foreign(Holder(args[0].clone()))));
+        interpreter.interpret(&statements, &exprs).unwrap();
+        interpreter.collect();
+
+        // Only the host closure and the foreign object reach these.
+        let Value::List(xs) = &stash.borrow()[0] else {
+            unreachable!()
+        };
+        assert_eq!(xs.borrow().len(), 2);
+        let f = interpreter
+            .get_global("boxed")
+            .downcast::<Holder>()
+            .unwrap()
+            .0
+            .clone();
+        let returned = interpreter.call(&f, vec![]).unwrap();
+        assert!(Value::is_equal(&returned, &f));
+    }
+
+    #[test]
+    fn host_values_outlive_runs() {
+        let (mut interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "fun mk() { var xs = [1]; fun get() { return xs; } return get; }",
+        );
+        interpreter.interpret(&statements, &exprs).unwrap();
+        let get = interpreter
+            .call(&interpreter.get_global("mk"), vec![])
+            .unwrap();
+
+        // Nothing the interpreter knows of holds on to `get` anymore, running
+        // more code mustn't take it apart.
+        let (_, statements, exprs) = prepare(Options::default(), "var a = [2]; push(a, a);");
+        interpreter.interpret(&statements, &exprs).unwrap();
+        let xs = interpreter.call(&get, vec![]).unwrap();
+        assert_eq!(xs.to_string(), "[1]");
+    }
+
+    #[test]
+    fn natives() {
+        assert_eq!(eval("clock"), Some("<native fn clock>".to_string()));
+        assert_eq!(eval("clock() > 0"), Some("true".to_string()));
+        assert_eq!(eval("clock(1)"), None);
+        assert_eq!(eval("fun f() {} f"), Some("<fn f>".to_string()));
+        assert_eq!(eval("clock == clock"), Some("true".to_string()));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let exit = |source| {
+            let (mut interpreter, statements, exprs) = prepare(Options::default(), source);
+            interpreter.interpret(&statements, &exprs).unwrap_err()
+        };
+
+        assert_eq!(exit("exit(0);").exit, Some(0));
+        assert_eq!(exit("exit(3);").exit, Some(3));
+        assert_eq!(exit("exit(255);").exit, Some(255));
+        for source in ["exit(256);", "exit(-1);", "exit(1.5);", "exit(\"1\");"] {
+            let error = exit(source);
+            assert_eq!(error.exit, None);
+            assert_eq!(
+                error.message,
+                "Exit code must be an integer between 0 and 255."
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_random() {
+        let seeded = Options {
+            seed: Some(42),
+            ..Options::default()
+        };
+        let draws = "var a = []; for (var i = 0; i < 5; i = i + 1) push(a, random()); a";
+        assert!(eval_with(seeded, draws).is_some());
+        assert_eq!(eval_with(seeded, draws), eval_with(seeded, draws));
+        assert_ne!(
+            eval_with(seeded, draws),
+            eval_with(
+                Options {
+                    seed: Some(7),
+                    ..seeded
+                },
+                draws
+            )
+        );
+
+        let in_range = "var ok = true; for (var i = 0; i < 100; i = i + 1) { \
+            var r = random(); var n = randomInt(-2, 2); \
+            var hit = false; for (var k = -2; k <= 2; k = k + 1) hit = hit or n == k; \
+            ok = ok and hit and r >= 0 and r < 1; } ok";
+        assert_eq!(eval_with(seeded, in_range), Some("true".to_string()));
+        assert_eq!(eval("randomInt(3, 3)"), Some("3".to_string()));
+        assert_eq!(eval("randomInt(2, 1)"), None);
+        assert_eq!(eval("randomInt(0.5, 1)"), None);
+        assert_eq!(eval("randomInt(\"a\", 1)"), None);
+    }
+
+    #[test]
+    fn getenv() {
+        std::env::set_var("JLOX_TEST_GETENV", "set");
+        assert_eq!(
+            eval("getenv(\"JLOX_TEST_GETENV\")"),
+            Some("set".to_string())
+        );
+        assert_eq!(eval("getenv(\"JLOX_TEST_UNSET\")"), Some("nil".to_string()));
+        assert_eq!(eval("getenv(1)"), None);
+    }
+
+    #[test]
+    fn read_line() {
+        let (interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "var a = readLine(); var b = readLine(); var c = readLine();",
+        );
+        let mut interpreter = interpreter.with_input(Box::new("first\r\nsecond".as_bytes()));
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+
+        for (name, expected) in [("a", "first"), ("b", "second"), ("c", "nil")] {
+            let (_, statements, exprs) = prepare(Options::default(), &format!("{};", name));
+            let Stmt::Expression(ref e) = statements[0] else {
+                unreachable!()
+            };
+            assert_eq!(
+                interpreter
+                    .using_exprs(&exprs, |interpreter| interpreter.evaluate(e.expression))
+                    .ok()
+                    .map(|v| v.to_string()),
+                Some(expected.to_string())
+            );
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn print_output() {
+        let output = Output::default();
+        let (interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "print readLine() + \"!\"; print [1, nil];",
+        );
+        let mut interpreter = interpreter
+            .with_input(Box::new("hi\n".as_bytes()))
+            .with_output(Box::new(output.clone()));
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+        assert_eq!(output.0.borrow().as_slice(), b"hi!\n[1, nil]\n");
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(eval("[1, \"a\", nil]"), Some("[1, a, nil]".to_string()));
+        assert_eq!(eval("[]"), Some("[]".to_string()));
+        assert_eq!(eval("[1, 2, 3][1]"), Some("2".to_string()));
+        assert_eq!(eval("[[1], [2, 3]][1][0]"), Some("2".to_string()));
+        assert_eq!(
+            eval("var xs = [1, 2]; var ys = xs; ys[0] = 5; xs"),
+            Some("[5, 2]".to_string())
+        );
+        assert_eq!(
+            eval("var xs = []; push(xs, 1); push(xs, 2); pop(xs); len(xs)"),
+            Some("1".to_string())
+        );
+        assert_eq!(eval("len(\"héllo\")"), Some("5".to_string()));
+        assert_eq!(
+            eval("var parts = []; for (var i = 0; i < 3; i = i + 1) push(parts, i); join(parts, \", \")"),
+            Some("0, 1, 2".to_string())
+        );
+        assert_eq!(
+            eval("join([\"a\", nil, [1]], \"\") + join([], \"-\")"),
+            Some("anil[1]".to_string())
+        );
+        assert_eq!(eval("[1, 2] == [1, 2]"), Some("false".to_string()));
+
+        // Lists inside themselves print once, repeats that aren't cycles in full.
+        assert_eq!(
+            eval("var l = [1]; push(l, l); push(l, [l]); l"),
+            Some("[1, [...], [[...]]]".to_string())
+        );
+        assert_eq!(eval("var l = [1]; [l, l]"), Some("[[1], [1]]".to_string()));
+
+        assert_eq!(eval("[1][1]"), None);
+        assert_eq!(eval("[1][-1]"), None);
+        assert_eq!(eval("[1][0.5]"), None);
+        assert_eq!(eval("[1][\"0\"]"), None);
+        assert_eq!(eval("1[0]"), None);
+        assert_eq!(eval("pop([])"), None);
+        assert_eq!(eval("push(1, 2)"), None);
+        assert_eq!(eval("join([1], 2)"), None);
+        assert_eq!(eval("join(\"ab\", \"\")"), None);
+    }
+
+    #[test]
+    fn maps() {
+        assert_eq!(
+            eval("({\"a\": 1, 2: true, nil: [], false: 0})"),
+            Some("{a: 1, 2: true, nil: [], false: 0}".to_string())
+        );
+        assert_eq!(eval("({})"), Some("{}".to_string()));
+        // At the start of a statement a brace opens a block instead.
+        assert_eq!(eval("var m = {}; m"), Some("{}".to_string()));
+        assert_eq!(eval("({\"a\": 1})[\"a\"]"), Some("1".to_string()));
+        assert_eq!(eval("({\"a\": 1})[\"b\"]"), Some("nil".to_string()));
+        assert_eq!(eval("({1: \"x\"})[2 - 1]"), Some("x".to_string()));
+        assert_eq!(
+            eval("var m = {}; m[\"k\"] = 1; m[\"k\"] = m[\"k\"] + 1; m"),
+            Some("{k: 2}".to_string())
+        );
+        assert_eq!(
+            eval("var m = {1: 1, \"1\": 2}; [keys(m), values(m), len(m)]"),
+            Some("[[1, 1], [1, 2], 2]".to_string())
+        );
+        assert_eq!(
+            eval("var m = {nil: nil}; [has(m, nil), has(m, false), m[nil]]"),
+            Some("[true, false, nil]".to_string())
+        );
+
+        assert_eq!(eval("({[]: 1})"), None);
+        assert_eq!(eval("({})[{}]"), None);
+        assert_eq!(eval("var m = {}; m[clock] = 1"), None);
+        assert_eq!(eval("keys([])"), None);
+
+        // Cycles through maps and lists alike print once.
+        assert_eq!(
+            eval("var m = {}; m[\"self\"] = m; m[\"list\"] = [m]; var l = [m]; m[\"l\"] = l; l"),
+            Some("[{self: {...}, list: [{...}], l: [...]}]".to_string())
+        );
+    }
+
+    #[test]
+    fn for_in() {
+        let sum = |source: &str| {
+            eval(&format!(
+                "var out = \"\"; for (x in {}) out = out + x; out",
+                source
+            ))
+        };
+        assert_eq!(sum("[1, 2, 3]"), Some("123".to_string()));
+        assert_eq!(sum("({\"a\": 1, \"b\": 2})"), Some("ab".to_string()));
+        assert_eq!(sum("\"héllo\""), Some("héllo".to_string()));
+        assert_eq!(sum("[]"), Some("".to_string()));
+        assert_eq!(sum("1"), None);
+
+        assert_eq!(
+            eval(
+                "var out = \"\"; \
+                 for (x in [1, 2, 3, 4, 5]) { if (x == 2) continue; if (x == 4) break; out = out + x; } \
+                 out"
+            ),
+            Some("13".to_string())
+        );
+        assert_eq!(
+            eval(
+                "var out = \"\"; \
+                 outer: for (x in \"ab\") for (y in [1, 2]) { if (y == 2) continue outer; out = out + x + y; } \
+                 out"
+            ),
+            Some("a1b1".to_string())
+        );
+        // Every iteration binds a new variable.
+        assert_eq!(
+            eval(
+                "var fs = []; for (x in [1, 2]) { fun f() { return x; } push(fs, f); } \
+                 fs[0]() + fs[1]()"
+            ),
+            Some("3".to_string())
+        );
+        // Growing the list from the body doesn't extend the loop.
+        assert_eq!(
+            eval("var xs = [1]; for (x in xs) push(xs, x); len(xs)"),
+            Some("2".to_string())
+        );
+    }
+
+    // Runs the program, giving back the message of the runtime error it
+    // stopped at, if any.
+    fn error(source: &str) -> Option<String> {
+        let (mut interpreter, statements, exprs) = prepare(Options::default(), source);
+        interpreter
+            .interpret(&statements, &exprs)
+            .err()
+            .map(|e| e.message)
+    }
+
+    #[test]
+    fn arity_mismatch() {
+        let expected = |n: &str, m: usize| Some(format!("Expected {} arguments but got {}.", n, m));
+
+        assert_eq!(error("fun f() {} f(1);"), expected("0", 1));
+        assert_eq!(error("fun f(a, b) {} f(1);"), expected("2", 1));
+        assert_eq!(error("class A { init(a) {} } A();"), expected("1", 0));
+        assert_eq!(error("class A {} A(1);"), expected("0", 1));
+        assert_eq!(error("clock(nil);"), expected("0", 1));
+        assert_eq!(error("len();"), expected("1", 0));
+        assert_eq!(error("push([]);"), expected("at least 2", 1));
+        assert_eq!(error("fun f() {} f(); clock();"), None);
+
+        assert_eq!(
+            eval("var xs = []; push(xs, 1); push(xs, 2, 3, 4); xs"),
+            Some("[1, 2, 3, 4]".to_string())
+        );
+
+        // The error points at the closing paren of the call.
+        let (mut interpreter, statements, exprs) =
+            prepare(Options::default(), "fun f() {}\nf(\n1\n);");
+        let e = interpreter.interpret(&statements, &exprs).err().unwrap();
+        assert_eq!((e.token.lexeme.as_str(), e.token.line), (")", 4));
+    }
+
+    #[test]
+    fn stack_traces() {
+        let source = "fun inner(x) {\n  return x / 0;\n}\n\
+                      fun outer() {\n  print inner(1);\n}\n\
+                      class A { init() { outer(); } }\n\
+                      A();";
+        let (mut interpreter, statements, exprs) = prepare(Options::default(), source);
+        let e = interpreter.interpret(&statements, &exprs).err().unwrap();
+
+        let trace: Vec<(&str, u64)> = e.trace.iter().map(|f| (f.name.as_str(), f.line)).collect();
+        assert_eq!(trace, vec![("inner", 5), ("outer", 7), ("A", 8)]);
+        assert_eq!(e.token.line, 2);
+
+        // Errors outside any call carry no trace, and the stack is empty again
+        // once a call fails.
+        let (_, statements, exprs) = prepare(Options::default(), "1 / 0;");
+        let e = interpreter.interpret(&statements, &exprs).err().unwrap();
+        assert!(e.trace.is_empty());
+        assert!(interpreter.frames.is_empty());
+    }
+
+    #[test]
+    fn step_and_time_limits() {
+        let bounded = Options {
+            max_steps: Some(100),
+            ..Options::default()
+        };
+        let (mut interpreter, statements, exprs) =
+            prepare(bounded, "var i = 0; while (true) i = i + 1;");
+        let e = interpreter.interpret(&statements, &exprs).unwrap_err();
+        assert_eq!(e.message, "Step limit exceeded (max steps 100).");
+        assert_eq!(e.limit, Some(Limit::Steps));
+
+        // The budget is per run, and runs within it are unaffected.
+        let tokens = Scanner::new("fun f(n) { return n; } for (x in [1, 2]) f(x);".to_string())
+            .scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        Resolver::new(&parser.exprs).resolve(&statements);
+        let exprs = Rc::new(parser.exprs);
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+
+        let timed = Options {
+            timeout: Some(Duration::from_millis(10)),
+            ..Options::default()
+        };
+        let (mut interpreter, statements, exprs) =
+            prepare(timed, "fun f() { f2(); } fun f2() { while (true) {} } f();");
+        let e = interpreter.interpret(&statements, &exprs).unwrap_err();
+        assert_eq!(e.limit, Some(Limit::Time));
+        assert_eq!(e.trace.len(), 2);
+    }
+
+    #[test]
+    fn script_args() {
+        assert_eq!(eval("args"), Some("[]".to_string()));
+
+        let (interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "var n = len(args); var first = args[0];",
+        );
+        let mut interpreter = interpreter.with_args(vec!["input.txt".to_string(), "3".to_string()]);
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+        assert_eq!(
+            interpreter.global("n").map(|v| v.to_string()),
+            Some("2".to_string())
+        );
+        assert_eq!(
+            interpreter.global("first").map(|v| v.to_string()),
+            Some("input.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn sandbox() {
+        let sandboxed = Options {
+            capabilities: Capabilities::none(),
+            ..Options::default()
+        };
+        assert_eq!(eval_with(sandboxed, "clock"), None);
+        assert_eq!(eval_with(sandboxed, "readLine"), None);
+        assert_eq!(eval_with(sandboxed, "getenv"), None);
+        assert_eq!(
+            eval_with(sandboxed, "len"),
+            Some("<native fn len>".to_string())
+        );
+
+        let clock_only = Options {
+            capabilities: Capabilities {
+                clock: true,
+                ..Capabilities::none()
+            },
+            ..Options::default()
+        };
+        assert!(eval_with(clock_only, "clock").is_some());
+        assert_eq!(eval_with(clock_only, "readLine"), None);
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        let shallow = Options {
+            max_call_depth: 50,
+            ..Options::default()
+        };
+        let (mut interpreter, statements, exprs) =
+            prepare(shallow, "fun f(n) { if (n > 0) f(n - 1); } f(49); f(50);");
+        let e = interpreter.interpret(&statements, &exprs).err().unwrap();
+
+        assert_eq!(e.message, "Stack overflow (max call depth 50).");
+        assert_eq!(e.limit, Some(Limit::CallDepth));
+        assert_eq!(e.trace.len(), 50);
+        assert!(interpreter.frames.is_empty());
+    }
+
+    #[test]
+    fn tail_calls() {
+        let shallow = Options {
+            max_call_depth: 50,
+            ..Options::default()
+        };
+        let run = |source: &str| {
+            let (mut interpreter, statements, exprs) = prepare(shallow, source);
+            let result = interpreter.interpret(&statements, &exprs);
+            assert!(interpreter.frames.is_empty());
+            result.err().map(|e| e.message)
+        };
+        let overflow = Some("Stack overflow (max call depth 50).".to_string());
+
+        // Returned calls run in place of the function returning them, however
+        // deep the recursion goes.
+        assert_eq!(
+            run(
+                "fun loop(n, acc) { if (n == 0) return acc; return loop(n - 1, acc + n); } \
+                 if (loop(1000, 0) != 500500) 1 / 0;"
+            ),
+            None
+        );
+        assert_eq!(
+            run(
+                "fun even(n) { if (n == 0) return true; return odd(n - 1); } \
+                 fun odd(n) { if (n == 0) return false; return even(n - 1); } \
+                 if (!even(1000)) 1 / 0;"
+            ),
+            None
+        );
+        assert_eq!(
+            run(
+                "class C { count(n) { if (n == 0) return \"done\"; return this.count(n - 1); } } \
+                 if (C().count(1000) != \"done\") 1 / 0;"
+            ),
+            None
+        );
+
+        // Calls anywhere else still nest.
+        assert_eq!(
+            run("fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); } f(1000);"),
+            overflow
+        );
+        assert_eq!(
+            run("fun g(x) { return x; } \
+                 fun h(n) { if (n == 0) return 0; return g(h(n - 1)); } h(1000);"),
+            overflow
+        );
+        assert_eq!(
+            run("fun f(n) { if (n == 0) return 0; var r = f(n - 1); return r; } f(1000);"),
+            overflow
+        );
+        assert_eq!(run("fun f(n) { if (n > 0) f(n - 1); } f(1000);"), overflow);
+
+        // Natives and classes are called as usual, and a bad tail call is
+        // still an error.
+        assert_eq!(
+            run(
+                "class A { init(n) { this.n = n; } } fun make() { return A(len(\"ab\")); } \
+                 if (make().n != 2) 1 / 0;"
+            ),
+            None
+        );
+        assert_eq!(
+            run("fun f() { return f(1); } f();"),
+            Some("Expected 0 arguments but got 1.".to_string())
+        );
+        assert_eq!(
+            run("fun f() { return 1(); } f();"),
+            Some("Can only call functions and classes.".to_string())
+        );
+
+        // The function making a tail call is gone from the trace.
+        let (mut interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "fun inner() {\n  return 1 / 0;\n}\nfun outer() {\n  return inner();\n}\nouter();",
+        );
+        let e = interpreter.interpret(&statements, &exprs).err().unwrap();
+        let trace: Vec<(&str, u64)> = e.trace.iter().map(|f| (f.name.as_str(), f.line)).collect();
+        assert_eq!(trace, vec![("inner", 5)]);
+    }
+
+    #[test]
+    fn inline_caches() {
+        // One site sees instances of different shapes, and fields added after
+        // a lookup was cached.
+        assert_eq!(
+            eval("class A {} fun x(o) { return o.x; } \
+                  var a = A(); a.x = 1; a.y = 2; var b = A(); b.y = 3; b.x = 4; var c = A(); c.x = 5; \
+                  x(a) + x(b) * 10 + x(a) * 100 + x(c) * 1000"),
+            Some("5141".to_string())
+        );
+        assert_eq!(
+            eval(
+                "class A {} var a = A(); a.x = 1; fun f() { return a.x; } \
+                  var before = f(); a.y = 2; a.x = 3; before + f() * 10"
+            ),
+            Some("31".to_string())
+        );
+        assert_eq!(
+            eval(
+                "class B { m() { return 1; } } fun m(o) { return o.m; } \
+                  var b = B(); var before = m(b)(); b.m = 2; before + m(b) * 10"
+            ),
+            Some("21".to_string())
+        );
+        assert_eq!(
+            eval("var g = 1; fun f() { return g; } var before = f(); var g = 2; before + f() * 10"),
+            Some("21".to_string())
+        );
+
+        let (mut interpreter, statements, exprs) = prepare(
+            Options::default(),
+            "class P {} var p = P(); p.x = 1; var t = 0; \
+             for (var i = 0; i < 10; i = i + 1) t = t + p.x;",
+        );
+        interpreter.interpret(&statements, &exprs).unwrap();
+        assert_eq!(
+            interpreter.cache_stats(),
+            CacheStats {
+                property_hits: 9,
+                property_misses: 2,
+                global_hits: 18,
+                global_misses: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn collect_breaks_cycles() {
+        let source = "{ fun f() { return f; } } \
+                      var xs = []; push(xs, xs); \
+                      { var o = {}; o[\"self\"] = o; } \
+                      class A {} { var a = A(); a.me = a; }";
+        let (mut interpreter, statements, _) = prepare(Options::default(), source);
+        for statement in &statements {
+            assert!(interpreter.execute(statement).is_ok());
+        }
+
+        // The function's block, the map and the instance only keep themselves
+        // alive, the list and `args` are still reachable from the globals.
+        assert_eq!(interpreter.heap.live(), 5);
+        interpreter.collect();
+        assert_eq!(interpreter.heap.live(), 2);
+        assert_eq!(
+            eval("var xs = [1]; push(xs, xs); len(xs)"),
+            Some("2".to_string())
+        );
+    }
+
+    #[test]
+    fn drop_frees_globals() {
+        let source = "fun f() { return f; } var xs = []; push(xs, xs, f);";
+        let (mut interpreter, statements, exprs) = prepare(Options::default(), source);
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+
+        let globals = Rc::downgrade(&interpreter.globals);
+        drop(interpreter);
+        assert!(globals.upgrade().is_none());
+    }
+
+    #[test]
+    fn closures_keep_the_resolved_binding() {
+        // A later declaration in the enclosing block must not change what the
+        // closure already refers to.
+        let source = "var a = \"global\"; var first; var second; \
+                      { fun show() { return a; } first = show(); var a = \"block\"; second = show(); } \
+                      first + \" \" + second";
+        assert_eq!(eval(source), Some("global global".to_string()));
+    }
+
+    #[test]
+    fn reading_uninitialized_variables() {
+        let expected = Some("Variable 'a' used before initialization.".to_string());
+        assert_eq!(error("var a; print a;"), expected);
+        assert_eq!(error("{ var a; print a; }"), expected);
+        assert_eq!(error("var a; a = 1; print a;"), None);
+        assert_eq!(error("var a = nil; print a;"), None);
+    }
+
+    #[test]
+    fn interpreter_state_persists() {
+        let (mut interpreter, statements, exprs) = prepare(Options::default(), "var a = 1;");
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+
+        let (_, statements, exprs) = prepare(Options::default(), "a = a + 1;");
+        assert!(interpreter.interpret(&statements, &exprs).is_ok());
+
+        let (_, statements, exprs) = prepare(Options::default(), "a;");
+        let Stmt::Expression(ref e) = statements[0] else {
+            unreachable!()
+        };
+        assert_eq!(
+            interpreter
+                .using_exprs(&exprs, |interpreter| interpreter.evaluate(e.expression))
+                .ok()
+                .map(|v| v.to_string()),
+            Some("2".to_string())
+        );
+    }
+}
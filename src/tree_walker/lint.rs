@@ -0,0 +1,524 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::errors::{Diagnostics, Severity};
+use super::parser::Parser;
+use super::resolver::Resolver;
+use super::scanner::Scanner;
+use super::stack;
+use super::syntax_tree::{Expr, ExprId, Exprs, Function, Literal, Stmt, Variable};
+use super::tokens::{LiteralType, Token, TokenType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    UnusedVariable,
+    UnusedGlobal,
+    Shadowing,
+    ConstantCondition,
+    EmptyBlock,
+    BoolComparison,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 6] = [
+        Rule::UnusedVariable,
+        Rule::UnusedGlobal,
+        Rule::Shadowing,
+        Rule::ConstantCondition,
+        Rule::EmptyBlock,
+        Rule::BoolComparison,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.to_string() == name)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UnusedGlobal => "unused-global",
+            Rule::Shadowing => "shadowing",
+            Rule::ConstantCondition => "constant-condition",
+            Rule::EmptyBlock => "empty-block",
+            Rule::BoolComparison => "bool-comparison",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// How each rule gets reported, None turns it off. Every rule warns by default.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    levels: HashMap<Rule, Option<Severity>>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            levels: Rule::ALL
+                .into_iter()
+                .map(|rule| (rule, Some(Severity::Warning)))
+                .collect(),
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn set(&mut self, rule: Rule, level: Option<Severity>) {
+        self.levels.insert(rule, level);
+    }
+
+    fn level(&self, rule: Rule) -> Option<Severity> {
+        self.levels.get(&rule).copied().flatten()
+    }
+}
+
+// Checks a program for code that runs but likely isn't what was meant. Scan,
+// parse and resolve errors are reported as usual and stop the checks.
+pub fn lint(source: &str, config: &LintConfig) -> Diagnostics {
+    let tokens = Scanner::new(source.to_string()).scan_tokens();
+
+    let mut diagnostics = Diagnostics::new();
+    for token in tokens.iter().filter(|t| t.r#type == TokenType::Error) {
+        diagnostics.error(token, &token.lexeme);
+    }
+
+    let tokens: Vec<Token> = tokens
+        .into_iter()
+        .filter(|t| t.r#type != TokenType::Error)
+        .collect();
+    let mut parser = Parser::new(tokens.clone());
+    let statements = parser.parse();
+    diagnostics.extend(parser.diagnostics);
+    if diagnostics.had_error() {
+        return diagnostics;
+    }
+
+    // Names are read where the resolver bound them, the same places the
+    // interpreter looks them up. Its warnings are left to the rules here.
+    let mut resolver = Resolver::new(&parser.exprs);
+    resolver.resolve(&statements);
+    if resolver.diagnostics.had_error() {
+        diagnostics.extend(resolver.diagnostics);
+        return diagnostics;
+    }
+
+    let mut linter = Linter {
+        config,
+        exprs: &parser.exprs,
+        braces: tokens
+            .into_iter()
+            .filter(|t| t.r#type == TokenType::LeftBrace)
+            .map(|t| (t.span.start, t))
+            .collect(),
+        scopes: vec![],
+        globals: vec![],
+        globals_read: HashSet::new(),
+        diagnostics,
+    };
+    linter.statements(&statements);
+    linter.end_globals();
+    linter.diagnostics
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+}
+
+// A declaration as seen by the linter, `used` once something reads it.
+struct Local {
+    name: Token,
+    used: bool,
+    kind: Kind,
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    exprs: &'a Exprs,
+    // Opening braces by offset, blocks only keep their span.
+    braces: HashMap<usize, Token>,
+    // Locals in declaration order, so the resolver's slots index them.
+    scopes: Vec<Vec<Local>>,
+    globals: Vec<Local>,
+    // Globals are looked up by name when the code runs, so reading one
+    // anywhere counts, even before it's declared.
+    globals_read: HashSet<String>,
+    diagnostics: Diagnostics,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: Rule, token: &Token, message: &str) {
+        if let Some(severity) = self.config.level(rule) {
+            let message = format!("{} [{}]", message, rule);
+            self.diagnostics.push(token, &message, severity);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(vec![]);
+    }
+
+    // Functions and classes are declarations too, but only variables are
+    // expected to be read in a local scope.
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+
+        for local in scope {
+            if local.kind == Kind::Variable && !local.used && !local.name.lexeme.starts_with('_') {
+                let message = format!("Local variable '{}' is never used.", local.name.lexeme);
+                self.report(Rule::UnusedVariable, &local.name, &message);
+            }
+        }
+    }
+
+    // Every global is there to be read, by the script or by a host through
+    // get_global, `_` names are left alone for the latter.
+    fn end_globals(&mut self) {
+        for global in std::mem::take(&mut self.globals) {
+            let name = &global.name.lexeme;
+            if self.globals_read.contains(name) || name.starts_with('_') {
+                continue;
+            }
+            let message = match global.kind {
+                Kind::Function => format!("Function '{}' is never used.", name),
+                Kind::Class => format!("Class '{}' is never used.", name),
+                _ => format!("Global variable '{}' is never used.", name),
+            };
+            self.report(Rule::UnusedGlobal, &global.name, &message);
+        }
+    }
+
+    fn declare(&mut self, name: &Token, kind: Kind) {
+        let shadows = self
+            .scopes
+            .iter()
+            .flatten()
+            .chain(&self.globals)
+            .any(|local| local.name.lexeme == name.lexeme);
+        let local = Local {
+            name: name.to_owned(),
+            used: false,
+            kind,
+        };
+
+        let Some(scope) = self.scopes.last_mut() else {
+            // Globals can be declared again, the first one gets reported.
+            if !shadows {
+                self.globals.push(local);
+            }
+            return;
+        };
+        scope.push(local);
+
+        if shadows {
+            let message = format!("'{}' shadows an outer declaration.", name.lexeme);
+            self.report(Rule::Shadowing, name, &message);
+        }
+    }
+
+    fn read(&mut self, variable: &Variable) {
+        let Some(slot) = variable.slot.get() else {
+            self.globals_read.insert(variable.name.lexeme.to_owned());
+            return;
+        };
+        let depth = self.scopes.len().checked_sub(slot.distance + 1);
+        if let Some(local) = depth.and_then(|depth| self.scopes[depth].get_mut(slot.index)) {
+            local.used = true;
+        }
+    }
+
+    fn constant_condition(&mut self, condition: ExprId, token: &Token) {
+        if let Some(value) = bool_literal(self.exprs, condition) {
+            let message = format!("Condition is always {}.", value);
+            self.report(Rule::ConstantCondition, token, &message);
+        }
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.stmt(statement);
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt], start: usize) {
+        if statements.is_empty() {
+            if let Some(brace) = self.braces.get(&start).cloned() {
+                self.report(Rule::EmptyBlock, &brace, "Empty block.");
+            }
+        }
+
+        self.begin_scope();
+        self.statements(statements);
+        self.end_scope();
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.begin_scope();
+        // Parameters are part of the signature, they may well go unused.
+        for param in &function.params {
+            self.declare(param, Kind::Parameter);
+        }
+        self.statements(&function.body);
+        self.end_scope();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(s) => self.expr(s.expression),
+            Stmt::Print(s) => self.expr(s.expression),
+            Stmt::Var(s) => {
+                if let Some(initializer) = &s.initializer {
+                    self.expr(*initializer);
+                }
+                self.declare(&s.name, Kind::Variable);
+            }
+            Stmt::Block(s) => self.block(&s.statements, s.span.start),
+            Stmt::If(s) => {
+                self.constant_condition(s.condition, &s.keyword);
+                self.expr(s.condition);
+                self.stmt(&s.then);
+                if let Some(r#else) = &s.r#else {
+                    self.stmt(r#else);
+                }
+            }
+            Stmt::While(s) => {
+                // `while (true)` is how endless loops are written, and `for`
+                // loops without a condition desugar to it.
+                if bool_literal(self.exprs, s.condition) == Some(false) {
+                    self.constant_condition(s.condition, &s.keyword);
+                }
+                self.expr(s.condition);
+                self.stmt(&s.body);
+                if let Some(increment) = &s.increment {
+                    self.expr(*increment);
+                }
+            }
+            Stmt::ForIn(s) => {
+                self.expr(s.iterable);
+                self.begin_scope();
+                self.declare(&s.name, Kind::Variable);
+                self.stmt(&s.body);
+                self.end_scope();
+            }
+            Stmt::Function(f) => {
+                self.declare(&f.name, Kind::Function);
+                self.function(f);
+            }
+            Stmt::Return(s) => {
+                if let Some(value) = &s.value {
+                    self.expr(*value);
+                }
+            }
+            Stmt::Class(s) => {
+                self.declare(&s.name, Kind::Class);
+                // Holds `this`, which the linter never reads through a slot.
+                self.begin_scope();
+                for method in &s.methods {
+                    self.function(method);
+                }
+                self.end_scope();
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => (),
+            Stmt::Match(s) => {
+                self.expr(s.subject);
+                for (pattern, body) in &s.arms {
+                    self.expr(*pattern);
+                    self.stmt(body);
+                }
+                if let Some(body) = &s.r#else {
+                    self.stmt(body);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: ExprId) {
+        let exprs = self.exprs;
+        stack::guard(|| match &exprs[expr] {
+            Expr::Assign(e) => self.expr(e.value),
+            Expr::Ternary(e) => {
+                self.constant_condition(e.condition, &e.operator);
+                self.expr(e.condition);
+                self.expr(e.then);
+                self.expr(e.r#else);
+            }
+            Expr::Logical(e) => {
+                self.expr(e.left);
+                self.expr(e.right);
+            }
+            Expr::Binary(e) => {
+                let equality = matches!(
+                    e.operator.r#type,
+                    TokenType::EqualEqual | TokenType::BangEqual
+                );
+                if equality
+                    && (bool_literal(exprs, e.left).is_some()
+                        || bool_literal(exprs, e.right).is_some())
+                {
+                    let message = format!(
+                        "Comparing with a boolean literal using '{}'.",
+                        e.operator.lexeme
+                    );
+                    self.report(Rule::BoolComparison, &e.operator, &message);
+                }
+                self.expr(e.left);
+                self.expr(e.right);
+            }
+            Expr::Unary(e) => self.expr(e.right),
+            Expr::Grouping(e) => self.expr(e.expression),
+            Expr::Literal(_) | Expr::This(_) => (),
+            Expr::Variable(e) => self.read(e),
+            Expr::Call(e) => {
+                self.expr(e.callee);
+                for argument in &e.arguments {
+                    self.expr(*argument);
+                }
+            }
+            Expr::Get(e) => self.expr(e.object),
+            Expr::Set(e) => {
+                self.expr(e.object);
+                self.expr(e.value);
+            }
+            Expr::List(e) => {
+                for element in &e.elements {
+                    self.expr(*element);
+                }
+            }
+            Expr::Map(e) => {
+                for (key, value) in &e.entries {
+                    self.expr(*key);
+                    self.expr(*value);
+                }
+            }
+            Expr::Index(e) => {
+                self.expr(e.object);
+                self.expr(e.index);
+            }
+            Expr::SetIndex(e) => {
+                self.expr(e.object);
+                self.expr(e.index);
+                self.expr(e.value);
+            }
+        })
+    }
+}
+
+fn bool_literal(exprs: &Exprs, mut expr: ExprId) -> Option<bool> {
+    loop {
+        match &exprs[expr] {
+            Expr::Literal(Literal {
+                value: Some(LiteralType::Bool(b)),
+                ..
+            }) => return Some(*b),
+            Expr::Grouping(g) => expr = g.expression,
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn messages(source: &str, config: &LintConfig) -> Vec<String> {
+        lint(source, config)
+            .messages()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn rules() {
+        let config = LintConfig::default();
+        let expect = |source: &str, message: &str| {
+            assert_eq!(messages(source, &config), vec![message], "{}", source);
+        };
+
+        expect(
+            "{ var a = 1; var _b = 2; }",
+            "Local variable 'a' is never used. [unused-variable]",
+        );
+        expect(
+            "var a = 1; print a; { var a = 2; print a; }",
+            "'a' shadows an outer declaration. [shadowing]",
+        );
+        expect(
+            "print true ? 1 : 2;",
+            "Condition is always true. [constant-condition]",
+        );
+        expect("if (nil) {}", "Empty block. [empty-block]");
+        expect(
+            "var a; print a == true;",
+            "Comparing with a boolean literal using '=='. [bool-comparison]",
+        );
+        expect(
+            "var a = 1; fun f() { var a = 2; return a; } print a + f();",
+            "'a' shadows an outer declaration. [shadowing]",
+        );
+        assert!(messages("while (true) { fun f(a) {} f(1); }", &config).is_empty());
+    }
+
+    #[test]
+    fn globals() {
+        let config = LintConfig::default();
+        assert_eq!(
+            messages("var a = 1; var _b = 2; fun f() {} class C {}", &config),
+            vec![
+                "Global variable 'a' is never used. [unused-global]",
+                "Function 'f' is never used. [unused-global]",
+                "Class 'C' is never used. [unused-global]",
+            ]
+        );
+        // Read before they're declared, by the time the call runs they are.
+        assert!(messages(
+            "fun show() { print count; } var count = 1; show();",
+            &config
+        )
+        .is_empty());
+        // The inner read is bound to the local, the global is left unread.
+        assert_eq!(
+            messages("var a = 1; { var a = 2; print a; }", &config),
+            vec![
+                "'a' shadows an outer declaration. [shadowing]",
+                "Global variable 'a' is never used. [unused-global]",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_errors() {
+        let config = LintConfig::default();
+        assert_eq!(
+            messages("{ var a = 1; var a = 2; print a; }", &config),
+            vec!["Already a variable with this name in this scope."]
+        );
+        assert!(lint("return 1;", &config).had_error());
+    }
+
+    #[test]
+    fn deep_expressions() {
+        let source = format!("print {};", vec!["1"; 100_000].join(" + "));
+        assert!(messages(&source, &LintConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn levels() {
+        let mut config = LintConfig::default();
+        config.set(Rule::EmptyBlock, None);
+        assert!(!lint("{}", &config).had_error());
+        assert!(messages("{}", &config).is_empty());
+
+        config.set(Rule::EmptyBlock, Some(Severity::Error));
+        assert!(lint("{}", &config).had_error());
+        assert_eq!(Rule::from_name("empty-block"), Some(Rule::EmptyBlock));
+    }
+}
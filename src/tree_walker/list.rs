@@ -0,0 +1,59 @@
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use super::syntax_tree::Value;
+
+// The elements of a list value. Lists can nest as deep as a script likes, so
+// dropping one takes apart the lists only it held one at a time instead of
+// recursing into them.
+#[derive(Debug, Default)]
+pub struct LoxList(Vec<Value>);
+
+impl From<Vec<Value>> for LoxList {
+    fn from(elements: Vec<Value>) -> Self {
+        LoxList(elements)
+    }
+}
+
+impl Deref for LoxList {
+    type Target = Vec<Value>;
+
+    fn deref(&self) -> &Vec<Value> {
+        &self.0
+    }
+}
+
+impl DerefMut for LoxList {
+    fn deref_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.0
+    }
+}
+
+impl Drop for LoxList {
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.0);
+        while let Some(value) = pending.pop() {
+            if let Value::List(list) = value {
+                if let Ok(list) = Rc::try_unwrap(list) {
+                    pending.append(&mut list.into_inner().0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[test]
+    fn deep_lists_drop() {
+        let mut list = Value::Nil;
+        for _ in 0..1_000_000 {
+            list = Value::List(Rc::new(RefCell::new(LoxList::from(vec![list]))));
+        }
+        drop(list);
+    }
+}
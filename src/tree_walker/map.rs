@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use super::gc::Marks;
+use super::stack;
+use super::syntax_tree::Value;
+
+// What a value hashes as when used for a map key. Only immutable values can
+// be keys, numbers compare by value so `0` and `-0` are the same key.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+enum MapKey {
+    Nil,
+    Boolean(bool),
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl MapKey {
+    fn from_value(value: &Value) -> Option<MapKey> {
+        match value {
+            Value::Nil => Some(MapKey::Nil),
+            Value::Boolean(b) => Some(MapKey::Boolean(*b)),
+            Value::Number(n) if n.is_nan() => None,
+            Value::Number(n) if *n == 0.0 => Some(MapKey::Number(0)),
+            Value::Number(n) => Some(MapKey::Number(n.to_bits())),
+            Value::String(s) => Some(MapKey::String(Rc::clone(s))),
+            _ => None,
+        }
+    }
+}
+
+// Entries keep their insertion order, so iterating and printing a map is
+// deterministic.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<MapKey, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        LoxMap {
+            entries: vec![],
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn is_valid_key(key: &Value) -> bool {
+        MapKey::from_value(key).is_some()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<Value> {
+        let i = self.index.get(&MapKey::from_value(key)?)?;
+        Some(self.entries[*i].1.to_owned())
+    }
+
+    pub fn has(&self, key: &Value) -> bool {
+        MapKey::from_value(key).is_some_and(|k| self.index.contains_key(&k))
+    }
+
+    // Gives back false, leaving the map untouched, when the key isn't valid.
+    pub fn set(&mut self, key: Value, value: Value) -> bool {
+        let Some(k) = MapKey::from_value(&key) else {
+            return false;
+        };
+
+        match self.index.get(&k) {
+            Some(i) => self.entries[*i].1 = value,
+            None => {
+                self.index.insert(k, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        true
+    }
+
+    pub fn trace(&self, marks: &mut Marks) {
+        for (key, value) in &self.entries {
+            marks.value(key);
+            marks.value(value);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.iter().map(|(k, _)| k.to_owned()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.entries.iter().map(|(_, v)| v.to_owned()).collect()
+    }
+}
+
+impl LoxMap {
+    // Shares what's being printed with the values in it, see `Value::write`.
+    pub fn write(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            key.write(f, printing)?;
+            write!(f, ": ")?;
+            stack::guard(|| value.write(f, printing))?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Display for LoxMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_equality() {
+        let mut map = LoxMap::new();
+        assert!(map.set(Value::Number(0.0), Value::Number(1.0)));
+        assert!(map.set(Value::Number(-0.0), Value::Number(2.0)));
+        assert!(map.set(Value::from("1"), Value::Nil));
+        assert!(map.set(Value::Number(1.0), Value::Boolean(true)));
+        assert!(map.set(Value::Nil, Value::Nil));
+        assert!(map.set(Value::Boolean(false), Value::Nil));
+
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(&Value::Number(0.0)).unwrap().to_string(), "2");
+        assert_eq!(map.get(&Value::Number(1.0)).unwrap().to_string(), "true");
+        assert!(map.has(&Value::Nil));
+        assert!(!map.has(&Value::Boolean(true)));
+        assert_eq!(
+            map.to_string(),
+            "{0: 2, 1: nil, 1: true, nil: nil, false: nil}"
+        );
+
+        assert!(!map.set(Value::Number(f64::NAN), Value::Nil));
+        assert!(!LoxMap::is_valid_key(&Value::List(Default::default())));
+    }
+}
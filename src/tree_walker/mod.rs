@@ -1,5 +1,24 @@
+pub mod callable;
+pub mod class;
+pub mod convert;
+pub mod debugger;
+pub mod dot;
+pub mod environment;
 pub mod errors;
+pub mod formatter;
+pub mod function;
+pub mod gc;
+pub mod incremental;
+pub mod interpreter;
+pub mod lint;
+pub mod list;
+pub mod map;
+pub mod optimizer;
 pub mod parser;
+pub mod resolver;
 pub mod scanner;
+pub mod stack;
 pub mod syntax_tree;
 pub mod tokens;
+pub mod tree;
+pub mod visitor;
@@ -0,0 +1,239 @@
+use std::rc::Rc;
+
+use super::interpreter::{Interpreter, Options};
+use super::stack;
+use super::syntax_tree::{Expr, ExprId, Exprs, Literal, Stmt, Value};
+use super::tokens::{LiteralType, Span, TokenType};
+
+// Folds the parts of expressions made only of literals before the program
+// runs. Folding goes through the interpreter itself so results match running
+// the code unfolded, and anything that fails is left alone to fail at runtime.
+pub struct Optimizer {
+    interpreter: Interpreter,
+    options: Options,
+}
+
+impl Optimizer {
+    pub fn new(options: Options) -> Self {
+        Optimizer {
+            interpreter: Interpreter::new(options),
+            options,
+        }
+    }
+
+    // Statements keep their shape, only the expressions they refer to change.
+    pub fn optimize(&mut self, statements: &[Stmt], exprs: &mut Exprs) {
+        for statement in statements {
+            self.stmt(exprs, statement);
+        }
+    }
+
+    fn stmt(&mut self, exprs: &mut Exprs, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(s) => self.expr(exprs, s.expression),
+            Stmt::Print(s) => self.expr(exprs, s.expression),
+            Stmt::Var(s) => self.opt_expr(exprs, s.initializer),
+            Stmt::Block(s) => self.optimize(&s.statements, exprs),
+            Stmt::If(s) => {
+                self.expr(exprs, s.condition);
+                self.stmt(exprs, &s.then);
+                self.opt_stmt(exprs, &s.r#else);
+            }
+            Stmt::While(s) => {
+                self.expr(exprs, s.condition);
+                self.stmt(exprs, &s.body);
+                self.opt_expr(exprs, s.increment);
+            }
+            Stmt::ForIn(s) => {
+                self.expr(exprs, s.iterable);
+                self.stmt(exprs, &s.body);
+            }
+            Stmt::Function(f) => self.optimize(&f.body, exprs),
+            Stmt::Return(s) => self.opt_expr(exprs, s.value),
+            Stmt::Class(s) => {
+                for method in &s.methods {
+                    self.optimize(&method.body, exprs);
+                }
+            }
+            Stmt::Match(s) => {
+                self.expr(exprs, s.subject);
+                for (pattern, body) in &s.arms {
+                    self.expr(exprs, *pattern);
+                    self.stmt(exprs, body);
+                }
+                self.opt_stmt(exprs, &s.r#else);
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => (),
+        }
+    }
+
+    fn opt_stmt(&mut self, exprs: &mut Exprs, stmt: &Option<Stmt>) {
+        if let Some(stmt) = stmt {
+            self.stmt(exprs, stmt);
+        }
+    }
+
+    fn opt_expr(&mut self, exprs: &mut Exprs, expr: Option<ExprId>) {
+        if let Some(expr) = expr {
+            self.expr(exprs, expr);
+        }
+    }
+
+    // Operands get folded first, a node whose operands all turned into
+    // literals may fold in turn. Folding replaces the node where it stands.
+    fn expr(&mut self, exprs: &mut Exprs, id: ExprId) {
+        for child in exprs[id].children() {
+            stack::guard(|| self.expr(exprs, child));
+        }
+
+        match &exprs[id] {
+            Expr::Ternary(e) => {
+                let Some(condition) = constant(&exprs[e.condition]) else {
+                    return;
+                };
+                let truthy = match condition {
+                    Value::Boolean(b) => b,
+                    _ if self.options.strict_truthiness => return,
+                    _ => condition.is_truthy(),
+                };
+                let branch = if truthy { e.then } else { e.r#else };
+                exprs[id] = take(exprs, branch);
+            }
+            Expr::Logical(e) => {
+                let Some(left) = constant(&exprs[e.left]) else {
+                    return;
+                };
+                let short_circuits = match e.operator.r#type {
+                    TokenType::Or => left.is_truthy(),
+                    TokenType::And => !left.is_truthy(),
+                    _ => !matches!(left, Value::Nil),
+                };
+                let operand = if short_circuits { e.left } else { e.right };
+                exprs[id] = take(exprs, operand);
+            }
+            Expr::Binary(e) => {
+                let left = constant(&exprs[e.left]).is_some();
+                // The comma operator only keeps its left side for side effects.
+                if e.operator.r#type == TokenType::Comma && left {
+                    let right = e.right;
+                    exprs[id] = take(exprs, right);
+                } else if left && constant(&exprs[e.right]).is_some() {
+                    self.fold(exprs, id);
+                }
+            }
+            Expr::Unary(e) if constant(&exprs[e.right]).is_some() => self.fold(exprs, id),
+            Expr::Grouping(e) if constant(&exprs[e.expression]).is_some() => self.fold(exprs, id),
+            _ => (),
+        }
+    }
+
+    // Replaces an expression over literals with its value, unless evaluating
+    // it fails.
+    fn fold(&mut self, exprs: &mut Exprs, id: ExprId) {
+        // The interpreter only runs shared arenas. Literals make no
+        // functions, so it lets go of this one once done.
+        let shared = Rc::new(std::mem::take(exprs));
+        let value = self
+            .interpreter
+            .using_exprs(&shared, |interpreter| interpreter.evaluate(id));
+        *exprs = Rc::into_inner(shared).expect("arena still shared after folding");
+
+        let literal = match value {
+            Ok(Value::Nil) => LiteralType::Nil,
+            Ok(Value::Boolean(b)) => LiteralType::Bool(b),
+            Ok(Value::Number(n)) => LiteralType::Number(n),
+            Ok(Value::String(s)) => LiteralType::String(s.to_string()),
+            _ => return,
+        };
+
+        exprs[id] = Expr::Literal(Literal::new(literal, exprs.span(id)));
+    }
+}
+
+fn constant(expr: &Expr) -> Option<Value> {
+    let Expr::Literal(literal) = expr else {
+        return None;
+    };
+
+    Some(match literal.value.to_owned().unwrap_or(LiteralType::Nil) {
+        LiteralType::Number(n) => Value::Number(n),
+        LiteralType::String(s) => Value::from(s),
+        LiteralType::Bool(b) => Value::Boolean(b),
+        LiteralType::Nil => Value::Nil,
+    })
+}
+
+// Moves the expression out, leaving a placeholder behind.
+fn take(exprs: &mut Exprs, id: ExprId) -> Expr {
+    let placeholder = Expr::Literal(Literal::new(LiteralType::Nil, Span::default()));
+    std::mem::replace(&mut exprs[id], placeholder)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::scanner::Scanner;
+    use crate::tree_walker::syntax_tree::ast_print;
+
+    use super::*;
+
+    fn fold_with(options: Options, source: &str) -> String {
+        let tokens = Scanner::new(format!("{};", source)).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        Optimizer::new(options).optimize(&statements, &mut parser.exprs);
+
+        let Some(Stmt::Expression(e)) = statements.into_iter().next() else {
+            panic!("not an expression: {}", source);
+        };
+        ast_print(&parser.exprs, e.expression)
+    }
+
+    fn fold(source: &str) -> String {
+        fold_with(Options::default(), source)
+    }
+
+    #[test]
+    fn folds_literals() {
+        assert_eq!(fold("1 + 2 * 3"), "7");
+        assert_eq!(fold("-(4)"), "-4");
+        assert_eq!(fold("\"a\" + \"b\""), "ab");
+        assert_eq!(fold("\"n: \" + (1 + 1)"), "n: 2");
+        assert_eq!(fold("!(1 < 2) == false"), "true");
+        assert_eq!(fold("(1, 2)"), "2");
+        assert_eq!(fold("6 & 3 | 8"), "10");
+        assert_eq!(fold("x + 1 * 2"), "(+ x 2)");
+    }
+
+    #[test]
+    fn folds_constant_conditions() {
+        assert_eq!(fold("true ? x : y"), "x");
+        assert_eq!(fold("nil ? x : y"), "y");
+        assert_eq!(fold("1 > 2 ? x : y"), "y");
+        assert_eq!(fold("nil ?? x"), "x");
+        assert_eq!(fold("false or x"), "x");
+        assert_eq!(fold("false and x"), "false");
+        assert_eq!(fold("x or true"), "(or x true)");
+    }
+
+    #[test]
+    fn leaves_errors_for_runtime() {
+        assert_eq!(fold("1 / 0"), "(/ 1 0)");
+        assert_eq!(fold("\"a\" - 1"), "(- a 1)");
+        assert_eq!(fold("-\"a\""), "(- a)");
+        assert_eq!(fold("1.5 | 1"), "(| 1.5 1)");
+
+        let strict = Options {
+            strict_truthiness: true,
+            ..Options::default()
+        };
+        assert_eq!(fold_with(strict, "1 ? x : y"), "(ternary 1 ? x : y)");
+        assert_eq!(fold_with(strict, "!nil"), "(! nil)");
+
+        let ieee = Options {
+            ieee_division: true,
+            ..Options::default()
+        };
+        assert_eq!(fold_with(ieee, "1 / 0"), "inf");
+    }
+}
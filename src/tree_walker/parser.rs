@@ -1,52 +1,595 @@
-use super::errors::error;
-use super::syntax_tree::{Binary, Expr, Grouping, Literal, Ternary, Unary};
-use super::tokens::{LiteralType, Token, TokenType};
+use std::fmt;
+use std::rc::Rc;
+
+use super::errors::Diagnostics;
+use super::stack;
+use super::syntax_tree::{
+    Assign, Binary, Block, Break, Call, Class, Continue, Expr, ExprId, Expression, Exprs, ForIn,
+    Function, Get, Grouping, If, Index, List, Literal, Logical, Map, Match, Print, Return, Set,
+    SetIndex, Stmt, Ternary, This, Unary, Var, Variable, While,
+};
+use super::tokens::{LiteralType, Span, Token, TokenType};
 
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    pub diagnostics: Diagnostics,
+    // The expressions of everything parsed, statements refer to them by id.
+    pub exprs: Exprs,
 }
 
 #[derive(Debug)]
 pub struct ParseError {
-    pub token: Token,
+    // Boxed to keep results carrying errors small.
+    pub token: Box<Token>,
     pub message: String,
 }
 
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, col {}] Error at '{}': {}",
+            self.token.line, self.token.column, self.token.lexeme, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            diagnostics: Diagnostics::new(),
+            exprs: Exprs::default(),
+        }
+    }
+
+    pub fn parse(&mut self) -> Vec<Stmt> {
+        let mut statements = vec![];
+
+        while !self.is_at_end() {
+            statements.extend(self.parse_declaration());
+        }
+
+        statements
+    }
+
+    // Parses the next top level declaration, None if it's bad. A bad one was
+    // already reported, the parser skips to the next one so its errors get
+    // reported too.
+    pub fn parse_declaration(&mut self) -> Option<Stmt> {
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(_) => {
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    // How many tokens were parsed so far.
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    // What the prompt runs: a lone expression without a trailing semicolon
+    // becomes a print of its value, anything else is parsed as statements.
+    // The value is also kept in `_`, so the next line can build on it.
+    pub fn parse_repl(&mut self) -> Vec<Stmt> {
+        let first = self.peek().clone();
+        if let Ok(expression) = self.expression() {
+            if self.is_at_end() {
+                let span = self.span_from(first.span);
+                let name = Token {
+                    r#type: TokenType::Identifier,
+                    lexeme: "_".to_string(),
+                    literal: None,
+                    length: 0,
+                    span,
+                    ..first
+                };
+                let variable = self
+                    .exprs
+                    .add(Expr::Variable(Variable::new(name.clone(), span)));
+                return vec![
+                    Stmt::Var(Box::new(Var::new(name, Some(expression), false, span))),
+                    Stmt::Print(Box::new(Print::new(variable, span))),
+                ];
+            }
+        }
+
+        self.current = 0;
+        self.diagnostics = Diagnostics::new();
+        self.exprs = Exprs::default();
+        self.parse()
     }
 
-    pub fn parse(&mut self) -> Option<Expr> {
-        if let Ok(expr) = self.expression() {
-            return Some(expr);
+    fn declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.r#match(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.r#match(&[TokenType::Fun]) {
+            let start = self.previous_span();
+            return Ok(Stmt::Function(self.function("function", start)?));
+        }
+        if self.r#match(&[TokenType::Var, TokenType::Const]) {
+            return self.var_declaration();
         }
 
-        None
+        self.statement()
     }
 
-    fn expression(&mut self) -> Result<Expr, ParseError> {
-        self.comma()
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous_span();
+        let name = self
+            .consume(TokenType::Identifier, "Expect class name.".to_string())?
+            .clone();
+        self.consume(
+            TokenType::LeftBrace,
+            "Expect '{' before class body.".to_string(),
+        )?;
+
+        let mut methods = vec![];
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let start = self.peek_span();
+            methods.push(self.function("method", start)?);
+        }
+
+        self.consume(
+            TokenType::RightBrace,
+            "Expect '}' after class body.".to_string(),
+        )?;
+
+        Ok(Stmt::Class(Box::new(Class::new(
+            name,
+            methods,
+            self.span_from(start),
+        ))))
     }
 
-    fn comma(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.ternary()?;
+    fn function(&mut self, kind: &str, start: Span) -> Result<Rc<Function>, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, format!("Expect {} name.", kind))?
+            .clone();
+        self.consume(
+            TokenType::LeftParen,
+            format!("Expect '(' after {} name.", kind),
+        )?;
+
+        let mut params = vec![];
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    self.error(
+                        self.peek().clone(),
+                        "Can't have more than 255 parameters.".to_string(),
+                    );
+                }
+
+                params.push(
+                    self.consume(TokenType::Identifier, "Expect parameter name.".to_string())?
+                        .clone(),
+                );
+
+                if !self.r#match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after parameters.".to_string(),
+        )?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            format!("Expect '{{' before {} body.", kind),
+        )?;
+        let body = self.block()?;
+
+        Ok(Rc::new(Function::new(
+            name,
+            params,
+            body,
+            self.span_from(start),
+        )))
+    }
+
+    // Both `var` and `const` declarations, constants must be initialized.
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous_span();
+        let constant = self.previous().r#type == TokenType::Const;
+        let name = self
+            .consume(TokenType::Identifier, "Expect variable name.".to_string())?
+            .clone();
+
+        let mut initializer = None;
+        if constant {
+            self.consume(
+                TokenType::Equal,
+                "Expect '=' after constant name.".to_string(),
+            )?;
+            initializer = Some(self.expression()?);
+        } else if self.r#match(&[TokenType::Equal]) {
+            initializer = Some(self.expression()?);
+        }
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.".to_string(),
+        )?;
+        Ok(Stmt::Var(Box::new(Var::new(
+            name,
+            initializer,
+            constant,
+            self.span_from(start),
+        ))))
+    }
 
-        while self.r#match(vec![TokenType::Comma]) {
-            let op = self.previous()?;
-            let right = self.ternary()?;
-            expr = Expr::Binary(Box::new(Binary::new(expr, op, right)));
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::Colon) {
+            return self.labeled_statement();
+        }
+        if self.r#match(&[TokenType::Break]) {
+            return self.break_statement();
+        }
+        if self.r#match(&[TokenType::Continue]) {
+            return self.continue_statement();
+        }
+        if self.r#match(&[TokenType::For]) {
+            return self.for_statement(None);
+        }
+        if self.r#match(&[TokenType::If]) {
+            return self.if_statement();
+        }
+        if self.r#match(&[TokenType::Match]) {
+            return self.match_statement();
+        }
+        if self.r#match(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+        if self.r#match(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.r#match(&[TokenType::While]) {
+            return self.while_statement(None);
+        }
+        if self.r#match(&[TokenType::LeftBrace]) {
+            let start = self.previous_span();
+            let statements = self.block()?;
+            return Ok(Stmt::Block(Box::new(Block::new(
+                statements,
+                self.span_from(start),
+            ))));
+        }
+
+        self.expression_statement()
+    }
+
+    fn labeled_statement(&mut self) -> Result<Stmt, ParseError> {
+        let label = self.advance().clone();
+        self.advance();
+
+        if self.r#match(&[TokenType::For]) {
+            return self.for_statement(Some(label));
+        }
+        if self.r#match(&[TokenType::While]) {
+            return self.while_statement(Some(label));
+        }
+
+        Err(self.error(self.peek().clone(), "Expect loop after label.".to_string()))
+    }
+
+    fn break_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        let mut label = None;
+        if self.r#match(&[TokenType::Identifier]) {
+            label = Some(self.previous().clone());
+        }
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after 'break'.".to_string(),
+        )?;
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Break(Box::new(Break::new(keyword, label, span))))
+    }
+
+    fn continue_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        let mut label = None;
+        if self.r#match(&[TokenType::Identifier]) {
+            label = Some(self.previous().clone());
+        }
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after 'continue'.".to_string(),
+        )?;
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Continue(Box::new(Continue::new(
+            keyword, label, span,
+        ))))
+    }
+
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let start = label.as_ref().map_or(keyword.span, |l| l.span);
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.".to_string())?;
+
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::In) {
+            let name = self.advance().clone();
+            self.advance();
+            let iterable = self.expression()?;
+            self.consume(
+                TokenType::RightParen,
+                "Expect ')' after for-in clause.".to_string(),
+            )?;
+            let body = self.statement()?;
+
+            let span = self.span_from(start);
+            return Ok(Stmt::ForIn(Box::new(ForIn::new(
+                keyword, name, iterable, body, label, span,
+            ))));
+        }
+
+        let initializer = if self.r#match(&[TokenType::Semicolon]) {
+            None
+        } else if self.r#match(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let mut condition = None;
+        let condition_start = self.peek_span().start;
+        if !self.check(TokenType::Semicolon) {
+            condition = Some(self.expression()?);
+        }
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after loop condition.".to_string(),
+        )?;
+
+        let mut increment = None;
+        if !self.check(TokenType::RightParen) {
+            increment = Some(self.expression()?);
+        }
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after for clauses.".to_string(),
+        )?;
+
+        let body = self.statement()?;
+
+        // Desugars into a while loop: { initializer; while (condition) body; }
+        // The increment stays on the loop so `continue` doesn't skip it.
+        let span = self.span_from(start);
+        let condition = condition.unwrap_or_else(|| {
+            self.exprs.add(Expr::Literal(Literal::new(
+                LiteralType::Bool(true),
+                Span::new(condition_start, condition_start),
+            )))
+        });
+        let mut body = Stmt::While(Box::new(While::new(
+            keyword, condition, body, increment, label, span,
+        )));
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(Box::new(Block::new(vec![initializer, body], span)));
+        }
+
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.".to_string())?;
+        let condition = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after if condition.".to_string(),
+        )?;
+
+        let then = self.statement()?;
+        let mut r#else = None;
+        if self.r#match(&[TokenType::Else]) {
+            r#else = Some(self.statement()?);
+        }
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::If(Box::new(If::new(
+            keyword, condition, then, r#else, span,
+        ))))
+    }
+
+    fn match_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous_span();
+        self.consume(
+            TokenType::LeftParen,
+            "Expect '(' after 'match'.".to_string(),
+        )?;
+        let subject = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after match subject.".to_string(),
+        )?;
+        self.consume(
+            TokenType::LeftBrace,
+            "Expect '{' before match arms.".to_string(),
+        )?;
+
+        let mut arms = vec![];
+        let mut r#else = None;
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            if self.r#match(&[TokenType::Else]) {
+                self.consume(TokenType::Arrow, "Expect '->' after 'else'.".to_string())?;
+                r#else = Some(self.statement()?);
+                break;
+            }
+
+            let pattern = self.assignment()?;
+            self.consume(
+                TokenType::Arrow,
+                "Expect '->' after match pattern.".to_string(),
+            )?;
+            arms.push((pattern, self.statement()?));
+        }
+
+        self.consume(
+            TokenType::RightBrace,
+            "Expect '}' after match arms.".to_string(),
+        )?;
+        Ok(Stmt::Match(Box::new(Match::new(
+            subject,
+            arms,
+            r#else,
+            self.span_from(start),
+        ))))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        let mut value = None;
+        if !self.check(TokenType::Semicolon) {
+            value = Some(self.expression()?);
+        }
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after return value.".to_string(),
+        )?;
+
+        let span = self.span_from(keyword.span);
+        Ok(Stmt::Return(Box::new(Return::new(keyword, value, span))))
+    }
+
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let start = label.as_ref().map_or(keyword.span, |l| l.span);
+        self.consume(
+            TokenType::LeftParen,
+            "Expect '(' after 'while'.".to_string(),
+        )?;
+        let condition = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            "Expect ')' after condition.".to_string(),
+        )?;
+        let body = self.statement()?;
+
+        Ok(Stmt::While(Box::new(While::new(
+            keyword,
+            condition,
+            body,
+            None,
+            label,
+            self.span_from(start),
+        ))))
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = vec![];
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.".to_string())?;
+        Ok(statements)
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous_span();
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.".to_string())?;
+        Ok(Stmt::Print(Box::new(Print::new(
+            value,
+            self.span_from(start),
+        ))))
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expr = self.expression()?;
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after expression.".to_string(),
+        )?;
+
+        let span = self.span_from(self.exprs.span(expr));
+        Ok(Stmt::Expression(Box::new(Expression::new(expr, span))))
+    }
+
+    // Every parenthesized expression comes back through here.
+    fn expression(&mut self) -> Result<ExprId, ParseError> {
+        stack::guard(|| self.comma())
+    }
+
+    fn comma(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.assignment()?;
+
+        while self.r#match(&[TokenType::Comma]) {
+            let op = self.previous().clone();
+            let right = self.assignment()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
         }
 
         Ok(expr)
     }
 
-    fn ternary(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.equality()?;
+    fn assignment(&mut self) -> Result<ExprId, ParseError> {
+        let expr = self.ternary()?;
+
+        if self.r#match(&[TokenType::Equal]) {
+            let equals = self.current - 1;
+            let value = self.assignment()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(value));
+
+            // The target turns into the assignment, keeping its place.
+            let assignment = match &self.exprs[expr] {
+                Expr::Variable(v) => Some(Expr::Assign(Assign::new(v.name.clone(), value, span))),
+                Expr::Get(g) => Some(Expr::Set(Set::new(g.object, g.name.clone(), value, span))),
+                Expr::Index(i) => Some(Expr::SetIndex(SetIndex::new(
+                    i.object,
+                    i.bracket.clone(),
+                    i.index,
+                    value,
+                    span,
+                ))),
+                _ => None,
+            };
+            if let Some(assignment) = assignment {
+                self.exprs[expr] = assignment;
+                return Ok(expr);
+            }
 
-        while self.r#match(vec![TokenType::Question]) {
+            // Reported but not thrown, the parser is not in a confused state.
+            self.error(
+                self.tokens[equals].clone(),
+                "Invalid assignment target.".to_string(),
+            );
+        }
+
+        Ok(expr)
+    }
+
+    fn ternary(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.coalesce()?;
+
+        while self.r#match(&[TokenType::Question]) {
+            let operator = self.previous().clone();
             let condition = expr;
             let then = self.ternary()?;
 
@@ -59,108 +602,424 @@ impl Parser {
 
             let r#else = self.ternary()?;
 
-            expr = Expr::Ternary(Box::new(Ternary::new(condition, then, r#else)))
+            let span = self.exprs.span(condition).to(self.exprs.span(r#else));
+            expr = self.exprs.add(Expr::Ternary(Ternary::new(
+                condition, operator, then, r#else, span,
+            )))
+        }
+
+        Ok(expr)
+    }
+
+    // `a ?? b` binds looser than `or`, so `a ?? b or c` reads as `a ?? (b or c)`.
+    fn coalesce(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.or()?;
+
+        while self.r#match(&[TokenType::QuestionQuestion]) {
+            let op = self.previous().clone();
+            let right = self.or()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Logical(Logical::new(expr, op, right, span)));
         }
 
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<Expr, ParseError> {
+    fn or(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.and()?;
+
+        while self.r#match(&[TokenType::Or]) {
+            let op = self.previous().clone();
+            let right = self.and()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Logical(Logical::new(expr, op, right, span)));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.equality()?;
+
+        while self.r#match(&[TokenType::And]) {
+            let op = self.previous().clone();
+            let right = self.equality()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Logical(Logical::new(expr, op, right, span)));
+        }
+
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.bit_or()?;
+
+        while self.r#match(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let op = self.previous().clone();
+            let right = self.bit_or()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
+        }
+
+        Ok(expr)
+    }
+
+    // Bitwise operators bind tighter than equality, so `x & 1 == 0` reads as
+    // `(x & 1) == 0`.
+    fn bit_or(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.r#match(&[TokenType::Pipe]) {
+            let op = self.previous().clone();
+            let right = self.bit_xor()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_xor(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.bit_and()?;
+
+        while self.r#match(&[TokenType::Caret]) {
+            let op = self.previous().clone();
+            let right = self.bit_and()?;
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
+        }
+
+        Ok(expr)
+    }
+
+    fn bit_and(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.comparison()?;
 
-        while self.r#match(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
-            let op = self.previous()?;
+        while self.r#match(&[TokenType::Ampersand]) {
+            let op = self.previous().clone();
             let right = self.comparison()?;
-            expr = Expr::Binary(Box::new(Binary::new(expr, op, right)));
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
         }
 
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, ParseError> {
+    fn comparison(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.term()?;
 
-        while self.r#match(vec![
+        while self.r#match(&[
             TokenType::Greater,
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
         ]) {
-            let op = self.previous()?;
+            let op = self.previous().clone();
             let right = self.term()?;
-            expr = Expr::Binary(Box::new(Binary::new(expr, op, right)));
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
         }
 
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, ParseError> {
+    fn term(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.factor()?;
 
-        while self.r#match(vec![TokenType::Minus, TokenType::Plus]) {
-            let op = self.previous()?;
+        while self.r#match(&[TokenType::Minus, TokenType::Plus]) {
+            let op = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::Binary(Box::new(Binary::new(expr, op, right)));
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
         }
 
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<Expr, ParseError> {
+    fn factor(&mut self) -> Result<ExprId, ParseError> {
         let mut expr = self.unary()?;
 
-        while self.r#match(vec![TokenType::Slash, TokenType::Star]) {
-            let op = self.previous()?;
+        while self.r#match(&[TokenType::Slash, TokenType::Star]) {
+            let op = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::Binary(Box::new(Binary::new(expr, op, right)));
+            let span = self.exprs.span(expr).to(self.exprs.span(right));
+            expr = self
+                .exprs
+                .add(Expr::Binary(Binary::new(expr, op, right, span)));
         }
 
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, ParseError> {
-        if self.r#match(vec![TokenType::Bang, TokenType::Minus]) {
-            let op = self.previous()?;
+    fn unary(&mut self) -> Result<ExprId, ParseError> {
+        if self.r#match(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
+            let op = self.previous().clone();
             let right = self.unary()?;
-            return Ok(Expr::Unary(Box::new(Unary::new(op, right))));
+            let span = op.span.to(self.exprs.span(right));
+            return Ok(self.exprs.add(Expr::Unary(Unary::new(op, right, span))));
         }
 
-        self.primary()
+        self.call()
     }
 
-    fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.r#match(vec![TokenType::False]) {
-            return Ok(Expr::Literal(Literal::new(LiteralType::Bool(false))));
+    fn call(&mut self) -> Result<ExprId, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.r#match(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.r#match(&[TokenType::Dot]) {
+                let name = self
+                    .consume(
+                        TokenType::Identifier,
+                        "Expect property name after '.'.".to_string(),
+                    )?
+                    .clone();
+                let span = self.exprs.span(expr).to(name.span);
+                expr = self.exprs.add(Expr::Get(Get::new(expr, name, span)));
+            } else if self.r#match(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self
+                    .consume(
+                        TokenType::RightBracket,
+                        "Expect ']' after index.".to_string(),
+                    )?
+                    .clone();
+                let span = self.exprs.span(expr).to(bracket.span);
+                expr = self
+                    .exprs
+                    .add(Expr::Index(Index::new(expr, bracket, index, span)));
+            } else {
+                break;
+            }
         }
-        if self.r#match(vec![TokenType::True]) {
-            return Ok(Expr::Literal(Literal::new(LiteralType::Bool(true))));
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: ExprId) -> Result<ExprId, ParseError> {
+        let mut arguments = vec![];
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    self.error(
+                        self.peek().clone(),
+                        "Can't have more than 255 arguments.".to_string(),
+                    );
+                }
+
+                // Arguments skip the comma operator, commas separate them instead.
+                arguments.push(self.assignment()?);
+
+                if !self.r#match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
-        if self.r#match(vec![TokenType::Nil]) {
-            return Ok(Expr::Literal(Literal::new(LiteralType::Nil)));
+
+        let paren = self
+            .consume(
+                TokenType::RightParen,
+                "Expect ')' after arguments.".to_string(),
+            )?
+            .clone();
+
+        let span = self.exprs.span(callee).to(paren.span);
+        Ok(self
+            .exprs
+            .add(Expr::Call(Call::new(callee, paren, arguments, span))))
+    }
+
+    fn primary(&mut self) -> Result<ExprId, ParseError> {
+        if self.r#match(&[TokenType::False]) {
+            return Ok(self.exprs.add(Expr::Literal(Literal::new(
+                LiteralType::Bool(false),
+                self.previous_span(),
+            ))));
+        }
+        if self.r#match(&[TokenType::True]) {
+            return Ok(self.exprs.add(Expr::Literal(Literal::new(
+                LiteralType::Bool(true),
+                self.previous_span(),
+            ))));
+        }
+        if self.r#match(&[TokenType::Nil]) {
+            return Ok(self.exprs.add(Expr::Literal(Literal::new(
+                LiteralType::Nil,
+                self.previous_span(),
+            ))));
+        }
+
+        if self.r#match(&[TokenType::Number, TokenType::String]) {
+            let prev = self.previous();
+            let literal = Literal::new(prev.literal.clone().unwrap(), prev.span);
+            return Ok(self.exprs.add(Expr::Literal(literal)));
+        }
+
+        if self.r#match(&[TokenType::This]) {
+            let keyword = self.previous().clone();
+            let span = keyword.span;
+            return Ok(self.exprs.add(Expr::This(This::new(keyword, span))));
         }
 
-        if self.r#match(vec![TokenType::Number, TokenType::String]) {
-            let prev = self.previous()?;
-            return Ok(Expr::Literal(Literal::new(prev.literal.unwrap())));
+        if self.r#match(&[TokenType::Identifier]) {
+            let name = self.previous().clone();
+            let span = name.span;
+            return Ok(self.exprs.add(Expr::Variable(Variable::new(name, span))));
         }
 
-        if self.r#match(vec![TokenType::LeftParen]) {
+        if self.r#match(&[TokenType::LeftParen]) {
+            let start = self.previous_span();
             let expr = self.expression()?;
-            let _ = self.consume(
+            self.consume(
                 TokenType::RightParen,
                 "Expect ')' after expression".to_string(),
             )?;
-            return Ok(Expr::Grouping(Box::new(Grouping::new(expr))));
+            return Ok(self
+                .exprs
+                .add(Expr::Grouping(Grouping::new(expr, self.span_from(start)))));
+        }
+
+        if self.r#match(&[TokenType::LeftBracket]) {
+            let start = self.previous_span();
+            let mut elements = vec![];
+
+            if !self.check(TokenType::RightBracket) {
+                loop {
+                    // Like arguments, commas separate the elements.
+                    elements.push(self.assignment()?);
+
+                    if !self.r#match(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+
+            self.consume(
+                TokenType::RightBracket,
+                "Expect ']' after list elements.".to_string(),
+            )?;
+            return Ok(self
+                .exprs
+                .add(Expr::List(List::new(elements, self.span_from(start)))));
         }
 
-        let e = Parser::error(self.peek(), "expect expression".to_string());
+        // Statements starting with a brace are blocks, so maps only show up in
+        // the middle of expressions.
+        if self.r#match(&[TokenType::LeftBrace]) {
+            let start = self.previous_span();
+            let mut entries = vec![];
+
+            if !self.check(TokenType::RightBrace) {
+                loop {
+                    let key = self.assignment()?;
+                    self.consume(TokenType::Colon, "Expect ':' after map key.".to_string())?;
+                    let value = self.assignment()?;
+                    entries.push((key, value));
+
+                    if !self.r#match(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+
+            let brace = self
+                .consume(
+                    TokenType::RightBrace,
+                    "Expect '}' after map entries.".to_string(),
+                )?
+                .clone();
+            return Ok(self
+                .exprs
+                .add(Expr::Map(Map::new(entries, brace, self.span_from(start)))));
+        }
+
+        // Binary operators with nothing on their left, like `+ 3`. The right
+        // operand is still parsed so the error doesn't cascade.
+        if self.r#match(&[TokenType::Comma]) {
+            return self.missing_left_operand(Parser::assignment);
+        }
+        if self.r#match(&[TokenType::QuestionQuestion]) {
+            return self.missing_left_operand(Parser::or);
+        }
+        if self.r#match(&[TokenType::Or]) {
+            return self.missing_left_operand(Parser::and);
+        }
+        if self.r#match(&[TokenType::And]) {
+            return self.missing_left_operand(Parser::equality);
+        }
+        if self.r#match(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            return self.missing_left_operand(Parser::bit_or);
+        }
+        if self.r#match(&[TokenType::Pipe]) {
+            return self.missing_left_operand(Parser::bit_xor);
+        }
+        if self.r#match(&[TokenType::Caret]) {
+            return self.missing_left_operand(Parser::bit_and);
+        }
+        if self.r#match(&[TokenType::Ampersand]) {
+            return self.missing_left_operand(Parser::comparison);
+        }
+        if self.r#match(&[
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::LessEqual,
+        ]) {
+            return self.missing_left_operand(Parser::term);
+        }
+        if self.r#match(&[TokenType::Plus]) {
+            return self.missing_left_operand(Parser::factor);
+        }
+        if self.r#match(&[TokenType::Slash, TokenType::Star]) {
+            return self.missing_left_operand(Parser::unary);
+        }
+
+        if self.check(TokenType::Error) {
+            let token = self.peek().clone();
+            let message = token.lexeme.to_owned();
+            return Err(self.error(token, message));
+        }
+
+        let e = self.error(self.peek().clone(), "expect expression".to_string());
+        Err(e)
+    }
+
+    fn missing_left_operand(
+        &mut self,
+        operand: fn(&mut Parser) -> Result<ExprId, ParseError>,
+    ) -> Result<ExprId, ParseError> {
+        let e = self.error(
+            self.previous().clone(),
+            "Binary operator without left-hand operand.".to_string(),
+        );
+        operand(self)?;
         Err(e)
     }
 
-    fn r#match(&mut self, types: Vec<TokenType>) -> bool {
-        for t in types {
+    fn r#match(&mut self, types: &[TokenType]) -> bool {
+        for &t in types {
             if self.check(t) {
-                let _ = self.advance();
+                self.advance();
                 return true;
             }
         }
@@ -168,331 +1027,937 @@ impl Parser {
         false
     }
 
-    fn synchronize(&mut self) -> Result<(), ParseError> {
-        let _ = self.advance();
+    fn synchronize(&mut self) {
+        self.advance();
 
         while !self.is_at_end() {
-            let current = self.previous()?;
-            if current.r#type == TokenType::Semicolon {
-                return Ok(());
+            if self.previous().r#type == TokenType::Semicolon {
+                return;
             }
 
             match self.peek().r#type {
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
+                | TokenType::Const
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return Ok(()),
+                | TokenType::Return => return,
                 _ => (),
             }
 
-            let _ = self.advance();
+            self.advance();
         }
-
-        Ok(())
     }
 
-    fn consume(&mut self, t: TokenType, message: String) -> Result<Token, ParseError> {
+    fn consume(&mut self, t: TokenType, message: String) -> Result<&Token, ParseError> {
         if self.check(t) {
-            return self.advance();
+            return Ok(self.advance());
         }
 
-        let e = Parser::error(self.peek(), message);
-        panic!("{:?}", e);
+        Err(self.error(self.peek().clone(), message))
     }
 
-    fn check(&mut self, t: TokenType) -> bool {
+    fn check(&self, t: TokenType) -> bool {
         if self.is_at_end() {
             return false;
         };
         self.peek().r#type == t
     }
 
-    fn advance(&mut self) -> Result<Token, ParseError> {
+    fn check_next(&mut self, t: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.r#type == t,
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1
         }
         self.previous()
     }
 
-    fn is_at_end(&mut self) -> bool {
+    pub fn is_at_end(&self) -> bool {
         matches!(self.peek().r#type, TokenType::Eof)
     }
 
-    fn peek(&mut self) -> Token {
-        self.tokens.get(self.current).unwrap().to_owned()
+    fn peek_span(&self) -> Span {
+        self.tokens[self.current].span
     }
 
-    fn previous(&self) -> Result<Token, ParseError> {
-        let token = self.tokens.get(self.current - 1);
-
-        if token.is_none() {
-            return Err(ParseError {
-                token: Token {
-                    r#type: TokenType::Nil,
-                    lexeme: "".to_string(),
-                    literal: None,
-                    line: 0,
-                },
-                message: "unexpected absense of token".to_string(),
-            });
+    fn previous_span(&self) -> Span {
+        match self.current.checked_sub(1) {
+            Some(i) => self.tokens[i].span,
+            None => Span::default(),
         }
+    }
+
+    // From the start of `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous_span())
+    }
 
-        Ok(token.unwrap().to_owned())
+    // Tokens are handed out by reference, only what ends up in the tree gets
+    // cloned.
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
     }
 
-    fn error(token: Token, message: String) -> ParseError {
-        error(&token, &message);
-        ParseError { token, message }
+    // Only called once something was consumed.
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current - 1]
+    }
+
+    fn error(&mut self, token: Token, message: String) -> ParseError {
+        self.diagnostics.error(&token, &message);
+        ParseError {
+            token: Box::new(token),
+            message,
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::tree_walker::scanner::Scanner;
+    use crate::tree_walker::syntax_tree::{ast_print, PrettyPrint};
 
     use super::*;
 
+    fn number(n: f64, start: usize, end: usize) -> Expr {
+        Expr::Literal(Literal::new(LiteralType::Number(n), Span::new(start, end)))
+    }
+
+    // The arena the parser should have built, nodes listed in the order it
+    // adds them: operands before the expressions using them.
+    fn arena(nodes: Vec<Expr>) -> Exprs {
+        let mut exprs = Exprs::default();
+        for node in nodes {
+            exprs.add(node);
+        }
+        exprs
+    }
+
     #[test]
     fn grouping_unary() {
-        let tokens = Scanner::new("(-1)".to_string()).scan_tokens();
+        let tokens = Scanner::new("(-1);".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 2, 3),
+            Expr::Unary(Unary::new(tokens[1].clone(), ExprId(0), Span::new(1, 3))),
+            Expr::Grouping(Grouping::new(ExprId(1), Span::new(0, 4))),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(2),
+                Span::new(0, 5)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
 
-        let mut parser = Parser::new(tokens);
-        let expected = Expr::Grouping(Box::new(Grouping::new(Expr::Unary(Box::new(Unary::new(
-            Token {
-                line: 1,
-                lexeme: "-".to_string(),
-                r#type: TokenType::Minus,
-                literal: None,
-            },
-            Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-        ))))));
+    #[test]
+    fn comma_separated_expressions() {
+        let tokens = Scanner::new("1+1,1-1,1==1;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 0, 1),
+            number(1.0, 2, 3),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[1].clone(),
+                ExprId(1),
+                Span::new(0, 3),
+            )),
+            number(1.0, 4, 5),
+            number(1.0, 6, 7),
+            Expr::Binary(Binary::new(
+                ExprId(3),
+                tokens[5].clone(),
+                ExprId(4),
+                Span::new(4, 7),
+            )),
+            Expr::Binary(Binary::new(
+                ExprId(2),
+                tokens[3].clone(),
+                ExprId(5),
+                Span::new(0, 7),
+            )),
+            number(1.0, 8, 9),
+            number(1.0, 11, 12),
+            Expr::Binary(Binary::new(
+                ExprId(7),
+                tokens[9].clone(),
+                ExprId(8),
+                Span::new(8, 12),
+            )),
+            Expr::Binary(Binary::new(
+                ExprId(6),
+                tokens[7].clone(),
+                ExprId(9),
+                Span::new(0, 12),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(10),
+                Span::new(0, 13)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
 
-        assert_eq!(parser.parse().unwrap(), expected);
+    #[test]
+    fn grouping_plus() {
+        let tokens = Scanner::new("(1+1);".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 1, 2),
+            number(1.0, 3, 4),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[2].clone(),
+                ExprId(1),
+                Span::new(1, 4),
+            )),
+            Expr::Grouping(Grouping::new(ExprId(2), Span::new(0, 5))),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(3),
+                Span::new(0, 6)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
     }
 
     #[test]
-    fn comma_separated_expressions() {
-        let tokens = Scanner::new("1+1,1-1,1==1".to_string()).scan_tokens();
+    fn ternary() {
+        let tokens = Scanner::new("1 == 1 ? 2 : 3;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 0, 1),
+            number(1.0, 5, 6),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[1].clone(),
+                ExprId(1),
+                Span::new(0, 6),
+            )),
+            number(2.0, 9, 10),
+            number(3.0, 13, 14),
+            Expr::Ternary(Ternary::new(
+                ExprId(2),
+                tokens[3].clone(),
+                ExprId(3),
+                ExprId(4),
+                Span::new(0, 14),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(5),
+                Span::new(0, 15)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn ternary_complex() {
+        let tokens = Scanner::new("5 * 20 == 99 ? 10 : 3 < 2 ? 1 : 0;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(5.0, 0, 1),
+            number(20.0, 4, 6),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[1].clone(),
+                ExprId(1),
+                Span::new(0, 6),
+            )),
+            number(99.0, 10, 12),
+            Expr::Binary(Binary::new(
+                ExprId(2),
+                tokens[3].clone(),
+                ExprId(3),
+                Span::new(0, 12),
+            )),
+            number(10.0, 15, 17),
+            number(3.0, 20, 21),
+            number(2.0, 24, 25),
+            Expr::Binary(Binary::new(
+                ExprId(6),
+                tokens[9].clone(),
+                ExprId(7),
+                Span::new(20, 25),
+            )),
+            number(1.0, 28, 29),
+            number(0.0, 32, 33),
+            Expr::Ternary(Ternary::new(
+                ExprId(8),
+                tokens[11].clone(),
+                ExprId(9),
+                ExprId(10),
+                Span::new(20, 33),
+            )),
+            Expr::Ternary(Ternary::new(
+                ExprId(4),
+                tokens[5].clone(),
+                ExprId(5),
+                ExprId(11),
+                Span::new(0, 33),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(12),
+                Span::new(0, 34)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn equality() {
+        let tokens = Scanner::new("1 == 1;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 0, 1),
+            number(1.0, 5, 6),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[1].clone(),
+                ExprId(1),
+                Span::new(0, 6),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(2),
+                Span::new(0, 7)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn complex_grouping() {
+        let tokens = Scanner::new("(1+10)/10+2 < 10*2;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 1, 2),
+            number(10.0, 3, 5),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[2].clone(),
+                ExprId(1),
+                Span::new(1, 5),
+            )),
+            Expr::Grouping(Grouping::new(ExprId(2), Span::new(0, 6))),
+            number(10.0, 7, 9),
+            Expr::Binary(Binary::new(
+                ExprId(3),
+                tokens[5].clone(),
+                ExprId(4),
+                Span::new(0, 9),
+            )),
+            number(2.0, 10, 11),
+            Expr::Binary(Binary::new(
+                ExprId(5),
+                tokens[7].clone(),
+                ExprId(6),
+                Span::new(0, 11),
+            )),
+            number(10.0, 14, 16),
+            number(2.0, 17, 18),
+            Expr::Binary(Binary::new(
+                ExprId(8),
+                tokens[11].clone(),
+                ExprId(9),
+                Span::new(14, 18),
+            )),
+            Expr::Binary(Binary::new(
+                ExprId(7),
+                tokens[9].clone(),
+                ExprId(10),
+                Span::new(0, 18),
+            )),
+        ]);
+
+        let actual = parser.parse();
+
+        assert_eq!(
+            actual,
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(11),
+                Span::new(0, 19)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn print_statement() {
+        let tokens = Scanner::new("print 1 + 1;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 6, 7),
+            number(1.0, 10, 11),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[2].clone(),
+                ExprId(1),
+                Span::new(6, 11),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Print(Box::new(Print::new(
+                ExprId(2),
+                Span::new(0, 12)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn var_declaration() {
+        let tokens = Scanner::new("var a = b;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = Stmt::Var(Box::new(Var::new(
+            tokens[1].clone(),
+            Some(ExprId(0)),
+            false,
+            Span::new(0, 10),
+        )));
+
+        assert_eq!(parser.parse(), vec![expected]);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![Expr::Variable(Variable::new(
+                tokens[3].clone(),
+                Span::new(8, 9),
+            ))])
+        );
+    }
+
+    #[test]
+    fn repl_input() {
+        let parse = |source: &str| {
+            let tokens = Scanner::new(source.to_string()).scan_tokens();
+            let mut parser = Parser::new(tokens);
+            let statements = parser.parse_repl();
+            (statements, parser.diagnostics.had_error())
+        };
+
+        let (statements, had_error) = parse("1 + 2");
+        assert!(matches!(&statements[..], [Stmt::Var(v), Stmt::Print(_)] if v.name.lexeme == "_"));
+        assert!(!had_error);
+
+        let (statements, had_error) = parse("a = 1; b = 2;");
+        assert!(matches!(
+            &statements[..],
+            [Stmt::Expression(_), Stmt::Expression(_)]
+        ));
+        assert!(!had_error);
 
+        let (statements, had_error) = parse("print 1 +;");
+        assert!(statements.is_empty() && had_error);
+    }
+
+    #[test]
+    fn const_declaration() {
+        let tokens = Scanner::new("const a = 1;".to_string()).scan_tokens();
+        let statements = Parser::new(tokens).parse();
+        assert!(matches!(&statements[..], [Stmt::Var(v)] if v.constant));
+
+        let tokens = Scanner::new("const a;".to_string()).scan_tokens();
         let mut parser = Parser::new(tokens);
-        let expected = Expr::Binary(Box::new(Binary::new(
-            Expr::Binary(Box::new(Binary::new(
-                Expr::Binary(Box::new(Binary::new(
-                    Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                    Token {
-                        line: 1,
-                        lexeme: "+".to_string(),
-                        r#type: TokenType::Plus,
-                        literal: None,
-                    },
-                    Expr::Literal(Literal::new(LiteralType::Number(1.0))),
+        parser.parse();
+        assert_eq!(
+            parser.diagnostics.messages(),
+            vec!["Expect '=' after constant name."]
+        );
+    }
+
+    #[test]
+    fn logical_precedence() {
+        let tokens = Scanner::new("a = true or false and nil;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        // The target was parsed first, the assignment took its place.
+        let expected = arena(vec![
+            Expr::Assign(Assign::new(tokens[0].clone(), ExprId(5), Span::new(0, 25))),
+            Expr::Literal(Literal::new(LiteralType::Bool(true), Span::new(4, 8))),
+            Expr::Literal(Literal::new(LiteralType::Bool(false), Span::new(12, 17))),
+            Expr::Literal(Literal::new(LiteralType::Nil, Span::new(22, 25))),
+            Expr::Logical(Logical::new(
+                ExprId(2),
+                tokens[5].clone(),
+                ExprId(3),
+                Span::new(12, 25),
+            )),
+            Expr::Logical(Logical::new(
+                ExprId(1),
+                tokens[3].clone(),
+                ExprId(4),
+                Span::new(4, 25),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(0),
+                Span::new(0, 26)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn for_desugars_to_while() {
+        let tokens = Scanner::new("for (;;) print 1;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = Stmt::While(Box::new(While::new(
+            tokens[0].clone(),
+            ExprId(1),
+            Stmt::Print(Box::new(Print::new(ExprId(0), Span::new(9, 17)))),
+            None,
+            None,
+            Span::new(0, 17),
+        )));
+
+        assert_eq!(parser.parse(), vec![expected]);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![
+                number(1.0, 15, 16),
+                Expr::Literal(Literal::new(LiteralType::Bool(true), Span::new(6, 6))),
+            ])
+        );
+    }
+
+    #[test]
+    fn for_with_all_clauses() {
+        let tokens =
+            Scanner::new("for (var i = 0; i < 1; i = i + 1) print i;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = Stmt::Block(Box::new(Block::new(
+            vec![
+                Stmt::Var(Box::new(Var::new(
+                    tokens[3].clone(),
+                    Some(ExprId(0)),
+                    false,
+                    Span::new(5, 15),
                 ))),
-                Token {
-                    line: 1,
-                    lexeme: ",".to_string(),
-                    r#type: TokenType::Comma,
-                    literal: None,
-                },
-                Expr::Binary(Box::new(Binary::new(
-                    Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                    Token {
-                        line: 1,
-                        lexeme: "-".to_string(),
-                        r#type: TokenType::Minus,
-                        literal: None,
-                    },
-                    Expr::Literal(Literal::new(LiteralType::Number(1.0))),
+                Stmt::While(Box::new(While::new(
+                    tokens[0].clone(),
+                    ExprId(3),
+                    Stmt::Print(Box::new(Print::new(ExprId(8), Span::new(34, 42)))),
+                    Some(ExprId(4)),
+                    None,
+                    Span::new(0, 42),
                 ))),
+            ],
+            Span::new(0, 42),
+        )));
+
+        assert_eq!(parser.parse(), vec![expected]);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![
+                number(0.0, 13, 14),
+                Expr::Variable(Variable::new(tokens[7].clone(), Span::new(16, 17))),
+                number(1.0, 20, 21),
+                Expr::Binary(Binary::new(
+                    ExprId(1),
+                    tokens[8].clone(),
+                    ExprId(2),
+                    Span::new(16, 21),
+                )),
+                Expr::Assign(Assign::new(
+                    tokens[11].clone(),
+                    ExprId(7),
+                    Span::new(23, 32)
+                )),
+                Expr::Variable(Variable::new(tokens[13].clone(), Span::new(27, 28))),
+                number(1.0, 31, 32),
+                Expr::Binary(Binary::new(
+                    ExprId(5),
+                    tokens[14].clone(),
+                    ExprId(6),
+                    Span::new(27, 32),
+                )),
+                Expr::Variable(Variable::new(tokens[18].clone(), Span::new(40, 41))),
+            ])
+        );
+    }
+
+    #[test]
+    fn class_declaration() {
+        let tokens = Scanner::new("class A { m() { return; } } A().m();".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = vec![
+            Stmt::Class(Box::new(Class::new(
+                tokens[1].clone(),
+                vec![Rc::new(Function::new(
+                    tokens[3].clone(),
+                    vec![],
+                    vec![Stmt::Return(Box::new(Return::new(
+                        tokens[7].clone(),
+                        None,
+                        Span::new(16, 23),
+                    )))],
+                    Span::new(10, 25),
+                ))],
+                Span::new(0, 27),
             ))),
-            Token {
-                line: 1,
-                lexeme: ",".to_string(),
-                r#type: TokenType::Comma,
-                literal: None,
-            },
-            Expr::Binary(Box::new(Binary::new(
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                Token {
-                    line: 1,
-                    lexeme: "==".to_string(),
-                    r#type: TokenType::EqualEqual,
-                    literal: None,
-                },
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
+            Stmt::Expression(Box::new(Expression::new(ExprId(3), Span::new(28, 36)))),
+        ];
+
+        assert_eq!(parser.parse(), expected);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![
+                Expr::Variable(Variable::new(tokens[11].clone(), Span::new(28, 29))),
+                Expr::Call(Call::new(
+                    ExprId(0),
+                    tokens[13].clone(),
+                    vec![],
+                    Span::new(28, 31),
+                )),
+                Expr::Get(Get::new(ExprId(1), tokens[15].clone(), Span::new(28, 33))),
+                Expr::Call(Call::new(
+                    ExprId(2),
+                    tokens[17].clone(),
+                    vec![],
+                    Span::new(28, 35),
+                )),
+            ])
+        );
+    }
+
+    #[test]
+    fn labeled_loop() {
+        let tokens = Scanner::new("outer: while (true) break outer;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = Stmt::While(Box::new(While::new(
+            tokens[2].clone(),
+            ExprId(0),
+            Stmt::Break(Box::new(Break::new(
+                tokens[6].clone(),
+                Some(tokens[7].clone()),
+                Span::new(20, 32),
             ))),
+            None,
+            Some(tokens[0].clone()),
+            Span::new(0, 32),
         )));
 
-        assert_eq!(parser.parse().unwrap(), expected);
+        assert_eq!(parser.parse(), vec![expected]);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![Expr::Literal(Literal::new(
+                LiteralType::Bool(true),
+                Span::new(14, 18)
+            ))])
+        );
     }
 
     #[test]
-    fn grouping_plus() {
-        let tokens = Scanner::new("(1+1)".to_string()).scan_tokens();
+    fn if_else() {
+        let tokens = Scanner::new("if (a) print 1; else print 2;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = Stmt::If(Box::new(If::new(
+            tokens[0].clone(),
+            ExprId(0),
+            Stmt::Print(Box::new(Print::new(ExprId(1), Span::new(7, 15)))),
+            Some(Stmt::Print(Box::new(Print::new(
+                ExprId(2),
+                Span::new(21, 29),
+            )))),
+            Span::new(0, 29),
+        )));
 
-        let mut parser = Parser::new(tokens);
-        let expected = Expr::Grouping(Box::new(Grouping::new(Expr::Binary(Box::new(
-            Binary::new(
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                Token {
-                    line: 1,
-                    lexeme: "+".to_string(),
-                    r#type: TokenType::Plus,
-                    literal: None,
-                },
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-            ),
-        )))));
+        assert_eq!(parser.parse(), vec![expected]);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![
+                Expr::Variable(Variable::new(tokens[2].clone(), Span::new(4, 5))),
+                number(1.0, 13, 14),
+                number(2.0, 27, 28),
+            ])
+        );
+    }
+
+    #[test]
+    fn match_statement() {
+        let tokens =
+            Scanner::new("match (1) { 1 -> print 1; else -> print 2; }".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = Stmt::Match(Box::new(Match::new(
+            ExprId(0),
+            vec![(
+                ExprId(1),
+                Stmt::Print(Box::new(Print::new(ExprId(2), Span::new(17, 25)))),
+            )],
+            Some(Stmt::Print(Box::new(Print::new(
+                ExprId(3),
+                Span::new(34, 42),
+            )))),
+            Span::new(0, 44),
+        )));
 
-        assert_eq!(parser.parse().unwrap(), expected);
+        assert_eq!(parser.parse(), vec![expected]);
+        assert_eq!(
+            parser.exprs,
+            arena(vec![
+                number(1.0, 7, 8),
+                number(1.0, 12, 13),
+                number(1.0, 23, 24),
+                number(2.0, 40, 41),
+            ])
+        );
     }
 
     #[test]
-    fn ternary() {
-        let tokens = Scanner::new("1 == 1 ? 2 : 3".to_string()).scan_tokens();
+    fn coalesce_precedence() {
+        let tokens = Scanner::new("a ?? b or c;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            Expr::Variable(Variable::new(tokens[0].clone(), Span::new(0, 1))),
+            Expr::Variable(Variable::new(tokens[2].clone(), Span::new(5, 6))),
+            Expr::Variable(Variable::new(tokens[4].clone(), Span::new(10, 11))),
+            Expr::Logical(Logical::new(
+                ExprId(1),
+                tokens[3].clone(),
+                ExprId(2),
+                Span::new(5, 11),
+            )),
+            Expr::Logical(Logical::new(
+                ExprId(0),
+                tokens[1].clone(),
+                ExprId(3),
+                Span::new(0, 11),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(4),
+                Span::new(0, 12)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn errors_do_not_panic() {
+        for source in ["print (1;", "var = 2;", "fun f( {}", "class { }", "1 = 2;"] {
+            let tokens = Scanner::new(source.to_string()).scan_tokens();
+            let mut parser = Parser::new(tokens);
+
+            parser.parse();
+            assert!(
+                parser.diagnostics.had_error(),
+                "expected a parse error for {}",
+                source
+            );
+        }
 
+        let tokens = Scanner::new("print 1;".to_string()).scan_tokens();
         let mut parser = Parser::new(tokens);
-        let expected = Expr::Ternary(Box::new(Ternary::new(
-            Expr::Binary(Box::new(Binary::new(
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                Token {
-                    line: 1,
-                    lexeme: "==".to_string(),
-                    r#type: TokenType::EqualEqual,
-                    literal: None,
-                },
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-            ))),
-            Expr::Literal(Literal::new(LiteralType::Number(2.0))),
-            Expr::Literal(Literal::new(LiteralType::Number(3.0))),
-        )));
+        parser.parse();
+        assert!(!parser.diagnostics.had_error());
+    }
 
-        assert_eq!(parser.parse().unwrap(), expected);
+    #[test]
+    fn recovers_after_bad_statements() {
+        let source = "print (1; var = 2; print 3; fun f( {} print 4;";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+
+        let statements = parser.parse();
+        assert_eq!(
+            parser.diagnostics.messages(),
+            vec![
+                "Expect ')' after expression",
+                "Expect variable name.",
+                "Expect parameter name.",
+            ]
+        );
+        let spans: Vec<Span> = statements.iter().map(|s| s.span()).collect();
+        assert_eq!(spans, vec![Span::new(19, 27), Span::new(38, 46)]);
     }
 
     #[test]
-    fn ternary_complex() {
-        let tokens = Scanner::new("5 * 20 == 99 ? 10 : 3 < 2 ? 1 : 0".to_string()).scan_tokens();
+    fn missing_left_operand() {
+        for source in ["+ 3;", "== 1 + 2;", "* -1;", "and true;"] {
+            let tokens = Scanner::new(source.to_string()).scan_tokens();
+            let mut parser = Parser::new(tokens);
+
+            let Err(e) = parser.expression() else {
+                panic!("expected a parse error for {}", source);
+            };
+            assert_eq!(e.message, "Binary operator without left-hand operand.");
+            // The right operand was consumed along with the operator.
+            assert!(parser.check(TokenType::Semicolon));
+        }
+    }
 
+    #[test]
+    fn lists_and_indexing() {
+        let tokens = Scanner::new("xs[0] = [1, [2]][1][0];".to_string()).scan_tokens();
         let mut parser = Parser::new(tokens);
-        let expected = Expr::Ternary(Box::new(Ternary::new(
-            Expr::Binary(Box::new(Binary::new(
-                Expr::Binary(Box::new(Binary::new(
-                    Expr::Literal(Literal::new(LiteralType::Number(5.0))),
-                    Token {
-                        line: 1,
-                        lexeme: "*".to_string(),
-                        r#type: TokenType::Star,
-                        literal: None,
-                    },
-                    Expr::Literal(Literal::new(LiteralType::Number(20.0))),
-                ))),
-                Token {
-                    line: 1,
-                    lexeme: "==".to_string(),
-                    r#type: TokenType::EqualEqual,
-                    literal: None,
-                },
-                Expr::Literal(Literal::new(LiteralType::Number(99.0))),
-            ))),
-            Expr::Literal(Literal::new(LiteralType::Number(10.0))),
-            Expr::Ternary(Box::new(Ternary::new(
-                Expr::Binary(Box::new(Binary::new(
-                    Expr::Literal(Literal::new(LiteralType::Number(3.0))),
-                    Token {
-                        line: 1,
-                        lexeme: "<".to_string(),
-                        r#type: TokenType::Less,
-                        literal: None,
-                    },
-                    Expr::Literal(Literal::new(LiteralType::Number(2.0))),
-                ))),
-                Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                Expr::Literal(Literal::new(LiteralType::Number(0.0))),
-            ))),
-        )));
 
-        assert_eq!(parser.parse().unwrap(), expected);
+        let Some(Stmt::Expression(e)) = parser.parse().into_iter().next() else {
+            panic!("not an expression statement");
+        };
+        assert_eq!(parser.exprs.span(e.expression), Span::new(0, 22));
+        assert_eq!(
+            ast_print(&parser.exprs, e.expression),
+            "(= ([] xs 0) ([] ([] (list 1 (list 2)) 1) 0))"
+        );
+
+        for source in ["[1, 2;", "xs[1;", "xs[];"] {
+            let tokens = Scanner::new(source.to_string()).scan_tokens();
+            let mut parser = Parser::new(tokens);
+
+            parser.parse();
+            assert!(
+                parser.diagnostics.had_error(),
+                "expected a parse error for {}",
+                source
+            );
+        }
     }
 
     #[test]
-    fn equality() {
-        let tokens = Scanner::new("1 == 1".to_string()).scan_tokens();
-
+    fn deep_expressions_print() {
+        let source = format!("print {};", vec!["1"; 100_000].join(" + "));
+        let tokens = Scanner::new(source).scan_tokens();
         let mut parser = Parser::new(tokens);
-        let expected = Expr::Binary(Box::new(Binary::new(
-            Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-            Token {
-                line: 1,
-                lexeme: "==".to_string(),
-                r#type: TokenType::EqualEqual,
-                literal: None,
-            },
-            Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-        )));
+        let statements = parser.parse();
 
-        assert_eq!(parser.parse().unwrap(), expected);
+        let printed = statements[0].pretty_print(&parser.exprs);
+        assert!(printed.starts_with("(print (+ (+ (+ "));
+        assert!(printed.ends_with(" 1) 1) 1))"));
+        assert_eq!(printed.matches('(').count(), 100_000);
     }
 
     #[test]
-    fn complex_grouping() {
-        let tokens = Scanner::new("(1+10)/10+2 < 10*2".to_string()).scan_tokens();
+    fn statements_print_indented() {
+        let source = "fun f(a) { if (a) return 1; else { print a; } }";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
 
+        assert_eq!(
+            statements[0].pretty_print(&parser.exprs),
+            "(fun f(a)\n  (if-else a\n    (return 1)\n    (block\n      (print a))))"
+        );
+    }
+
+    #[test]
+    fn statements_serialize_to_json() {
+        let tokens = Scanner::new("print nil;".to_string()).scan_tokens();
         let mut parser = Parser::new(tokens);
-        let expected = Expr::Binary(Box::new(Binary::new(
-            Expr::Binary(Box::new(Binary::new(
-                Expr::Binary(Box::new(Binary::new(
-                    Expr::Grouping(Box::new(Grouping::new(Expr::Binary(Box::new(
-                        Binary::new(
-                            Expr::Literal(Literal::new(LiteralType::Number(1.0))),
-                            Token {
-                                line: 1,
-                                lexeme: "+".to_string(),
-                                r#type: TokenType::Plus,
-                                literal: None,
-                            },
-                            Expr::Literal(Literal::new(LiteralType::Number(10.0))),
-                        ),
-                    ))))),
-                    Token {
-                        line: 1,
-                        lexeme: "/".to_string(),
-                        r#type: TokenType::Slash,
-                        literal: None,
-                    },
-                    Expr::Literal(Literal::new(LiteralType::Number(10.0))),
-                ))),
-                Token {
-                    line: 1,
-                    lexeme: "+".to_string(),
-                    r#type: TokenType::Plus,
-                    literal: None,
-                },
-                Expr::Literal(Literal::new(LiteralType::Number(2.0))),
-            ))),
-            Token {
-                line: 1,
-                lexeme: "<".to_string(),
-                r#type: TokenType::Less,
-                literal: None,
-            },
-            Expr::Binary(Box::new(Binary::new(
-                Expr::Literal(Literal::new(LiteralType::Number(10.0))),
-                Token {
-                    line: 1,
-                    lexeme: "*".to_string(),
-                    r#type: TokenType::Star,
-                    literal: None,
-                },
-                Expr::Literal(Literal::new(LiteralType::Number(2.0))),
-            ))),
-        )));
+        let statements = parser.parse();
+
+        let json = serde_json::to_value(&statements).unwrap();
+        assert_eq!(json[0]["Print"]["expression"], 0);
+        assert_eq!(json[0]["Print"]["span"]["end"], 10);
+        let json = serde_json::to_value(&parser.exprs).unwrap();
+        assert_eq!(json[0]["Literal"]["value"], "Nil");
+    }
+
+    #[test]
+    fn bitwise_precedence() {
+        let tokens = Scanner::new("1 | 2 & 3 == 0;".to_string()).scan_tokens();
+
+        let mut parser = Parser::new(tokens.clone());
+        let expected = arena(vec![
+            number(1.0, 0, 1),
+            number(2.0, 4, 5),
+            number(3.0, 8, 9),
+            Expr::Binary(Binary::new(
+                ExprId(1),
+                tokens[3].clone(),
+                ExprId(2),
+                Span::new(4, 9),
+            )),
+            Expr::Binary(Binary::new(
+                ExprId(0),
+                tokens[1].clone(),
+                ExprId(3),
+                Span::new(0, 9),
+            )),
+            number(0.0, 13, 14),
+            Expr::Binary(Binary::new(
+                ExprId(4),
+                tokens[5].clone(),
+                ExprId(5),
+                Span::new(0, 14),
+            )),
+        ]);
+
+        assert_eq!(
+            parser.parse(),
+            vec![Stmt::Expression(Box::new(Expression::new(
+                ExprId(6),
+                Span::new(0, 15)
+            )))]
+        );
+        assert_eq!(parser.exprs, expected);
+    }
+
+    #[test]
+    fn statement_spans_cover_source() {
+        let source = "fun f(a) {\n  return a;\n}\nprint f(1);";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
 
-        let actual = parser.parse().unwrap();
+        let statements = Parser::new(tokens).parse();
+        let text: Vec<&str> = statements
+            .iter()
+            .map(|s| &source[s.span().start..s.span().end])
+            .collect();
 
-        assert_eq!(actual, expected);
+        assert_eq!(text, vec!["fun f(a) {\n  return a;\n}", "print f(1);"]);
     }
 }
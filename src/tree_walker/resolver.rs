@@ -0,0 +1,656 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+
+use super::errors::Diagnostics;
+use super::stack;
+use super::syntax_tree::{
+    Assign, Binary, Block, Break, Call, Class, Continue, Expr, ExprId, Expression, Exprs, ForIn,
+    Function, Get, Grouping, If, Index, List, Literal, Logical, Map, Match, Print, Return, Set,
+    SetIndex, Slot, Stmt, Ternary, This, Unary, Var, Variable, While,
+};
+use super::tokens::{LiteralType, Token};
+
+#[derive(PartialEq, Clone, Copy)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum ClassType {
+    None,
+    Class,
+}
+
+// A local variable as seen by the resolver, `defined` turns true once its
+// initializer is done. Slots are handed out in declaration order, the same
+// order the interpreter defines the variables in.
+#[derive(Clone, Copy)]
+struct Binding {
+    defined: bool,
+    constant: bool,
+    slot: usize,
+}
+
+pub struct Resolver<'a> {
+    exprs: &'a Exprs,
+    scopes: Vec<HashMap<String, Binding>>,
+    // Globals aren't kept in any scope, only whether they are constants.
+    global_constants: HashSet<String>,
+    // Labels of the loops enclosing the current statement, unlabeled ones as None.
+    loops: Vec<Option<String>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    pub diagnostics: Diagnostics,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(exprs: &'a Exprs) -> Self {
+        Resolver {
+            exprs,
+            scopes: vec![],
+            global_constants: HashSet::new(),
+            loops: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            diagnostics: Diagnostics::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) {
+        let mut warned = false;
+        for (i, statement) in statements.iter().enumerate() {
+            statement.resolve(self);
+
+            // Only the first return is worth pointing at, the code after it
+            // still gets resolved.
+            if let Stmt::Return(r) = statement {
+                if i + 1 < statements.len() && !warned {
+                    self.warning(&r.keyword, "Unreachable code after 'return'.");
+                    warned = true;
+                }
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        self.declare_binding(name, false);
+    }
+
+    fn declare_binding(&mut self, name: &Token, constant: bool) {
+        let Some(scope) = self.scopes.last_mut() else {
+            if constant {
+                self.global_constants.insert(name.lexeme.to_owned());
+            } else {
+                self.global_constants.remove(&name.lexeme);
+            }
+            return;
+        };
+
+        if scope.contains_key(&name.lexeme) {
+            self.error(name, "Already a variable with this name in this scope.");
+            return;
+        }
+
+        let binding = Binding {
+            defined: false,
+            constant,
+            slot: scope.len(),
+        };
+        scope.insert(name.lexeme.to_owned(), binding);
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(binding) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            binding.defined = true;
+        }
+    }
+
+    fn is_constant(&self, name: &Token) -> bool {
+        for scope in self.scopes.iter().rev() {
+            if let Some(binding) = scope.get(&name.lexeme) {
+                return binding.constant;
+            }
+        }
+        self.global_constants.contains(&name.lexeme)
+    }
+
+    // Records on the node how many scopes away the variable lives and where
+    // in that scope, globals aren't in any scope.
+    fn resolve_local(&mut self, slot: &Cell<Option<Slot>>, name: &Token) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(distance, scope)| {
+                let binding = scope.get(&name.lexeme)?;
+                Some(Slot {
+                    distance,
+                    index: binding.slot,
+                })
+            });
+        slot.set(found);
+    }
+
+    fn resolve_function(&mut self, function: &Function, r#type: FunctionType) {
+        let enclosing = self.current_function;
+        self.current_function = r#type;
+        let enclosing_loops = std::mem::take(&mut self.loops);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve(&function.body);
+        self.end_scope();
+
+        self.loops = enclosing_loops;
+        self.current_function = enclosing;
+    }
+
+    fn resolve_jump(&mut self, keyword: &Token, label: &Option<Token>) {
+        if self.loops.is_empty() {
+            let message = format!("Can't use '{}' outside of a loop.", keyword.lexeme);
+            self.error(keyword, &message);
+            return;
+        }
+
+        if let Some(label) = label {
+            if !self.loops.contains(&Some(label.lexeme.to_owned())) {
+                let message = format!("Undefined label '{}'.", label.lexeme);
+                self.error(label, &message);
+            }
+        }
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.diagnostics.error(token, message);
+    }
+
+    fn warning(&mut self, token: &Token, message: &str) {
+        self.diagnostics.warning(token, message);
+    }
+}
+
+pub trait Resolve {
+    fn resolve(&self, resolver: &mut Resolver);
+}
+
+impl Resolve for Stmt {
+    fn resolve(&self, resolver: &mut Resolver) {
+        use Stmt::*;
+        match *self {
+            Expression(ref e) => e.resolve(resolver),
+            Print(ref p) => p.resolve(resolver),
+            Var(ref v) => v.resolve(resolver),
+            Block(ref b) => b.resolve(resolver),
+            If(ref i) => i.resolve(resolver),
+            While(ref w) => w.resolve(resolver),
+            Function(ref f) => f.resolve(resolver),
+            Return(ref r) => r.resolve(resolver),
+            Class(ref c) => c.resolve(resolver),
+            Break(ref b) => b.resolve(resolver),
+            Continue(ref c) => c.resolve(resolver),
+            Match(ref m) => m.resolve(resolver),
+            ForIn(ref f) => f.resolve(resolver),
+        }
+    }
+}
+
+impl Resolve for Expression {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.expression.resolve(resolver);
+    }
+}
+
+impl Resolve for Print {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.expression.resolve(resolver);
+    }
+}
+
+impl Resolve for Var {
+    fn resolve(&self, resolver: &mut Resolver) {
+        resolver.declare_binding(&self.name, self.constant);
+        if let Some(initializer) = &self.initializer {
+            initializer.resolve(resolver);
+        }
+        resolver.define(&self.name);
+    }
+}
+
+impl Resolve for Block {
+    fn resolve(&self, resolver: &mut Resolver) {
+        resolver.begin_scope();
+        resolver.resolve(&self.statements);
+        resolver.end_scope();
+    }
+}
+
+impl Resolve for If {
+    fn resolve(&self, resolver: &mut Resolver) {
+        if let Expr::Literal(Literal {
+            value: Some(LiteralType::Bool(condition)),
+            ..
+        }) = &resolver.exprs[self.condition]
+        {
+            if !condition {
+                resolver.warning(
+                    &self.keyword,
+                    "Condition is always false, branch never runs.",
+                );
+            } else if self.r#else.is_some() {
+                resolver.warning(&self.keyword, "Condition is always true, else never runs.");
+            }
+        }
+
+        self.condition.resolve(resolver);
+        self.then.resolve(resolver);
+        if let Some(r#else) = &self.r#else {
+            r#else.resolve(resolver);
+        }
+    }
+}
+
+impl Resolve for While {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.condition.resolve(resolver);
+
+        let label = self.label.as_ref().map(|l| l.lexeme.to_owned());
+        resolver.loops.push(label);
+        self.body.resolve(resolver);
+        resolver.loops.pop();
+
+        if let Some(increment) = &self.increment {
+            increment.resolve(resolver);
+        }
+    }
+}
+
+impl Resolve for ForIn {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.iterable.resolve(resolver);
+
+        resolver.begin_scope();
+        resolver.declare(&self.name);
+        resolver.define(&self.name);
+
+        let label = self.label.as_ref().map(|l| l.lexeme.to_owned());
+        resolver.loops.push(label);
+        self.body.resolve(resolver);
+        resolver.loops.pop();
+
+        resolver.end_scope();
+    }
+}
+
+impl Resolve for Break {
+    fn resolve(&self, resolver: &mut Resolver) {
+        resolver.resolve_jump(&self.keyword, &self.label);
+    }
+}
+
+impl Resolve for Continue {
+    fn resolve(&self, resolver: &mut Resolver) {
+        resolver.resolve_jump(&self.keyword, &self.label);
+    }
+}
+
+impl Resolve for Match {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.subject.resolve(resolver);
+
+        for (pattern, body) in &self.arms {
+            pattern.resolve(resolver);
+            body.resolve(resolver);
+        }
+
+        if let Some(body) = &self.r#else {
+            body.resolve(resolver);
+        }
+    }
+}
+
+impl Resolve for Function {
+    fn resolve(&self, resolver: &mut Resolver) {
+        resolver.declare(&self.name);
+        resolver.define(&self.name);
+        resolver.resolve_function(self, FunctionType::Function);
+    }
+}
+
+impl Resolve for Return {
+    fn resolve(&self, resolver: &mut Resolver) {
+        if resolver.current_function == FunctionType::None {
+            resolver.error(&self.keyword, "Can't return from top-level code.");
+        }
+
+        if let Some(value) = &self.value {
+            if resolver.current_function == FunctionType::Initializer {
+                resolver.error(&self.keyword, "Can't return a value from an initializer.");
+            }
+            value.resolve(resolver);
+
+            // Nothing runs in the function after a returned call, it can take
+            // the function's place.
+            let in_function = matches!(
+                resolver.current_function,
+                FunctionType::Function | FunctionType::Method
+            );
+            let is_call = matches!(resolver.exprs[*value], Expr::Call(_));
+            self.tail_call.set(in_function && is_call);
+        }
+    }
+}
+
+impl Resolve for Class {
+    fn resolve(&self, resolver: &mut Resolver) {
+        let enclosing = resolver.current_class;
+        resolver.current_class = ClassType::Class;
+
+        resolver.declare(&self.name);
+        resolver.define(&self.name);
+
+        resolver.begin_scope();
+        if let Some(scope) = resolver.scopes.last_mut() {
+            let binding = Binding {
+                defined: true,
+                constant: true,
+                slot: 0,
+            };
+            scope.insert("this".to_string(), binding);
+        }
+
+        for method in &self.methods {
+            let r#type = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            resolver.resolve_function(method, r#type);
+        }
+
+        resolver.end_scope();
+        resolver.current_class = enclosing;
+    }
+}
+
+impl Resolve for ExprId {
+    fn resolve(&self, resolver: &mut Resolver) {
+        let exprs = resolver.exprs;
+        stack::guard(|| exprs[*self].resolve(resolver));
+    }
+}
+
+impl Resolve for Expr {
+    fn resolve(&self, resolver: &mut Resolver) {
+        use Expr::*;
+        match *self {
+            Assign(ref a) => a.resolve(resolver),
+            Ternary(ref t) => t.resolve(resolver),
+            Logical(ref l) => l.resolve(resolver),
+            Binary(ref b) => b.resolve(resolver),
+            Unary(ref u) => u.resolve(resolver),
+            Grouping(ref g) => g.resolve(resolver),
+            Literal(ref l) => l.resolve(resolver),
+            Variable(ref v) => v.resolve(resolver),
+            Call(ref c) => c.resolve(resolver),
+            Get(ref g) => g.resolve(resolver),
+            Set(ref s) => s.resolve(resolver),
+            This(ref t) => t.resolve(resolver),
+            List(ref l) => l.resolve(resolver),
+            Map(ref m) => m.resolve(resolver),
+            Index(ref i) => i.resolve(resolver),
+            SetIndex(ref s) => s.resolve(resolver),
+        }
+    }
+}
+
+impl Resolve for Assign {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.value.resolve(resolver);
+        if resolver.is_constant(&self.name) {
+            let message = format!("Can't assign to constant '{}'.", self.name.lexeme);
+            resolver.error(&self.name, &message);
+        }
+        resolver.resolve_local(&self.slot, &self.name);
+    }
+}
+
+impl Resolve for Ternary {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.condition.resolve(resolver);
+        self.then.resolve(resolver);
+        self.r#else.resolve(resolver);
+    }
+}
+
+impl Resolve for Logical {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.left.resolve(resolver);
+        self.right.resolve(resolver);
+    }
+}
+
+impl Resolve for Binary {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.left.resolve(resolver);
+        self.right.resolve(resolver);
+    }
+}
+
+impl Resolve for Unary {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.right.resolve(resolver);
+    }
+}
+
+impl Resolve for Grouping {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.expression.resolve(resolver);
+    }
+}
+
+impl Resolve for Literal {
+    fn resolve(&self, _: &mut Resolver) {}
+}
+
+impl Resolve for Variable {
+    fn resolve(&self, resolver: &mut Resolver) {
+        let in_own_initializer = resolver
+            .scopes
+            .last()
+            .and_then(|scope| scope.get(&self.name.lexeme))
+            .is_some_and(|binding| !binding.defined);
+
+        if in_own_initializer {
+            resolver.error(
+                &self.name,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+
+        resolver.resolve_local(&self.slot, &self.name);
+    }
+}
+
+impl Resolve for Call {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.callee.resolve(resolver);
+        for argument in &self.arguments {
+            argument.resolve(resolver);
+        }
+    }
+}
+
+impl Resolve for Get {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.object.resolve(resolver);
+    }
+}
+
+impl Resolve for Set {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.value.resolve(resolver);
+        self.object.resolve(resolver);
+    }
+}
+
+impl Resolve for List {
+    fn resolve(&self, resolver: &mut Resolver) {
+        for element in &self.elements {
+            element.resolve(resolver);
+        }
+    }
+}
+
+impl Resolve for Map {
+    fn resolve(&self, resolver: &mut Resolver) {
+        for (key, value) in &self.entries {
+            key.resolve(resolver);
+            value.resolve(resolver);
+        }
+    }
+}
+
+impl Resolve for Index {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.object.resolve(resolver);
+        self.index.resolve(resolver);
+    }
+}
+
+impl Resolve for SetIndex {
+    fn resolve(&self, resolver: &mut Resolver) {
+        self.value.resolve(resolver);
+        self.object.resolve(resolver);
+        self.index.resolve(resolver);
+    }
+}
+
+impl Resolve for This {
+    fn resolve(&self, resolver: &mut Resolver) {
+        if resolver.current_class == ClassType::None {
+            resolver.error(&self.keyword, "Can't use 'this' outside of a class.");
+            return;
+        }
+
+        resolver.resolve_local(&self.slot, &self.keyword);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    // Resolves the program, telling whether any error was found.
+    fn resolve(source: &str) -> bool {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+
+        let mut resolver = Resolver::new(&parser.exprs);
+        resolver.resolve(&statements);
+        resolver.diagnostics.had_error()
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+
+        let mut resolver = Resolver::new(&parser.exprs);
+        resolver.resolve(&statements);
+        assert!(!resolver.diagnostics.had_error());
+        resolver
+            .diagnostics
+            .messages()
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn this_inside_method() {
+        assert!(!resolve("class A { m() { return this; } }"));
+    }
+
+    #[test]
+    fn this_outside_class() {
+        assert!(resolve("print this;"));
+        assert!(resolve("fun f() { return this; }"));
+    }
+
+    #[test]
+    fn loop_jumps() {
+        assert!(!resolve("a: while (true) { while (true) break a; }"));
+        assert!(resolve("break;"));
+        assert!(resolve("while (true) { fun f() { continue; } }"));
+        assert!(resolve("a: while (true) break b;"));
+    }
+
+    #[test]
+    fn dead_code_warnings() {
+        assert_eq!(
+            warnings("fun f() { return 1; print 2; return 3; print 4; }"),
+            vec!["Unreachable code after 'return'."]
+        );
+        assert!(warnings("fun f() { { return 1; } print 2; }").is_empty());
+        assert_eq!(
+            warnings("if (false) print 1; if (true) print 2; else print 3; if (true) print 4;"),
+            vec![
+                "Condition is always false, branch never runs.",
+                "Condition is always true, else never runs."
+            ]
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert!(!resolve("const a = 1; print a;"));
+        assert!(resolve("const a = 1; a = 2;"));
+        assert!(resolve("{ const a = 1; fun f() { a = 2; } }"));
+        assert!(!resolve("const a = 1; { var a = 1; a = 2; }"));
+        assert!(!resolve("const a = 1; var a = 2; a = 3;"));
+    }
+
+    #[test]
+    fn variable_slots() {
+        let source = "var g; { var a = 1; var b = 2; fun f(x) { return b + x + g; } }";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+        Resolver::new(&parser.exprs).resolve(&statements);
+
+        let slots: Vec<(&str, Option<Slot>)> = (0..parser.exprs.len())
+            .filter_map(|i| match &parser.exprs[ExprId(i)] {
+                Expr::Variable(v) => Some((v.name.lexeme.as_str(), v.slot.get())),
+                _ => None,
+            })
+            .collect();
+        let slot = |distance, index| Some(Slot { distance, index });
+        assert_eq!(
+            slots,
+            vec![("b", slot(1, 1)), ("x", slot(0, 0)), ("g", None)]
+        );
+    }
+}
@@ -1,109 +1,201 @@
 use std::collections::HashMap;
-use std::fmt;
 
-use super::errors::{report, Error};
-use super::tokens::{LiteralType, Token, TokenType};
+use super::tokens::{LiteralType, Span, SpannedToken, Token, TokenType};
+
+// Reserved words and the tokens they scan to.
+pub const KEYWORDS: [(&str, TokenType); 21] = [
+    ("and", TokenType::And),
+    ("break", TokenType::Break),
+    ("class", TokenType::Class),
+    ("const", TokenType::Const),
+    ("continue", TokenType::Continue),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("in", TokenType::In),
+    ("match", TokenType::Match),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
+// The tokens in the source and where they are, for editors and highlighters.
+// Scan errors come out as Error tokens over the offending source, there's no
+// Eof token.
+pub fn tokenize(source: &str) -> Vec<SpannedToken> {
+    spanned(Scanner::new(source.to_string()))
+}
+
+// Like `tokenize`, also handing out comments and whitespace as tokens. They
+// cover the whole source, back to back.
+pub fn tokenize_with_trivia(source: &str) -> Vec<SpannedToken> {
+    spanned(Scanner::new(source.to_string()).with_trivia())
+}
+
+fn spanned(scanner: Scanner) -> Vec<SpannedToken> {
+    scanner
+        .filter(|token| token.r#type != TokenType::Eof)
+        .map(|token| SpannedToken {
+            r#type: token.r#type,
+            span: token.span,
+            line: token.line,
+            column: token.column,
+        })
+        .collect()
+}
 
 pub struct Scanner {
     source: String,
-    tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: u64,
+    // Column of `current`, counted in chars. Kept up to date as the scanner
+    // moves rather than counted back from the line start, which made long
+    // lines quadratic.
+    column: u64,
+    start_line: u64,
+    start_column: u64,
     keywords: HashMap<String, TokenType>,
+    // Set once the Eof token was handed out, the iterator is done after it.
+    done: bool,
+    // Hand out comments as tokens instead of skipping them, for tools working
+    // on the source like the formatter.
+    comments: bool,
+    // Hand out whitespace and the shebang line too, every byte of the source
+    // ends up in a token.
+    trivia: bool,
+    // Put on every span, see `with_source`.
+    source_id: u32,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Scanner {
         Scanner {
             source,
-            tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
-            keywords: HashMap::from([
-                (String::from("and"), TokenType::And),
-                (String::from("class"), TokenType::Class),
-                (String::from("else"), TokenType::Else),
-                (String::from("false"), TokenType::False),
-                (String::from("for"), TokenType::For),
-                (String::from("fun"), TokenType::Fun),
-                (String::from("if"), TokenType::If),
-                (String::from("nil"), TokenType::Nil),
-                (String::from("or"), TokenType::Or),
-                (String::from("print"), TokenType::Print),
-                (String::from("return"), TokenType::Return),
-                (String::from("super"), TokenType::Super),
-                (String::from("this"), TokenType::This),
-                (String::from("true"), TokenType::True),
-                (String::from("var"), TokenType::Var),
-                (String::from("while"), TokenType::While),
-            ]),
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+            keywords: KEYWORDS
+                .into_iter()
+                .map(|(word, r#type)| (word.to_string(), r#type))
+                .collect(),
+            done: false,
+            comments: false,
+            trivia: false,
+            source_id: 0,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token();
-        }
+    // Numbers the source, so the spans of its tokens can be told apart from
+    // those of other sources, like earlier lines of the prompt.
+    pub fn with_source(mut self, id: u32) -> Self {
+        self.source_id = id;
+        self
+    }
 
-        self.tokens.extend([Token {
-            r#type: TokenType::Eof,
-            lexeme: String::new(),
-            line: self.line,
-            literal: None,
-        }]);
+    pub fn with_comments(mut self) -> Self {
+        self.comments = true;
+        self
+    }
+
+    pub fn with_trivia(mut self) -> Self {
+        self.comments = true;
+        self.trivia = true;
+        self
+    }
 
-        self.tokens.clone()
+    // Starts scanning at `offset` instead of the beginning, on `line`. The
+    // offset must be where a token could start, not inside one.
+    pub fn starting_at(mut self, offset: usize, line: u64) -> Self {
+        self.start = offset;
+        self.current = offset;
+        self.line = line;
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        self.column = self.source[line_start..offset].chars().count() as u64 + 1;
+        self
+    }
+
+    // Lexes the whole source at once, the Eof token included. The scanner is
+    // done after that, the tokens are moved out to the caller.
+    pub fn scan_tokens(self) -> Vec<Token> {
+        self.collect()
     }
 
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
-    fn scan_token(&mut self) {
+    fn scan_token(&mut self) -> Option<Token> {
         let c = self.advance();
 
         match c {
-            '(' => self.add_token(TokenType::LeftParen, None),
-            ')' => self.add_token(TokenType::RightParen, None),
-            '{' => self.add_token(TokenType::LeftBrace, None),
-            '}' => self.add_token(TokenType::RightBrace, None),
-            ',' => self.add_token(TokenType::Comma, None),
-            '.' => self.add_token(TokenType::Dot, None),
-            '-' => self.add_token(TokenType::Minus, None),
-            '+' => self.add_token(TokenType::Plus, None),
-            ';' => self.add_token(TokenType::Semicolon, None),
-            ':' => self.add_token(TokenType::Colon, None),
-            '*' => self.add_token(TokenType::Star, None),
-            '?' => self.add_token(TokenType::Question, None),
+            '(' => Some(self.make_token(TokenType::LeftParen, None)),
+            ')' => Some(self.make_token(TokenType::RightParen, None)),
+            '{' => Some(self.make_token(TokenType::LeftBrace, None)),
+            '}' => Some(self.make_token(TokenType::RightBrace, None)),
+            '[' => Some(self.make_token(TokenType::LeftBracket, None)),
+            ']' => Some(self.make_token(TokenType::RightBracket, None)),
+            ',' => Some(self.make_token(TokenType::Comma, None)),
+            '.' => Some(self.make_token(TokenType::Dot, None)),
+            '-' => {
+                if self.next_matches('>') {
+                    Some(self.make_token(TokenType::Arrow, None))
+                } else {
+                    Some(self.make_token(TokenType::Minus, None))
+                }
+            }
+            '+' => Some(self.make_token(TokenType::Plus, None)),
+            ';' => Some(self.make_token(TokenType::Semicolon, None)),
+            ':' => Some(self.make_token(TokenType::Colon, None)),
+            '*' => Some(self.make_token(TokenType::Star, None)),
+            '?' => {
+                if self.next_matches('?') {
+                    Some(self.make_token(TokenType::QuestionQuestion, None))
+                } else {
+                    Some(self.make_token(TokenType::Question, None))
+                }
+            }
+            '&' => Some(self.make_token(TokenType::Ampersand, None)),
+            '|' => Some(self.make_token(TokenType::Pipe, None)),
+            '^' => Some(self.make_token(TokenType::Caret, None)),
+            '~' => Some(self.make_token(TokenType::Tilde, None)),
             '!' => {
                 if self.next_matches('=') {
-                    self.add_token(TokenType::BangEqual, None);
+                    Some(self.make_token(TokenType::BangEqual, None))
                 } else {
-                    self.add_token(TokenType::Bang, None);
+                    Some(self.make_token(TokenType::Bang, None))
                 }
             }
             '=' => {
                 if self.next_matches('=') {
-                    self.add_token(TokenType::EqualEqual, None);
+                    Some(self.make_token(TokenType::EqualEqual, None))
                 } else {
-                    self.add_token(TokenType::Equal, None);
+                    Some(self.make_token(TokenType::Equal, None))
                 }
             }
             '>' => {
                 if self.next_matches('=') {
-                    self.add_token(TokenType::GreaterEqual, None);
+                    Some(self.make_token(TokenType::GreaterEqual, None))
                 } else {
-                    self.add_token(TokenType::Greater, None);
+                    Some(self.make_token(TokenType::Greater, None))
                 }
             }
             '<' => {
                 if self.next_matches('=') {
-                    self.add_token(TokenType::LessEqual, None);
+                    Some(self.make_token(TokenType::LessEqual, None))
                 } else {
-                    self.add_token(TokenType::Less, None);
+                    Some(self.make_token(TokenType::Less, None))
                 }
             }
             '/' => {
@@ -112,6 +204,7 @@ impl Scanner {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.comment()
                 } else if self.next_matches('*') {
                     // This is here to detect commented blocks.
                     while !(self.is_at_end() || (self.peek() == '*' && self.peek_next() == '/')) {
@@ -119,102 +212,137 @@ impl Scanner {
                     }
 
                     // Advance twice to skip "*/"
-                    self.advance();
-                    self.advance();
+                    if !self.is_at_end() {
+                        self.advance();
+                        self.advance();
+                    }
+                    self.comment()
                 } else {
-                    self.add_token(TokenType::Slash, None);
+                    Some(self.make_token(TokenType::Slash, None))
+                }
+            }
+            '"' => Some(self.string('"')),
+            // Raw strings: escapes are never processed and they may hold '"'.
+            '`' => Some(self.string('`')),
+            ' ' | '\r' | '\t' | '\n' => {
+                while matches!(self.peek(), ' ' | '\r' | '\t' | '\n') {
+                    self.advance();
                 }
+                self.trivia
+                    .then(|| self.make_token(TokenType::Whitespace, None))
             }
-            '"' => self.string(),
-            ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
             _ => {
                 if Scanner::is_digit(c) {
-                    self.number();
+                    Some(self.number())
                 } else if Scanner::is_alpha(c) {
-                    self.identifier();
+                    Some(self.identifier())
                 } else {
-                    report(Error {
-                        line: self.line,
-                        message: fmt::format(format_args!("Unexpected character: {}", c)),
-                        place: String::new(),
-                    })
+                    Some(self.error_token(format!("Unexpected character: {}", c)))
                 }
             }
         }
     }
 
+    // `start` and `current` are byte offsets, always kept on char boundaries.
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
-        self.current += 1;
+        let c = self.source[self.current..].chars().next().unwrap();
+        self.current += c.len_utf8();
+        self.column += 1;
+
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
+
         c
     }
 
-    fn add_token(&mut self, t: TokenType, l: Option<LiteralType>) {
-        self.tokens.extend([Token {
+    fn comment(&mut self) -> Option<Token> {
+        self.comments
+            .then(|| self.make_token(TokenType::Comment, None))
+    }
+
+    fn make_token(&mut self, t: TokenType, l: Option<LiteralType>) -> Token {
+        let lexeme = self.source[self.start..self.current].to_string();
+        Token {
             r#type: t,
-            lexeme: self.source[self.start..self.current].to_string(),
-            line: self.line,
+            length: lexeme.chars().count() as u64,
+            lexeme,
+            line: self.start_line,
             literal: l,
-        }]);
+            column: self.start_column,
+            span: Span::new(self.start, self.current).in_source(self.source_id),
+        }
+    }
+
+    // Lexical errors are handed to the caller as tokens, the lexeme holds the
+    // message and the span points at the offending source.
+    fn error_token(&mut self, message: String) -> Token {
+        Token {
+            r#type: TokenType::Error,
+            length: self.source[self.start..self.current].chars().count() as u64,
+            lexeme: message,
+            line: self.start_line,
+            literal: None,
+            column: self.start_column,
+            span: Span::new(self.start, self.current).in_source(self.source_id),
+        }
     }
 
     fn next_matches(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.peek() != expected {
             return false;
         }
 
-        self.current += 1;
+        self.current += expected.len_utf8();
+        self.column += 1;
         true
     }
 
     fn peek(&mut self) -> char {
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&mut self) -> char {
-        self.source.chars().nth(self.current + 1).unwrap_or('\0')
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
-    fn string(&mut self) {
-        while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
+    fn string(&mut self, delimiter: char) -> Token {
+        while !self.is_at_end() && self.peek() != delimiter {
             self.advance();
         }
 
         if self.is_at_end() {
-            report(Error {
-                line: self.line,
-                message: fmt::format(format_args!("Unterminated string")),
-                place: String::new(),
-            })
+            return self.error_token("Unterminated string".to_string());
         }
 
         self.advance();
 
         let value = self.source[self.start + 1..self.current - 1].to_string();
 
-        self.add_token(TokenType::String, Some(LiteralType::String(value)));
+        self.make_token(TokenType::String, Some(LiteralType::String(value)))
     }
 
     fn is_digit(c: char) -> bool {
         c.is_ascii_digit()
     }
 
-    fn number(&mut self) {
-        while !self.is_at_end() && Scanner::is_digit(self.peek()) {
+    fn is_digit_or_separator(c: char) -> bool {
+        Scanner::is_digit(c) || c == '_'
+    }
+
+    fn number(&mut self) -> Token {
+        while !self.is_at_end() && Scanner::is_digit_or_separator(self.peek()) {
             self.advance();
         }
 
         if !self.is_at_end() && self.peek() == '.' && Scanner::is_digit(self.peek_next()) {
             self.advance();
 
-            while !self.is_at_end() && Scanner::is_digit(self.peek()) {
+            while !self.is_at_end() && Scanner::is_digit_or_separator(self.peek()) {
                 self.advance();
             }
         }
@@ -224,19 +352,29 @@ impl Scanner {
         }
 
         let literal = self.source[self.start..self.current].to_string();
-        let value: f64 = literal.parse().unwrap();
-        self.add_token(TokenType::Number, Some(LiteralType::Number(value)));
+
+        // Separators are only allowed between two digits.
+        if literal.contains("__")
+            || literal.ends_with('_')
+            || literal.contains("_.")
+            || literal.contains("._")
+        {
+            return self.error_token(format!("Invalid digit separator in number: {}", literal));
+        }
+
+        let value: f64 = literal.replace('_', "").parse().unwrap();
+        self.make_token(TokenType::Number, Some(LiteralType::Number(value)))
     }
 
     fn is_alpha(c: char) -> bool {
-        c.is_ascii_alphabetic() || c == '_'
+        c.is_alphabetic() || c == '_'
     }
 
     fn is_alpha_numeric(c: char) -> bool {
         Scanner::is_digit(c) || Scanner::is_alpha(c)
     }
 
-    fn identifier(&mut self) {
+    fn identifier(&mut self) -> Token {
         while Scanner::is_alpha_numeric(self.peek()) {
             self.advance();
         }
@@ -248,7 +386,49 @@ impl Scanner {
             .unwrap_or(&TokenType::Identifier)
             .to_owned();
 
-        self.add_token(tkn_type, None);
+        self.make_token(tkn_type, None)
+    }
+}
+
+// Tokens are lexed lazily, one per call, ending with a single Eof token.
+impl Iterator for Scanner {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.done {
+            return None;
+        }
+
+        // Skips a shebang line at the start of the file, like `#!/usr/bin/env jlox`.
+        if self.current == 0 && self.source.starts_with("#!") {
+            while !self.is_at_end() && self.peek() != '\n' {
+                self.advance();
+            }
+            if self.trivia {
+                return Some(self.make_token(TokenType::Comment, None));
+            }
+        }
+
+        while !self.is_at_end() {
+            self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
+
+            if let Some(token) = self.scan_token() {
+                return Some(token);
+            }
+        }
+
+        self.done = true;
+        Some(Token {
+            r#type: TokenType::Eof,
+            lexeme: String::new(),
+            line: self.line,
+            literal: None,
+            column: self.column,
+            length: 0,
+            span: Span::new(self.current, self.current).in_source(self.source_id),
+        })
     }
 }
 
@@ -260,7 +440,7 @@ mod test {
 
     #[test]
     fn grouping() {
-        let mut scanner = Scanner::new("(()){}".to_string());
+        let scanner = Scanner::new("(()){}".to_string());
 
         assert_eq!(
             scanner.scan_tokens(),
@@ -270,42 +450,63 @@ mod test {
                     lexeme: "(".to_string(),
                     line: 1,
                     literal: None,
+                    column: 1,
+                    length: 1,
+                    span: Span::new(0, 1),
                 },
                 Token {
                     r#type: TokenType::LeftParen,
                     lexeme: "(".to_string(),
                     line: 1,
                     literal: None,
+                    column: 2,
+                    length: 1,
+                    span: Span::new(1, 2),
                 },
                 Token {
                     r#type: TokenType::RightParen,
                     lexeme: ")".to_string(),
                     line: 1,
                     literal: None,
+                    column: 3,
+                    length: 1,
+                    span: Span::new(2, 3),
                 },
                 Token {
                     r#type: TokenType::RightParen,
                     lexeme: ")".to_string(),
                     line: 1,
                     literal: None,
+                    column: 4,
+                    length: 1,
+                    span: Span::new(3, 4),
                 },
                 Token {
                     r#type: TokenType::LeftBrace,
                     lexeme: "{".to_string(),
                     line: 1,
                     literal: None,
+                    column: 5,
+                    length: 1,
+                    span: Span::new(4, 5),
                 },
                 Token {
                     r#type: TokenType::RightBrace,
                     lexeme: "}".to_string(),
                     line: 1,
                     literal: None,
+                    column: 6,
+                    length: 1,
+                    span: Span::new(5, 6),
                 },
                 Token {
                     r#type: TokenType::Eof,
                     lexeme: "".to_string(),
                     line: 1,
                     literal: None,
+                    column: 7,
+                    length: 0,
+                    span: Span::new(6, 6),
                 },
             ]
         );
@@ -313,7 +514,7 @@ mod test {
 
     #[test]
     fn operators() {
-        let mut scanner = Scanner::new("!*+-/=<> <= ==".to_string());
+        let scanner = Scanner::new("!*+-/=<> <= ==".to_string());
 
         assert_eq!(
             scanner.scan_tokens(),
@@ -323,66 +524,99 @@ mod test {
                     lexeme: "!".to_string(),
                     line: 1,
                     literal: None,
+                    column: 1,
+                    length: 1,
+                    span: Span::new(0, 1),
                 },
                 Token {
                     r#type: TokenType::Star,
                     lexeme: "*".to_string(),
                     line: 1,
                     literal: None,
+                    column: 2,
+                    length: 1,
+                    span: Span::new(1, 2),
                 },
                 Token {
                     r#type: TokenType::Plus,
                     lexeme: "+".to_string(),
                     line: 1,
                     literal: None,
+                    column: 3,
+                    length: 1,
+                    span: Span::new(2, 3),
                 },
                 Token {
                     r#type: TokenType::Minus,
                     lexeme: "-".to_string(),
                     line: 1,
                     literal: None,
+                    column: 4,
+                    length: 1,
+                    span: Span::new(3, 4),
                 },
                 Token {
                     r#type: TokenType::Slash,
                     lexeme: "/".to_string(),
                     line: 1,
                     literal: None,
+                    column: 5,
+                    length: 1,
+                    span: Span::new(4, 5),
                 },
                 Token {
                     r#type: TokenType::Equal,
                     lexeme: "=".to_string(),
                     line: 1,
                     literal: None,
+                    column: 6,
+                    length: 1,
+                    span: Span::new(5, 6),
                 },
                 Token {
                     r#type: TokenType::Less,
                     lexeme: "<".to_string(),
                     line: 1,
                     literal: None,
+                    column: 7,
+                    length: 1,
+                    span: Span::new(6, 7),
                 },
                 Token {
                     r#type: TokenType::Greater,
                     lexeme: ">".to_string(),
                     line: 1,
                     literal: None,
+                    column: 8,
+                    length: 1,
+                    span: Span::new(7, 8),
                 },
                 Token {
                     r#type: TokenType::LessEqual,
                     lexeme: "<=".to_string(),
                     line: 1,
                     literal: None,
+                    column: 10,
+                    length: 2,
+                    span: Span::new(9, 11),
                 },
                 Token {
                     r#type: TokenType::EqualEqual,
                     lexeme: "==".to_string(),
                     line: 1,
                     literal: None,
+                    column: 13,
+                    length: 2,
+                    span: Span::new(12, 14),
                 },
                 Token {
                     r#type: TokenType::Eof,
                     lexeme: "".to_string(),
                     line: 1,
                     literal: None,
+                    column: 15,
+                    length: 0,
+                    span: Span::new(14, 14),
                 },
             ]
         );
@@ -390,7 +624,7 @@ mod test {
 
     #[test]
     fn string_literals() {
-        let mut scanner = Scanner::new("\"this is a string literal\"()".to_string());
+        let scanner = Scanner::new("\"this is a string literal\"()".to_string());
 
         assert_eq!(
             scanner.scan_tokens(),
@@ -400,24 +634,36 @@ mod test {
                     lexeme: "\"this is a string literal\"".to_string(),
                     line: 1,
                     literal: Some(LiteralType::String("this is a string literal".to_string())),
+                    column: 1,
+                    length: 26,
+                    span: Span::new(0, 26),
                 },
                 Token {
                     r#type: TokenType::LeftParen,
                     lexeme: "(".to_string(),
                     line: 1,
                     literal: None,
+                    column: 27,
+                    length: 1,
+                    span: Span::new(26, 27),
                 },
                 Token {
                     r#type: TokenType::RightParen,
                     lexeme: ")".to_string(),
                     line: 1,
                     literal: None,
+                    column: 28,
+                    length: 1,
+                    span: Span::new(27, 28),
                 },
                 Token {
                     r#type: TokenType::Eof,
                     lexeme: "".to_string(),
                     line: 1,
                     literal: None,
+                    column: 29,
+                    length: 0,
+                    span: Span::new(28, 28),
                 },
             ]
         );
@@ -425,7 +671,7 @@ mod test {
 
     #[test]
     fn number_literals() {
-        let mut scanner = Scanner::new("123.45".to_string());
+        let scanner = Scanner::new("123.45".to_string());
 
         assert_eq!(
             scanner.scan_tokens(),
@@ -435,12 +681,18 @@ mod test {
                     lexeme: "123.45".to_string(),
                     line: 1,
                     literal: Some(LiteralType::Number(123.45)),
+                    column: 1,
+                    length: 6,
+                    span: Span::new(0, 6),
                 },
                 Token {
                     r#type: TokenType::Eof,
                     lexeme: "".to_string(),
                     line: 1,
                     literal: None,
+                    column: 7,
+                    length: 0,
+                    span: Span::new(6, 6),
                 },
             ]
         );
@@ -448,7 +700,7 @@ mod test {
 
     #[test]
     fn ingnores_comment_blocks() {
-        let mut scanner = Scanner::new("/* \nthis \nis \na \ncomment */123.45".to_string());
+        let scanner = Scanner::new("/* \nthis \nis \na \ncomment */123.45".to_string());
 
         assert_eq!(
             scanner.scan_tokens(),
@@ -456,14 +708,20 @@ mod test {
                 Token {
                     r#type: TokenType::Number,
                     lexeme: "123.45".to_string(),
-                    line: 1,
+                    line: 5,
                     literal: Some(LiteralType::Number(123.45)),
+                    column: 11,
+                    length: 6,
+                    span: Span::new(27, 33),
                 },
                 Token {
                     r#type: TokenType::Eof,
                     lexeme: "".to_string(),
-                    line: 1,
+                    line: 5,
                     literal: None,
+                    column: 17,
+                    length: 0,
+                    span: Span::new(33, 33),
                 },
             ]
         );
@@ -471,7 +729,7 @@ mod test {
 
     #[test]
     fn question_mark() {
-        let mut scanner = Scanner::new("123.45 ?".to_string());
+        let scanner = Scanner::new("123.45 ?".to_string());
 
         assert_eq!(
             scanner.scan_tokens(),
@@ -481,20 +739,254 @@ mod test {
                     lexeme: "123.45".to_string(),
                     line: 1,
                     literal: Some(LiteralType::Number(123.45)),
+                    column: 1,
+                    length: 6,
+                    span: Span::new(0, 6),
                 },
                 Token {
                     r#type: TokenType::Question,
                     lexeme: "?".to_string(),
                     line: 1,
                     literal: None,
+                    column: 8,
+                    length: 1,
+                    span: Span::new(7, 8),
+                },
+                Token {
+                    r#type: TokenType::Eof,
+                    lexeme: "".to_string(),
+                    line: 1,
+                    literal: None,
+                    column: 9,
+                    length: 0,
+                    span: Span::new(8, 8),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn number_literals_with_separators() {
+        let scanner = Scanner::new("1_000_000.000_1".to_string());
+
+        assert_eq!(
+            scanner.scan_tokens(),
+            vec![
+                Token {
+                    r#type: TokenType::Number,
+                    lexeme: "1_000_000.000_1".to_string(),
+                    line: 1,
+                    literal: Some(LiteralType::Number(1000000.0001)),
+                    column: 1,
+                    length: 15,
+                    span: Span::new(0, 15),
+                },
+                Token {
+                    r#type: TokenType::Eof,
+                    lexeme: "".to_string(),
+                    line: 1,
+                    literal: None,
+                    column: 16,
+                    length: 0,
+                    span: Span::new(15, 15),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unicode() {
+        let scanner = Scanner::new("/* ✨ */ café \"héllo 世界\"".to_string());
+
+        assert_eq!(
+            scanner.scan_tokens(),
+            vec![
+                Token {
+                    r#type: TokenType::Identifier,
+                    lexeme: "café".to_string(),
+                    line: 1,
+                    literal: None,
+                    column: 9,
+                    length: 4,
+                    span: Span::new(10, 15),
+                },
+                Token {
+                    r#type: TokenType::String,
+                    lexeme: "\"héllo 世界\"".to_string(),
+                    line: 1,
+                    literal: Some(LiteralType::String("héllo 世界".to_string())),
+                    column: 14,
+                    length: 10,
+                    span: Span::new(16, 31),
+                },
+                Token {
+                    r#type: TokenType::Eof,
+                    lexeme: "".to_string(),
+                    line: 1,
+                    literal: None,
+                    column: 24,
+                    length: 0,
+                    span: Span::new(31, 31),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_string_literals() {
+        let scanner = Scanner::new("`say \"hi\"\n\\d+`\n;".to_string());
+
+        assert_eq!(
+            scanner.scan_tokens(),
+            vec![
+                Token {
+                    r#type: TokenType::String,
+                    lexeme: "`say \"hi\"\n\\d+`".to_string(),
+                    line: 1,
+                    literal: Some(LiteralType::String("say \"hi\"\n\\d+".to_string())),
+                    column: 1,
+                    length: 14,
+                    span: Span::new(0, 14),
+                },
+                Token {
+                    r#type: TokenType::Semicolon,
+                    lexeme: ";".to_string(),
+                    line: 3,
+                    literal: None,
+                    column: 1,
+                    length: 1,
+                    span: Span::new(15, 16),
+                },
+                Token {
+                    r#type: TokenType::Eof,
+                    lexeme: "".to_string(),
+                    line: 3,
+                    literal: None,
+                    column: 2,
+                    length: 0,
+                    span: Span::new(16, 16),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn error_tokens() {
+        let scanner = Scanner::new("@ 1_".to_string());
+
+        assert_eq!(
+            scanner.scan_tokens(),
+            vec![
+                Token {
+                    r#type: TokenType::Error,
+                    lexeme: "Unexpected character: @".to_string(),
+                    line: 1,
+                    literal: None,
+                    column: 1,
+                    length: 1,
+                    span: Span::new(0, 1),
+                },
+                Token {
+                    r#type: TokenType::Error,
+                    lexeme: "Invalid digit separator in number: 1_".to_string(),
+                    line: 1,
+                    literal: None,
+                    column: 3,
+                    length: 2,
+                    span: Span::new(2, 4),
                 },
                 Token {
                     r#type: TokenType::Eof,
                     lexeme: "".to_string(),
                     line: 1,
                     literal: None,
+                    column: 5,
+                    length: 0,
+                    span: Span::new(4, 4),
                 },
             ]
         );
     }
+
+    #[test]
+    fn lexes_lazily() {
+        let mut scanner = Scanner::new("var a; // trailing".to_string());
+
+        assert_eq!(scanner.next().map(|t| t.r#type), Some(TokenType::Var));
+        assert_eq!(
+            scanner.by_ref().map(|t| t.r#type).collect::<Vec<_>>(),
+            vec![TokenType::Identifier, TokenType::Semicolon, TokenType::Eof]
+        );
+        assert_eq!(scanner.next(), None);
+    }
+
+    #[test]
+    fn skips_shebang() {
+        let scanner = Scanner::new("#!/usr/bin/env jlox\n;".to_string());
+
+        assert_eq!(
+            scanner.scan_tokens(),
+            vec![
+                Token {
+                    r#type: TokenType::Semicolon,
+                    lexeme: ";".to_string(),
+                    line: 2,
+                    literal: None,
+                    column: 1,
+                    length: 1,
+                    span: Span::new(20, 21),
+                },
+                Token {
+                    r#type: TokenType::Eof,
+                    lexeme: "".to_string(),
+                    line: 2,
+                    literal: None,
+                    column: 2,
+                    length: 0,
+                    span: Span::new(21, 21),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_with_positions() {
+        use super::{tokenize, tokenize_with_trivia};
+
+        let source = "#!jlox\nprint \"é\"; // hi\n@";
+        let kinds = |tokens: Vec<SpannedToken>| -> Vec<(TokenType, &str)> {
+            tokens
+                .into_iter()
+                .map(|t| (t.r#type, &source[t.span.start..t.span.end]))
+                .collect()
+        };
+
+        assert_eq!(
+            kinds(tokenize(source)),
+            vec![
+                (TokenType::Print, "print"),
+                (TokenType::String, "\"é\""),
+                (TokenType::Semicolon, ";"),
+                (TokenType::Error, "@"),
+            ]
+        );
+        assert_eq!(
+            kinds(tokenize_with_trivia(source)),
+            vec![
+                (TokenType::Comment, "#!jlox"),
+                (TokenType::Whitespace, "\n"),
+                (TokenType::Print, "print"),
+                (TokenType::Whitespace, " "),
+                (TokenType::String, "\"é\""),
+                (TokenType::Semicolon, ";"),
+                (TokenType::Whitespace, " "),
+                (TokenType::Comment, "// hi"),
+                (TokenType::Whitespace, "\n"),
+                (TokenType::Error, "@"),
+            ]
+        );
+
+        let semicolon = &tokenize(source)[2];
+        assert_eq!((semicolon.line, semicolon.column), (2, 10));
+    }
 }
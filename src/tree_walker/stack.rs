@@ -0,0 +1,23 @@
+// Expressions nest as deep as the source makes them, a chain of a hundred
+// thousand `+` is a tree that deep, and everything walking them recurses: the
+// parser, resolver, optimizer and interpreter, the printers and dumps, the
+// linter, the language server and the `walk_*` visitor helpers. Each level of
+// those walks goes through here, moving over to a fresh stack segment when
+// the current one is about to run out.
+
+// Room left that's enough for one level of any of the walks.
+#[cfg(not(target_arch = "wasm32"))]
+const RED_ZONE: usize = 128 * 1024;
+#[cfg(not(target_arch = "wasm32"))]
+const SEGMENT: usize = 4 * 1024 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn guard<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, SEGMENT, f)
+}
+
+// There's no switching stacks in wasm, deep code is bound by the host's.
+#[cfg(target_arch = "wasm32")]
+pub fn guard<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
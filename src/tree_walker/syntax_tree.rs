@@ -1,48 +1,497 @@
-use crate::tree_walker::tokens::{LiteralType, Token};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops;
+use std::rc::Rc;
 
+use serde::Serialize;
+
+use crate::tree_walker::tokens::{LiteralType, Span, Token};
+
+use super::callable::Callable;
+use super::class::{LoxClass, LoxInstance};
 use super::errors::RuntimeError;
-use super::tokens::TokenType;
+use super::gc::Marks;
+use super::list::LoxList;
+use super::map::LoxMap;
+use super::stack;
+use super::visitor::{ExprVisitor, StmtVisitor};
 
-#[derive(PartialEq, PartialOrd, Debug)]
+// Expressions live in an arena, `Exprs`, and refer to the ones under them by
+// their ExprId.
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
 pub enum Expr {
-    Ternary(Box<Ternary>),
-    Binary(Box<Binary>),
-    Unary(Box<Unary>),
-    Grouping(Box<Grouping>),
+    Assign(Assign),
+    Ternary(Ternary),
+    Logical(Logical),
+    Binary(Binary),
+    Unary(Unary),
+    Grouping(Grouping),
     Literal(Literal),
+    Variable(Variable),
+    Call(Call),
+    Get(Get),
+    Set(Set),
+    This(This),
+    List(List),
+    Map(Map),
+    Index(Index),
+    SetIndex(SetIndex),
 }
 
-pub fn ast_print(expr: Expr) -> String {
-    expr.pretty_print()
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub enum Stmt {
+    Expression(Box<Expression>),
+    Print(Box<Print>),
+    Var(Box<Var>),
+    Block(Box<Block>),
+    If(Box<If>),
+    While(Box<While>),
+    ForIn(Box<ForIn>),
+    Function(Rc<Function>),
+    Return(Box<Return>),
+    Class(Box<Class>),
+    Break(Box<Break>),
+    Continue(Box<Continue>),
+    Match(Box<Match>),
 }
 
-pub trait PrettyPrint {
-    fn pretty_print(&self) -> String;
+// Where an expression sits in its arena.
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy, Serialize)]
+pub struct ExprId(pub usize);
+
+// Where the resolver found a local variable: how many scopes out, and its
+// place among the variables of that scope.
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
+pub struct Slot {
+    pub distance: usize,
+    pub index: usize,
 }
 
-impl PrettyPrint for Expr {
-    fn pretty_print(&self) -> String {
+// Where a field was found the last time a property was looked up at some
+// site: the shape of the instance and the field's place in it.
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
+pub struct InlineCache {
+    pub shape: u64,
+    pub index: usize,
+}
+
+// Every expression of a program, in the order the parser made them.
+// Statements only hold ids into here, so the whole lot is dropped at once.
+#[derive(PartialEq, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Exprs {
+    nodes: Vec<Expr>,
+}
+
+impl Exprs {
+    pub fn add(&mut self, expr: Expr) -> ExprId {
+        self.nodes.push(expr);
+        ExprId(self.nodes.len() - 1)
+    }
+
+    pub fn span(&self, id: ExprId) -> Span {
+        self[id].span()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Expr> {
+        self.nodes.iter_mut()
+    }
+}
+
+impl ops::Index<ExprId> for Exprs {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.nodes[id.0]
+    }
+}
+
+impl ops::IndexMut<ExprId> for Exprs {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expr {
+        &mut self.nodes[id.0]
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        use Expr::*;
+        match *self {
+            Assign(ref e) => e.span,
+            Ternary(ref e) => e.span,
+            Logical(ref e) => e.span,
+            Binary(ref e) => e.span,
+            Unary(ref e) => e.span,
+            Grouping(ref e) => e.span,
+            Literal(ref e) => e.span,
+            Variable(ref e) => e.span,
+            Call(ref e) => e.span,
+            Get(ref e) => e.span,
+            Set(ref e) => e.span,
+            This(ref e) => e.span,
+            List(ref e) => e.span,
+            Map(ref e) => e.span,
+            Index(ref e) => e.span,
+            SetIndex(ref e) => e.span,
+        }
+    }
+
+    // The expressions right under this one, in source order.
+    pub fn children(&self) -> Vec<ExprId> {
         use Expr::*;
         match *self {
-            Ternary(ref e) => e.pretty_print(),
-            Binary(ref e) => e.pretty_print(),
-            Unary(ref e) => e.pretty_print(),
-            Grouping(ref e) => e.pretty_print(),
-            Literal(ref e) => e.pretty_print(),
+            Assign(ref e) => vec![e.value],
+            Ternary(ref e) => vec![e.condition, e.then, e.r#else],
+            Logical(ref e) => vec![e.left, e.right],
+            Binary(ref e) => vec![e.left, e.right],
+            Unary(ref e) => vec![e.right],
+            Grouping(ref e) => vec![e.expression],
+            Literal(_) | Variable(_) | This(_) => vec![],
+            Call(ref e) => [e.callee].into_iter().chain(e.arguments.clone()).collect(),
+            Get(ref e) => vec![e.object],
+            Set(ref e) => vec![e.object, e.value],
+            List(ref e) => e.elements.clone(),
+            Map(ref e) => e.entries.iter().flat_map(|&(k, v)| [k, v]).collect(),
+            Index(ref e) => vec![e.object, e.index],
+            SetIndex(ref e) => vec![e.object, e.index, e.value],
+        }
+    }
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        use Stmt::*;
+        match *self {
+            Expression(ref s) => s.span,
+            Print(ref s) => s.span,
+            Var(ref s) => s.span,
+            Block(ref s) => s.span,
+            If(ref s) => s.span,
+            While(ref s) => s.span,
+            ForIn(ref s) => s.span,
+            Function(ref s) => s.span,
+            Return(ref s) => s.span,
+            Class(ref s) => s.span,
+            Break(ref s) => s.span,
+            Continue(ref s) => s.span,
+            Match(ref s) => s.span,
+        }
+    }
+}
+
+pub fn ast_print(exprs: &Exprs, expr: ExprId) -> String {
+    exprs[expr].pretty_print(exprs)
+}
+
+// S-expressions, one per line. Nested statements go on lines of their own,
+// indented under the node holding them.
+pub trait PrettyPrint {
+    fn pretty_print(&self, exprs: &Exprs) -> String;
+}
+
+fn indent(printed: &str) -> String {
+    printed
+        .lines()
+        .map(|line| format!("\n  {}", line))
+        .collect()
+}
+
+fn label(label: &Option<Token>) -> String {
+    match label {
+        Some(label) => format!("{}: ", label.lexeme),
+        None => String::new(),
+    }
+}
+
+// Does the printing for PrettyPrint, a node at a time.
+struct Printer<'a> {
+    exprs: &'a Exprs,
+}
+
+impl Printer<'_> {
+    fn expr(&mut self, id: ExprId) -> String {
+        let exprs = self.exprs;
+        stack::guard(|| exprs[id].accept(self))
+    }
+
+    fn nested<'a>(&mut self, statements: impl IntoIterator<Item = &'a Stmt>) -> String {
+        statements
+            .into_iter()
+            .map(|statement| indent(&statement.accept(self)))
+            .collect()
+    }
+}
+
+impl PrettyPrint for Stmt {
+    fn pretty_print(&self, exprs: &Exprs) -> String {
+        self.accept(&mut Printer { exprs })
+    }
+}
+
+impl PrettyPrint for Expr {
+    fn pretty_print(&self, exprs: &Exprs) -> String {
+        self.accept(&mut Printer { exprs })
+    }
+}
+
+impl StmtVisitor<String> for Printer<'_> {
+    fn visit_expression(&mut self, stmt: &Expression) -> String {
+        format!("(; {})", self.expr(stmt.expression))
+    }
+
+    fn visit_print(&mut self, stmt: &Print) -> String {
+        format!("(print {})", self.expr(stmt.expression))
+    }
+
+    fn visit_var(&mut self, stmt: &Var) -> String {
+        let keyword = if stmt.constant { "const" } else { "var" };
+        match &stmt.initializer {
+            Some(initializer) => format!(
+                "({} {} = {})",
+                keyword,
+                stmt.name.lexeme,
+                self.expr(*initializer)
+            ),
+            None => format!("({} {})", keyword, stmt.name.lexeme),
+        }
+    }
+
+    fn visit_block(&mut self, stmt: &Block) -> String {
+        format!("(block{})", self.nested(&stmt.statements))
+    }
+
+    fn visit_if(&mut self, stmt: &If) -> String {
+        match &stmt.r#else {
+            Some(r#else) => format!(
+                "(if-else {}{}{})",
+                self.expr(stmt.condition),
+                self.nested([&stmt.then]),
+                self.nested([r#else]),
+            ),
+            None => format!(
+                "(if {}{})",
+                self.expr(stmt.condition),
+                self.nested([&stmt.then])
+            ),
+        }
+    }
+
+    fn visit_while(&mut self, stmt: &While) -> String {
+        let step = match &stmt.increment {
+            Some(increment) => indent(&format!("(step {})", self.expr(*increment))),
+            None => String::new(),
+        };
+        format!(
+            "({}while {}{}{})",
+            label(&stmt.label),
+            self.expr(stmt.condition),
+            self.nested([&stmt.body]),
+            step,
+        )
+    }
+
+    fn visit_for_in(&mut self, stmt: &ForIn) -> String {
+        format!(
+            "({}for {} in {}{})",
+            label(&stmt.label),
+            stmt.name.lexeme,
+            self.expr(stmt.iterable),
+            self.nested([&stmt.body]),
+        )
+    }
+
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> String {
+        let params: Vec<&str> = stmt.params.iter().map(|p| p.lexeme.as_str()).collect();
+        format!(
+            "(fun {}({}){})",
+            stmt.name.lexeme,
+            params.join(" "),
+            self.nested(&stmt.body)
+        )
+    }
+
+    fn visit_return(&mut self, stmt: &Return) -> String {
+        match &stmt.value {
+            Some(value) => format!("(return {})", self.expr(*value)),
+            None => "(return)".to_string(),
+        }
+    }
+
+    fn visit_class(&mut self, stmt: &Class) -> String {
+        let methods: String = stmt
+            .methods
+            .iter()
+            .map(|method| indent(&self.visit_function(method)))
+            .collect();
+        format!("(class {}{})", stmt.name.lexeme, methods)
+    }
+
+    fn visit_break(&mut self, stmt: &Break) -> String {
+        match &stmt.label {
+            Some(label) => format!("(break {})", label.lexeme),
+            None => "(break)".to_string(),
+        }
+    }
+
+    fn visit_continue(&mut self, stmt: &Continue) -> String {
+        match &stmt.label {
+            Some(label) => format!("(continue {})", label.lexeme),
+            None => "(continue)".to_string(),
+        }
+    }
+
+    fn visit_match(&mut self, stmt: &Match) -> String {
+        let mut arms: String = stmt
+            .arms
+            .iter()
+            .map(|(value, body)| indent(&format!("({}{})", self.expr(*value), self.nested([body]))))
+            .collect();
+        if let Some(r#else) = &stmt.r#else {
+            arms += &indent(&format!("(else{})", self.nested([r#else])));
+        }
+        format!("(match {}{})", self.expr(stmt.subject), arms)
+    }
+}
+
+impl ExprVisitor<String> for Printer<'_> {
+    fn visit_assign(&mut self, expr: &Assign) -> String {
+        format!("(= {} {})", expr.name.lexeme, self.expr(expr.value))
+    }
+
+    fn visit_ternary(&mut self, expr: &Ternary) -> String {
+        format!(
+            "(ternary {} ? {} : {})",
+            self.expr(expr.condition),
+            self.expr(expr.then),
+            self.expr(expr.r#else),
+        )
+    }
+
+    fn visit_logical(&mut self, expr: &Logical) -> String {
+        format!(
+            "({} {} {})",
+            expr.operator.lexeme,
+            self.expr(expr.left),
+            self.expr(expr.right),
+        )
+    }
+
+    fn visit_binary(&mut self, expr: &Binary) -> String {
+        format!(
+            "({} {} {})",
+            expr.operator.lexeme,
+            self.expr(expr.left),
+            self.expr(expr.right),
+        )
+    }
+
+    fn visit_unary(&mut self, expr: &Unary) -> String {
+        format!("({} {})", expr.operator.lexeme, self.expr(expr.right))
+    }
+
+    fn visit_grouping(&mut self, expr: &Grouping) -> String {
+        format!("(group {})", self.expr(expr.expression))
+    }
+
+    fn visit_literal(&mut self, expr: &Literal) -> String {
+        match &expr.value {
+            Some(v) => format!("{}", v),
+            None => "Nil".to_string(),
+        }
+    }
+
+    fn visit_variable(&mut self, expr: &Variable) -> String {
+        expr.name.lexeme.to_owned()
+    }
+
+    fn visit_call(&mut self, expr: &Call) -> String {
+        let mut printed = format!("(call {}", self.expr(expr.callee));
+        for argument in &expr.arguments {
+            printed += &format!(" {}", self.expr(*argument));
         }
+        printed + ")"
+    }
+
+    fn visit_get(&mut self, expr: &Get) -> String {
+        format!("(. {} {})", self.expr(expr.object), expr.name.lexeme)
+    }
+
+    fn visit_set(&mut self, expr: &Set) -> String {
+        format!(
+            "(= (. {} {}) {})",
+            self.expr(expr.object),
+            expr.name.lexeme,
+            self.expr(expr.value),
+        )
+    }
+
+    fn visit_this(&mut self, _: &This) -> String {
+        "this".to_string()
+    }
+
+    fn visit_list(&mut self, expr: &List) -> String {
+        let mut printed = "(list".to_string();
+        for element in &expr.elements {
+            printed += &format!(" {}", self.expr(*element));
+        }
+        printed + ")"
+    }
+
+    fn visit_map(&mut self, expr: &Map) -> String {
+        let mut printed = "(map".to_string();
+        for (key, value) in &expr.entries {
+            printed += &format!(" ({} {})", self.expr(*key), self.expr(*value));
+        }
+        printed + ")"
+    }
+
+    fn visit_index(&mut self, expr: &Index) -> String {
+        format!("([] {} {})", self.expr(expr.object), self.expr(expr.index))
+    }
+
+    fn visit_set_index(&mut self, expr: &SetIndex) -> String {
+        format!(
+            "(= ([] {} {}) {})",
+            self.expr(expr.object),
+            self.expr(expr.index),
+            self.expr(expr.value),
+        )
     }
 }
 
-#[derive(Debug)]
+// Values get copied around all the time, so they are kept to three words:
+// nothing is stored inline that is bigger than a pointer and its length.
+#[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Boolean(bool),
     Number(f64),
-    String(String),
+    // Shared, strings never change so copies only bump the count.
+    String(Rc<str>),
+    Callable(Rc<dyn Callable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    // Shared, every copy of a list value sees the changes made through others.
+    List(Rc<RefCell<LoxList>>),
+    Map(Rc<RefCell<LoxMap>>),
+    // An object the host handed to the script, opaque to it. Scripts can only
+    // pass it around, natives get it back with `Value::downcast`.
+    Foreign(Rc<dyn Any>),
 }
 
+#[cfg(target_pointer_width = "64")]
+const _: () = assert!(std::mem::size_of::<Value>() == 24);
+
 impl Value {
-    fn is_truthy(&self) -> bool {
+    pub fn is_truthy(&self) -> bool {
         match self {
             Value::Nil => false,
             Value::Boolean(b) => b.to_owned(),
@@ -50,278 +499,701 @@ impl Value {
         }
     }
 
-    fn variant_eq(a: &Value, b: &Value) -> bool {
+    // Bitwise operators only accept numbers without a fractional part that
+    // fit in an i64, rather than clamping the rest.
+    pub fn to_integer(n: f64, operator: &Token) -> Result<i64, RuntimeError> {
+        if n.fract() != 0.0 || !(i64::MIN as f64..i64::MAX as f64).contains(&n) {
+            return Err(RuntimeError::new(
+                operator.to_owned(),
+                "Operands must be integers.".to_string(),
+            ));
+        }
+
+        Ok(n as i64)
+    }
+
+    // Hands the objects this value refers to over to the collector.
+    pub fn trace(&self, marks: &mut Marks) {
+        match self {
+            Value::Callable(f) if marks.visit(f) => f.trace(marks),
+            Value::Class(c) if marks.visit(c) => c.trace(marks),
+            Value::Instance(i) if marks.visit(i) => i.borrow().trace(marks),
+            Value::List(l) if marks.visit(l) => {
+                for element in l.borrow().iter() {
+                    marks.value(element);
+                }
+            }
+            Value::Map(m) if marks.visit(m) => m.borrow().trace(marks),
+            _ => (),
+        }
+    }
+
+    pub fn variant_eq(a: &Value, b: &Value) -> bool {
         std::mem::discriminant(a) == std::mem::discriminant(b)
     }
+
+    pub fn is_equal(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(l), Value::Boolean(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::String(l), Value::String(r)) => Rc::ptr_eq(l, r) || l == r,
+            (Value::Callable(l), Value::Callable(r)) => Rc::ptr_eq(l, r),
+            (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+            (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+            (Value::List(l), Value::List(r)) => Rc::ptr_eq(l, r),
+            (Value::Map(l), Value::Map(r)) => Rc::ptr_eq(l, r),
+            (Value::Foreign(l), Value::Foreign(r)) => Rc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
 }
 
-pub fn interpret(expr: Expr) {
-    match expr.eval() {
-        Ok(value) => println!("{:?}", value),
-        Err(e) => e.report(),
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &mut vec![])
     }
 }
 
-pub trait Eval {
-    fn eval(&self) -> Result<Value, RuntimeError>;
+impl Value {
+    // `printing` holds the lists and maps the value is nested in, one found
+    // inside itself is shown as `[...]` or `{...}` instead of going around
+    // forever.
+    pub fn write(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            // Integral numbers already print without a fraction, only the
+            // non finite ones need spelling out the way the book does.
+            Value::Number(n) if n.is_nan() => write!(f, "NaN"),
+            Value::Number(n) if n.is_infinite() => {
+                write!(f, "{}Infinity", if *n < 0.0 { "-" } else { "" })
+            }
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Callable(fun) => write!(f, "{}", fun),
+            Value::Class(class) => write!(f, "{}", class),
+            Value::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Value::List(list) => {
+                let address = Rc::as_ptr(list) as *const ();
+                if printing.contains(&address) {
+                    return write!(f, "[...]");
+                }
+
+                printing.push(address);
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    stack::guard(|| element.write(f, printing))?;
+                }
+                printing.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                let address = Rc::as_ptr(map) as *const ();
+                if printing.contains(&address) {
+                    return write!(f, "{{...}}");
+                }
+
+                printing.push(address);
+                map.borrow().write(f, printing)?;
+                printing.pop();
+                Ok(())
+            }
+            Value::Foreign(_) => write!(f, "<foreign object>"),
+        }
+    }
 }
 
-impl Eval for Expr {
-    fn eval(&self) -> Result<Value, RuntimeError> {
-        use Expr::*;
-        match *self {
-            Ternary(ref t) => t.eval(),
-            Binary(ref b) => b.eval(),
-            Unary(ref u) => u.eval(),
-            Grouping(ref g) => g.eval(),
-            Literal(ref l) => l.eval(),
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Expression {
+    pub expression: ExprId,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(e: ExprId, span: Span) -> Self {
+        Expression {
+            expression: e,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Print {
+    pub expression: ExprId,
+    pub span: Span,
+}
+
+impl Print {
+    pub fn new(e: ExprId, span: Span) -> Self {
+        Print {
+            expression: e,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Var {
+    pub name: Token,
+    pub initializer: Option<ExprId>,
+    // Declared with `const`, it can't be assigned to after its initializer.
+    pub constant: bool,
+    pub span: Span,
+}
+
+impl Var {
+    pub fn new(name: Token, initializer: Option<ExprId>, constant: bool, span: Span) -> Self {
+        Var {
+            name,
+            initializer,
+            constant,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+
+impl Block {
+    pub fn new(statements: Vec<Stmt>, span: Span) -> Self {
+        Block { statements, span }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct If {
+    pub keyword: Token,
+    pub condition: ExprId,
+    pub then: Stmt,
+    pub r#else: Option<Stmt>,
+    pub span: Span,
+}
+
+impl If {
+    pub fn new(
+        keyword: Token,
+        condition: ExprId,
+        then: Stmt,
+        r#else: Option<Stmt>,
+        span: Span,
+    ) -> Self {
+        If {
+            keyword,
+            condition,
+            then,
+            r#else,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct While {
+    pub keyword: Token,
+    pub condition: ExprId,
+    pub body: Stmt,
+    pub increment: Option<ExprId>,
+    pub label: Option<Token>,
+    pub span: Span,
+}
+
+impl While {
+    pub fn new(
+        keyword: Token,
+        condition: ExprId,
+        body: Stmt,
+        increment: Option<ExprId>,
+        label: Option<Token>,
+        span: Span,
+    ) -> Self {
+        While {
+            keyword,
+            condition,
+            body,
+            increment,
+            label,
+            span,
+        }
+    }
+
+    pub fn is_target(&self, label: &Option<String>) -> bool {
+        is_loop_target(&self.label, label)
+    }
+}
+
+// Unlabeled jumps target the innermost loop, labeled ones the loop carrying
+// that label.
+fn is_loop_target(own: &Option<Token>, label: &Option<String>) -> bool {
+    match (label, own) {
+        (None, _) => true,
+        (Some(target), Some(own)) => *target == own.lexeme,
+        (Some(_), None) => false,
+    }
+}
+
+// Loops over the elements of a list, the keys of a map or the chars of a
+// string, binding each to a fresh variable.
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct ForIn {
+    pub keyword: Token,
+    pub name: Token,
+    pub iterable: ExprId,
+    pub body: Stmt,
+    pub label: Option<Token>,
+    pub span: Span,
+}
+
+impl ForIn {
+    pub fn new(
+        keyword: Token,
+        name: Token,
+        iterable: ExprId,
+        body: Stmt,
+        label: Option<Token>,
+        span: Span,
+    ) -> Self {
+        ForIn {
+            keyword,
+            name,
+            iterable,
+            body,
+            label,
+            span,
+        }
+    }
+
+    pub fn is_target(&self, label: &Option<String>) -> bool {
+        is_loop_target(&self.label, label)
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Break {
+    pub keyword: Token,
+    pub label: Option<Token>,
+    pub span: Span,
+}
+
+impl Break {
+    pub fn new(keyword: Token, label: Option<Token>, span: Span) -> Self {
+        Break {
+            keyword,
+            label,
+            span,
         }
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug)]
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Continue {
+    pub keyword: Token,
+    pub label: Option<Token>,
+    pub span: Span,
+}
+
+impl Continue {
+    pub fn new(keyword: Token, label: Option<Token>, span: Span) -> Self {
+        Continue {
+            keyword,
+            label,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+impl Function {
+    pub fn new(name: Token, params: Vec<Token>, body: Vec<Stmt>, span: Span) -> Self {
+        Function {
+            name,
+            params,
+            body,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Return {
+    pub keyword: Token,
+    pub value: Option<ExprId>,
+    pub span: Span,
+    // The value is a call the function hands its frame over to, set by the
+    // resolver.
+    #[serde(skip)]
+    pub tail_call: Cell<bool>,
+}
+
+impl Return {
+    pub fn new(keyword: Token, value: Option<ExprId>, span: Span) -> Self {
+        Return {
+            keyword,
+            value,
+            span,
+            tail_call: Cell::new(false),
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Class {
+    pub name: Token,
+    pub methods: Vec<Rc<Function>>,
+    pub span: Span,
+}
+
+impl Class {
+    pub fn new(name: Token, methods: Vec<Rc<Function>>, span: Span) -> Self {
+        Class {
+            name,
+            methods,
+            span,
+        }
+    }
+}
+
+// Arms are tried top to bottom and only the first one whose value equals the
+// subject runs, there is no fall-through. The else arm runs when none match,
+// and without one an unmatched subject is a no-op.
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Match {
+    pub subject: ExprId,
+    pub arms: Vec<(ExprId, Stmt)>,
+    pub r#else: Option<Stmt>,
+    pub span: Span,
+}
+
+impl Match {
+    pub fn new(
+        subject: ExprId,
+        arms: Vec<(ExprId, Stmt)>,
+        r#else: Option<Stmt>,
+        span: Span,
+    ) -> Self {
+        Match {
+            subject,
+            arms,
+            r#else,
+            span,
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Assign {
+    pub name: Token,
+    pub value: ExprId,
+    pub span: Span,
+    // Resolved like a variable's.
+    #[serde(skip)]
+    pub slot: Cell<Option<Slot>>,
+}
+
+impl Assign {
+    pub fn new(name: Token, value: ExprId, span: Span) -> Self {
+        Assign {
+            name,
+            value,
+            span,
+            slot: Cell::new(None),
+        }
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
 pub struct Ternary {
-    condition: Expr,
-    then: Expr,
-    r#else: Expr,
+    pub condition: ExprId,
+    pub operator: Token,
+    pub then: ExprId,
+    pub r#else: ExprId,
+    pub span: Span,
 }
 
 impl Ternary {
-    pub fn new(cond: Expr, th: Expr, el: Expr) -> Self {
+    pub fn new(cond: ExprId, op: Token, th: ExprId, el: ExprId, span: Span) -> Self {
         Ternary {
             condition: cond,
+            operator: op,
             then: th,
             r#else: el,
+            span,
         }
     }
 }
 
-impl PrettyPrint for Ternary {
-    fn pretty_print(&self) -> String {
-        format!(
-            "(ternary {} ? {} : {})",
-            self.condition.pretty_print(),
-            self.then.pretty_print(),
-            self.r#else.pretty_print(),
-        )
-    }
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Logical {
+    pub left: ExprId,
+    pub operator: Token,
+    pub right: ExprId,
+    pub span: Span,
 }
 
-impl Eval for Ternary {
-    fn eval(&self) -> Result<Value, RuntimeError> {
-        let condition = self.condition.eval()?;
-
-        match condition {
-            Value::Boolean(b) => {
-                if b {
-                    return self.then.eval();
-                }
-                self.r#else.eval()
-            }
-            _ => Err(RuntimeError::new(
-                Token {
-                    r#type: TokenType::Question,
-                    lexeme: "?".to_string(),
-                    literal: None,
-                    line: 0,
-                },
-                "We shouldn't be here... ternary condition didn't returned a boolean.".to_string(),
-            )),
+impl Logical {
+    pub fn new(l: ExprId, op: Token, r: ExprId, span: Span) -> Self {
+        Logical {
+            left: l,
+            operator: op,
+            right: r,
+            span,
         }
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug)]
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
 pub struct Binary {
-    left: Expr,
-    operator: Token,
-    right: Expr,
+    pub left: ExprId,
+    pub operator: Token,
+    pub right: ExprId,
+    pub span: Span,
 }
 
 impl Binary {
-    pub fn new(l: Expr, op: Token, r: Expr) -> Self {
+    pub fn new(l: ExprId, op: Token, r: ExprId, span: Span) -> Self {
         Binary {
             left: l,
             operator: op,
             right: r,
+            span,
         }
     }
 }
 
-impl PrettyPrint for Binary {
-    fn pretty_print(&self) -> String {
-        format!(
-            "({} {} {})",
-            self.operator.lexeme,
-            self.left.pretty_print(),
-            self.right.pretty_print(),
-        )
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Unary {
+    pub operator: Token,
+    pub right: ExprId,
+    pub span: Span,
+}
+
+impl Unary {
+    pub fn new(op: Token, r: ExprId, span: Span) -> Self {
+        Unary {
+            operator: op,
+            right: r,
+            span,
+        }
     }
 }
 
-impl Eval for Binary {
-    fn eval(&self) -> Result<Value, RuntimeError> {
-        let left = self.left.eval()?;
-        let right = self.right.eval()?;
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Grouping {
+    pub expression: ExprId,
+    pub span: Span,
+}
 
-        if !Value::variant_eq(&left, &right) {
-            return Err(RuntimeError::new(
-                self.operator.to_owned(),
-                "Types don't match in binary expression.".to_string(),
-            ));
+impl Grouping {
+    pub fn new(e: ExprId, span: Span) -> Self {
+        Grouping {
+            expression: e,
+            span,
         }
+    }
+}
 
-        if let (Value::Number(l), Value::Number(r)) = (&left, &right) {
-            match self.operator.r#type {
-                TokenType::Plus => return Ok(Value::Number(l + r)),
-                TokenType::Minus => return Ok(Value::Number(l - r)),
-                TokenType::Slash => return Ok(Value::Number(l / r)),
-                TokenType::Star => return Ok(Value::Number(l * r)),
-                TokenType::Greater => return Ok(Value::Boolean(l > r)),
-                TokenType::GreaterEqual => return Ok(Value::Boolean(l >= r)),
-                TokenType::Less => return Ok(Value::Boolean(l < r)),
-                TokenType::LessEqual => return Ok(Value::Boolean(l <= r)),
-                TokenType::BangEqual => return Ok(Value::Boolean(l != r)),
-                TokenType::EqualEqual => return Ok(Value::Boolean(l == r)),
-                _ => {
-                    return Err(RuntimeError::new(
-                        self.operator.to_owned(),
-                        "Invalid binary expression operator.".to_string(),
-                    ));
-                }
-            }
-        }
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Literal {
+    pub value: Option<LiteralType>,
+    pub span: Span,
+}
 
-        if let (Value::String(l), Value::String(r)) = (&left, &right) {
-            match self.operator.r#type {
-                TokenType::Plus => return Ok(Value::String(l.to_owned() + r)),
-                TokenType::Greater => return Ok(Value::Boolean(l > r)),
-                TokenType::GreaterEqual => return Ok(Value::Boolean(l >= r)),
-                TokenType::Less => return Ok(Value::Boolean(l < r)),
-                TokenType::LessEqual => return Ok(Value::Boolean(l <= r)),
-                TokenType::BangEqual => return Ok(Value::Boolean(l != r)),
-                TokenType::EqualEqual => return Ok(Value::Boolean(l == r)),
-                _ => {
-                    return Err(RuntimeError::new(
-                        self.operator.to_owned(),
-                        "Invalid binary expression operator.".to_string(),
-                    ));
-                }
-            }
+impl Literal {
+    pub fn new(v: LiteralType, span: Span) -> Self {
+        Literal {
+            value: Some(v),
+            span,
         }
+    }
+}
 
-        if let (Value::Boolean(l), Value::Boolean(r)) = (&left, &right) {
-            match self.operator.r#type {
-                TokenType::BangEqual => return Ok(Value::Boolean(l != r)),
-                TokenType::EqualEqual => return Ok(Value::Boolean(l == r)),
-                _ => {
-                    return Err(RuntimeError::new(
-                        self.operator.to_owned(),
-                        "Invalid binary expression operator.".to_string(),
-                    ));
-                }
-            }
-        }
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Variable {
+    pub name: Token,
+    pub span: Span,
+    // Set by the resolver, None for globals which are looked up by name.
+    #[serde(skip)]
+    pub slot: Cell<Option<Slot>>,
+    // Where the global was found the last time, the interpreter checks it's
+    // still there.
+    #[serde(skip)]
+    pub global: Cell<Option<usize>>,
+}
 
-        Err(RuntimeError::new(
-            self.operator.to_owned(),
-            "Invalid binary expression operator.".to_string(),
-        ))
+impl Variable {
+    pub fn new(name: Token, span: Span) -> Self {
+        Variable {
+            name,
+            span,
+            slot: Cell::new(None),
+            global: Cell::new(None),
+        }
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug)]
-pub struct Unary {
-    operator: Token,
-    right: Expr,
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Call {
+    pub callee: ExprId,
+    pub paren: Token,
+    pub arguments: Vec<ExprId>,
+    pub span: Span,
 }
 
-impl Unary {
-    pub fn new(op: Token, r: Expr) -> Self {
-        Unary {
-            operator: op,
-            right: r,
+impl Call {
+    pub fn new(callee: ExprId, paren: Token, arguments: Vec<ExprId>, span: Span) -> Self {
+        Call {
+            callee,
+            paren,
+            arguments,
+            span,
         }
     }
 }
 
-impl PrettyPrint for Unary {
-    fn pretty_print(&self) -> String {
-        format!("({} {})", self.operator.lexeme, self.right.pretty_print())
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Get {
+    pub object: ExprId,
+    pub name: Token,
+    pub span: Span,
+    // Filled in by the interpreter as it runs.
+    #[serde(skip)]
+    pub cache: Cell<Option<InlineCache>>,
+}
+
+impl Get {
+    pub fn new(object: ExprId, name: Token, span: Span) -> Self {
+        Get {
+            object,
+            name,
+            span,
+            cache: Cell::new(None),
+        }
     }
 }
 
-impl Eval for Unary {
-    fn eval(&self) -> Result<Value, RuntimeError> {
-        let right = self.right.eval()?;
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Set {
+    pub object: ExprId,
+    pub name: Token,
+    pub value: ExprId,
+    pub span: Span,
+    #[serde(skip)]
+    pub cache: Cell<Option<InlineCache>>,
+}
 
-        match self.operator.r#type {
-            TokenType::Bang => Ok(Value::Boolean(!right.is_truthy())),
-            TokenType::Minus => match right {
-                Value::Number(n) => Ok(Value::Number(-n)),
-                _ => Err(RuntimeError::new(
-                    self.operator.to_owned(),
-                    "Minus should only be used with the number type.".to_string(),
-                )),
-            },
-            _ => Err(RuntimeError::new(
-                self.operator.to_owned(),
-                "Invalid operator in unary expression.".to_string(),
-            )),
+impl Set {
+    pub fn new(object: ExprId, name: Token, value: ExprId, span: Span) -> Self {
+        Set {
+            object,
+            name,
+            value,
+            span,
+            cache: Cell::new(None),
         }
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug)]
-pub struct Grouping {
-    expression: Expr,
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct List {
+    pub elements: Vec<ExprId>,
+    pub span: Span,
 }
 
-impl Grouping {
-    pub fn new(e: Expr) -> Self {
-        Grouping { expression: e }
+impl List {
+    pub fn new(elements: Vec<ExprId>, span: Span) -> Self {
+        List { elements, span }
     }
 }
 
-impl PrettyPrint for Grouping {
-    fn pretty_print(&self) -> String {
-        format!("(group {})", self.expression.pretty_print())
-    }
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Map {
+    pub entries: Vec<(ExprId, ExprId)>,
+    // The closing brace, invalid keys are reported there.
+    pub brace: Token,
+    pub span: Span,
 }
 
-impl Eval for Grouping {
-    fn eval(&self) -> Result<Value, RuntimeError> {
-        self.expression.eval()
+impl Map {
+    pub fn new(entries: Vec<(ExprId, ExprId)>, brace: Token, span: Span) -> Self {
+        Map {
+            entries,
+            brace,
+            span,
+        }
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug)]
-pub struct Literal {
-    value: Option<LiteralType>,
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct Index {
+    pub object: ExprId,
+    // The closing bracket, runtime errors point at it.
+    pub bracket: Token,
+    pub index: ExprId,
+    pub span: Span,
 }
 
-impl Literal {
-    pub fn new(v: LiteralType) -> Self {
-        Literal { value: Some(v) }
+impl Index {
+    pub fn new(object: ExprId, bracket: Token, index: ExprId, span: Span) -> Self {
+        Index {
+            object,
+            bracket,
+            index,
+            span,
+        }
     }
 }
 
-impl PrettyPrint for Literal {
-    fn pretty_print(&self) -> String {
-        match &self.value {
-            Some(v) => format!("{}", v),
-            None => "Nil".to_string(),
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct SetIndex {
+    pub object: ExprId,
+    pub bracket: Token,
+    pub index: ExprId,
+    pub value: ExprId,
+    pub span: Span,
+}
+
+impl SetIndex {
+    pub fn new(object: ExprId, bracket: Token, index: ExprId, value: ExprId, span: Span) -> Self {
+        SetIndex {
+            object,
+            bracket,
+            index,
+            value,
+            span,
         }
     }
 }
 
-impl Eval for Literal {
-    fn eval(&self) -> Result<Value, RuntimeError> {
-        Ok(match self.value.to_owned().unwrap_or(LiteralType::Nil) {
-            LiteralType::Number(v) => Value::Number(v),
-            LiteralType::String(v) => Value::String(v),
-            LiteralType::Bool(v) => Value::Boolean(v),
-            LiteralType::Nil => Value::Nil,
-        })
+#[derive(PartialEq, PartialOrd, Debug, Serialize)]
+pub struct This {
+    pub keyword: Token,
+    pub span: Span,
+    // Resolved like a variable's, `this` is never global.
+    #[serde(skip)]
+    pub slot: Cell<Option<Slot>>,
+}
+
+impl This {
+    pub fn new(keyword: Token, span: Span) -> Self {
+        This {
+            keyword,
+            span,
+            slot: Cell::new(None),
+        }
     }
 }
@@ -1,12 +1,16 @@
 use std::fmt::{self, Display};
 
-#[derive(PartialEq, PartialOrd, Debug, Clone)]
+use serde::Serialize;
+
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy, Serialize)]
 pub enum TokenType {
     // Single char tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -16,6 +20,10 @@ pub enum TokenType {
     Slash,
     Star,
     Question,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     //  One or two character tokens.
     Bang,
@@ -26,6 +34,8 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
+    QuestionQuestion,
 
     // Literals.
     Identifier,
@@ -34,12 +44,17 @@ pub enum TokenType {
 
     // Kewords.
     And,
+    Break,
     Class,
+    Const,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
+    Match,
     Nil,
     Or,
     Print,
@@ -50,6 +65,14 @@ pub enum TokenType {
     Var,
     While,
 
+    // Only handed out when the scanner keeps comments, or whitespace for
+    // that matter.
+    Comment,
+    Whitespace,
+
+    // Lexical error, the lexeme holds the message.
+    Error,
+
     // Final token.
     Eof,
 }
@@ -60,7 +83,7 @@ impl fmt::Display for TokenType {
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug, Clone)]
+#[derive(PartialEq, PartialOrd, Debug, Clone, Serialize)]
 pub enum LiteralType {
     Number(f64),
     String(String),
@@ -79,10 +102,54 @@ impl Display for LiteralType {
     }
 }
 
-#[derive(PartialEq, PartialOrd, Debug, Clone)]
+#[derive(PartialEq, PartialOrd, Debug, Clone, Serialize)]
 pub struct Token {
     pub r#type: TokenType,
     pub lexeme: String,
     pub literal: Option<LiteralType>,
     pub line: u64,
+    // Both counted in chars, the column is where the lexeme starts.
+    pub column: u64,
+    pub length: u64,
+    pub span: Span,
+}
+
+// A token as tokenize hands it out: its kind and where it is, the text being
+// what the span covers.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct SpannedToken {
+    pub r#type: TokenType,
+    pub span: Span,
+    pub line: u64,
+    pub column: u64,
+}
+
+// Byte offsets into the source, `end` is exclusive.
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    // Which source the offsets are into, as the host numbered it for the
+    // scanner. 0 when it didn't.
+    #[serde(skip)]
+    pub source: u32,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+            source: 0,
+        }
+    }
+
+    pub fn in_source(self, source: u32) -> Self {
+        Span { source, ..self }
+    }
+
+    // Covers everything from the start of self to the end of other.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end).in_source(self.source)
+    }
 }
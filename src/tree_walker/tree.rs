@@ -0,0 +1,234 @@
+use super::stack;
+use super::syntax_tree::{Expr, ExprId, Exprs, Function, Stmt};
+use super::tokens::LiteralType;
+
+// Renders the syntax tree indented, one node per line with box drawing lines
+// tying children to their parent:
+//   Binary '*'
+//   ├─ Unary '-'
+//   │  └─ Variable a
+//   └─ Literal "b"
+#[allow(dead_code)]
+pub fn ast_to_tree(exprs: &Exprs, expr: ExprId) -> String {
+    let mut out = String::new();
+    expr_node(exprs, expr).render(&mut out, "", "");
+    out
+}
+
+// Every top level statement as a tree of its own.
+pub fn program_to_tree(statements: &[Stmt], exprs: &Exprs) -> String {
+    let mut out = String::new();
+    for statement in statements {
+        stmt_node(exprs, statement).render(&mut out, "", "");
+    }
+    out
+}
+
+struct Node {
+    label: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn leaf(label: impl Into<String>) -> Self {
+        Node {
+            label: label.into(),
+            children: vec![],
+        }
+    }
+
+    fn parent<'a>(
+        exprs: &Exprs,
+        label: impl Into<String>,
+        children: impl IntoIterator<Item = &'a ExprId>,
+    ) -> Self {
+        Node {
+            label: label.into(),
+            children: children.into_iter().map(|e| expr_node(exprs, *e)).collect(),
+        }
+    }
+
+    fn with(mut self, children: impl IntoIterator<Item = Node>) -> Self {
+        self.children.extend(children);
+        self
+    }
+
+    // `first` goes before the label, `rest` before every line under it.
+    fn render(&self, out: &mut String, first: &str, rest: &str) {
+        out.push_str(first);
+        out.push_str(&self.label);
+        out.push('\n');
+
+        for (i, child) in self.children.iter().enumerate() {
+            stack::guard(|| {
+                if i + 1 == self.children.len() {
+                    child.render(out, &format!("{}└─ ", rest), &format!("{}   ", rest));
+                } else {
+                    child.render(out, &format!("{}├─ ", rest), &format!("{}│  ", rest));
+                }
+            });
+        }
+    }
+}
+
+// Trees as deep as the expressions they show are taken apart a node at a time.
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.children);
+        while let Some(mut node) = pending.pop() {
+            pending.append(&mut node.children);
+        }
+    }
+}
+
+fn expr_node(exprs: &Exprs, expr: ExprId) -> Node {
+    stack::guard(|| match &exprs[expr] {
+        Expr::Assign(e) => Node::parent(exprs, format!("Assign {}", e.name.lexeme), [&e.value]),
+        Expr::Ternary(e) => Node::parent(exprs, "Ternary", [&e.condition, &e.then, &e.r#else]),
+        Expr::Logical(e) => Node::parent(
+            exprs,
+            format!("Logical '{}'", e.operator.lexeme),
+            [&e.left, &e.right],
+        ),
+        Expr::Binary(e) => Node::parent(
+            exprs,
+            format!("Binary '{}'", e.operator.lexeme),
+            [&e.left, &e.right],
+        ),
+        Expr::Unary(e) => Node::parent(exprs, format!("Unary '{}'", e.operator.lexeme), [&e.right]),
+        Expr::Grouping(e) => Node::parent(exprs, "Grouping", [&e.expression]),
+        Expr::Literal(e) => match &e.value {
+            Some(LiteralType::String(s)) => Node::leaf(format!("Literal \"{}\"", s)),
+            Some(LiteralType::Nil) | None => Node::leaf("Literal nil"),
+            Some(v) => Node::leaf(format!("Literal {}", v)),
+        },
+        Expr::Variable(e) => Node::leaf(format!("Variable {}", e.name.lexeme)),
+        Expr::Call(e) => Node::parent(
+            exprs,
+            "Call",
+            std::iter::once(&e.callee).chain(&e.arguments),
+        ),
+        Expr::Get(e) => Node::parent(exprs, format!("Get .{}", e.name.lexeme), [&e.object]),
+        Expr::Set(e) => Node::parent(
+            exprs,
+            format!("Set .{}", e.name.lexeme),
+            [&e.object, &e.value],
+        ),
+        Expr::This(_) => Node::leaf("This"),
+        Expr::List(e) => Node::parent(exprs, "List", &e.elements),
+        Expr::Map(e) => Node::parent(exprs, "Map", e.entries.iter().flat_map(|(k, v)| [k, v])),
+        Expr::Index(e) => Node::parent(exprs, "Index", [&e.object, &e.index]),
+        Expr::SetIndex(e) => Node::parent(exprs, "SetIndex", [&e.object, &e.index, &e.value]),
+    })
+}
+
+fn function_node(exprs: &Exprs, kind: &str, function: &Function) -> Node {
+    let params: Vec<&str> = function.params.iter().map(|p| p.lexeme.as_str()).collect();
+    let label = format!("{} {}({})", kind, function.name.lexeme, params.join(", "));
+    Node::leaf(label).with(function.body.iter().map(|s| stmt_node(exprs, s)))
+}
+
+fn stmt_node(exprs: &Exprs, stmt: &Stmt) -> Node {
+    match stmt {
+        Stmt::Expression(s) => Node::parent(exprs, "Expression", [&s.expression]),
+        Stmt::Print(s) => Node::parent(exprs, "Print", [&s.expression]),
+        Stmt::Var(s) => {
+            let keyword = if s.constant { "Const" } else { "Var" };
+            Node::parent(
+                exprs,
+                format!("{} {}", keyword, s.name.lexeme),
+                &s.initializer,
+            )
+        }
+        Stmt::Block(s) => {
+            Node::leaf("Block").with(s.statements.iter().map(|s| stmt_node(exprs, s)))
+        }
+        Stmt::If(s) => Node::parent(exprs, "If", [&s.condition]).with(
+            std::iter::once(&s.then)
+                .chain(&s.r#else)
+                .map(|s| stmt_node(exprs, s)),
+        ),
+        Stmt::While(s) => Node::parent(
+            exprs,
+            "While",
+            std::iter::once(&s.condition).chain(&s.increment),
+        )
+        .with([stmt_node(exprs, &s.body)]),
+        Stmt::ForIn(s) => Node::parent(exprs, format!("ForIn {}", s.name.lexeme), [&s.iterable])
+            .with([stmt_node(exprs, &s.body)]),
+        Stmt::Function(s) => function_node(exprs, "Fun", s),
+        Stmt::Return(s) => Node::parent(exprs, "Return", &s.value),
+        Stmt::Class(s) => Node::leaf(format!("Class {}", s.name.lexeme))
+            .with(s.methods.iter().map(|m| function_node(exprs, "Method", m))),
+        Stmt::Break(_) => Node::leaf("Break"),
+        Stmt::Continue(_) => Node::leaf("Continue"),
+        Stmt::Match(s) => Node::parent(exprs, "Match", [&s.subject])
+            .with(s.arms.iter().map(|(value, body)| {
+                Node::parent(exprs, "Arm", [value]).with([stmt_node(exprs, body)])
+            }))
+            .with(
+                s.r#else
+                    .iter()
+                    .map(|body| Node::leaf("Else").with([stmt_node(exprs, body)])),
+            ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    fn parse(source: &str) -> (Vec<Stmt>, Exprs) {
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        (parser.parse(), parser.exprs)
+    }
+
+    #[test]
+    fn expression_tree() {
+        let (statements, exprs) = parse("-a * (\"b\" + 1);");
+        let Stmt::Expression(ref e) = statements[0] else {
+            unreachable!()
+        };
+
+        assert_eq!(
+            ast_to_tree(&exprs, e.expression),
+            "Binary '*'\n\
+             ├─ Unary '-'\n\
+             │  └─ Variable a\n\
+             └─ Grouping\n\
+             \x20  └─ Binary '+'\n\
+             \x20     ├─ Literal \"b\"\n\
+             \x20     └─ Literal 1\n"
+        );
+    }
+
+    #[test]
+    fn deep_expressions() {
+        // The rendered tree grows with the square of its depth, so it's not as
+        // deep as the other walks get tested with.
+        let (statements, exprs) = parse(&format!("print {};", vec!["1"; 10_000].join(" + ")));
+        let tree = program_to_tree(&statements, &exprs);
+        assert_eq!(tree.lines().count(), 20_000);
+    }
+
+    #[test]
+    fn program_tree() {
+        let (statements, exprs) = parse("fun f(a) { if (a) return a; }\nprint f(1);");
+        assert_eq!(
+            program_to_tree(&statements, &exprs),
+            "Fun f(a)\n\
+             └─ If\n\
+             \x20  ├─ Variable a\n\
+             \x20  └─ Return\n\
+             \x20     └─ Variable a\n\
+             Print\n\
+             └─ Call\n\
+             \x20  ├─ Variable f\n\
+             \x20  └─ Literal 1\n"
+        );
+    }
+}
@@ -0,0 +1,378 @@
+use std::rc::Rc;
+
+use super::stack;
+use super::syntax_tree::{
+    Assign, Binary, Block, Break, Call, Class, Continue, Expr, ExprId, Expression, Exprs, ForIn,
+    Function, Get, Grouping, If, Index, List, Literal, Logical, Map, Match, Print, Return, Set,
+    SetIndex, Stmt, Ternary, This, Unary, Var, Variable, While,
+};
+
+// One method per kind of node, for code working on the syntax tree without
+// matching on it: `accept` calls the method for the node's kind. Expressions
+// and statements get visitors of their own, so they can give back different
+// things, like the interpreter does with values and control flow.
+pub trait ExprVisitor<R> {
+    fn visit_assign(&mut self, expr: &Assign) -> R;
+    fn visit_ternary(&mut self, expr: &Ternary) -> R;
+    fn visit_logical(&mut self, expr: &Logical) -> R;
+    fn visit_binary(&mut self, expr: &Binary) -> R;
+    fn visit_unary(&mut self, expr: &Unary) -> R;
+    fn visit_grouping(&mut self, expr: &Grouping) -> R;
+    fn visit_literal(&mut self, expr: &Literal) -> R;
+    fn visit_variable(&mut self, expr: &Variable) -> R;
+    fn visit_call(&mut self, expr: &Call) -> R;
+    fn visit_get(&mut self, expr: &Get) -> R;
+    fn visit_set(&mut self, expr: &Set) -> R;
+    fn visit_this(&mut self, expr: &This) -> R;
+    fn visit_list(&mut self, expr: &List) -> R;
+    fn visit_map(&mut self, expr: &Map) -> R;
+    fn visit_index(&mut self, expr: &Index) -> R;
+    fn visit_set_index(&mut self, expr: &SetIndex) -> R;
+}
+
+pub trait StmtVisitor<R> {
+    fn visit_expression(&mut self, stmt: &Expression) -> R;
+    fn visit_print(&mut self, stmt: &Print) -> R;
+    fn visit_var(&mut self, stmt: &Var) -> R;
+    fn visit_block(&mut self, stmt: &Block) -> R;
+    fn visit_if(&mut self, stmt: &If) -> R;
+    fn visit_while(&mut self, stmt: &While) -> R;
+    fn visit_for_in(&mut self, stmt: &ForIn) -> R;
+    fn visit_function(&mut self, stmt: &Rc<Function>) -> R;
+    fn visit_return(&mut self, stmt: &Return) -> R;
+    fn visit_class(&mut self, stmt: &Class) -> R;
+    fn visit_break(&mut self, stmt: &Break) -> R;
+    fn visit_continue(&mut self, stmt: &Continue) -> R;
+    fn visit_match(&mut self, stmt: &Match) -> R;
+}
+
+// Every kind of node, giving back the same for all of them.
+pub trait Visitor<R>: ExprVisitor<R> + StmtVisitor<R> {}
+
+impl<R, V: ExprVisitor<R> + StmtVisitor<R>> Visitor<R> for V {}
+
+impl Expr {
+    pub fn accept<R, V: ExprVisitor<R>>(&self, visitor: &mut V) -> R {
+        use Expr::*;
+        match *self {
+            Assign(ref e) => visitor.visit_assign(e),
+            Ternary(ref e) => visitor.visit_ternary(e),
+            Logical(ref e) => visitor.visit_logical(e),
+            Binary(ref e) => visitor.visit_binary(e),
+            Unary(ref e) => visitor.visit_unary(e),
+            Grouping(ref e) => visitor.visit_grouping(e),
+            Literal(ref e) => visitor.visit_literal(e),
+            Variable(ref e) => visitor.visit_variable(e),
+            Call(ref e) => visitor.visit_call(e),
+            Get(ref e) => visitor.visit_get(e),
+            Set(ref e) => visitor.visit_set(e),
+            This(ref e) => visitor.visit_this(e),
+            List(ref e) => visitor.visit_list(e),
+            Map(ref e) => visitor.visit_map(e),
+            Index(ref e) => visitor.visit_index(e),
+            SetIndex(ref e) => visitor.visit_set_index(e),
+        }
+    }
+}
+
+impl Stmt {
+    pub fn accept<R, V: StmtVisitor<R>>(&self, visitor: &mut V) -> R {
+        use Stmt::*;
+        match *self {
+            Expression(ref s) => visitor.visit_expression(s),
+            Print(ref s) => visitor.visit_print(s),
+            Var(ref s) => visitor.visit_var(s),
+            Block(ref s) => visitor.visit_block(s),
+            If(ref s) => visitor.visit_if(s),
+            While(ref s) => visitor.visit_while(s),
+            ForIn(ref s) => visitor.visit_for_in(s),
+            Function(ref s) => visitor.visit_function(s),
+            Return(ref s) => visitor.visit_return(s),
+            Class(ref s) => visitor.visit_class(s),
+            Break(ref s) => visitor.visit_break(s),
+            Continue(ref s) => visitor.visit_continue(s),
+            Match(ref s) => visitor.visit_match(s),
+        }
+    }
+}
+
+// Visit the nodes right under a node, dropping what the visitor gives back.
+// For visitors only interested in some kinds of node, which walk the rest:
+//   fn visit_call(&mut self, expr: &Call) {
+//       self.calls += 1;
+//       walk_call(self.exprs, self, expr);
+//   }
+// The expressions under a node are looked up in `exprs`.
+
+// Every walk down into an expression goes through here, on a stack with room
+// to spare.
+fn walk_expr<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: ExprId) {
+    stack::guard(|| exprs[expr].accept(visitor));
+}
+
+pub fn walk_assign<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Assign) {
+    walk_expr(exprs, visitor, expr.value);
+}
+
+pub fn walk_ternary<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Ternary) {
+    walk_expr(exprs, visitor, expr.condition);
+    walk_expr(exprs, visitor, expr.then);
+    walk_expr(exprs, visitor, expr.r#else);
+}
+
+pub fn walk_logical<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Logical) {
+    walk_expr(exprs, visitor, expr.left);
+    walk_expr(exprs, visitor, expr.right);
+}
+
+pub fn walk_binary<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Binary) {
+    walk_expr(exprs, visitor, expr.left);
+    walk_expr(exprs, visitor, expr.right);
+}
+
+pub fn walk_unary<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Unary) {
+    walk_expr(exprs, visitor, expr.right);
+}
+
+pub fn walk_grouping<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Grouping) {
+    walk_expr(exprs, visitor, expr.expression);
+}
+
+pub fn walk_call<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Call) {
+    walk_expr(exprs, visitor, expr.callee);
+    for argument in &expr.arguments {
+        walk_expr(exprs, visitor, *argument);
+    }
+}
+
+pub fn walk_get<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Get) {
+    walk_expr(exprs, visitor, expr.object);
+}
+
+pub fn walk_set<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Set) {
+    walk_expr(exprs, visitor, expr.object);
+    walk_expr(exprs, visitor, expr.value);
+}
+
+pub fn walk_list<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &List) {
+    for element in &expr.elements {
+        walk_expr(exprs, visitor, *element);
+    }
+}
+
+pub fn walk_map<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Map) {
+    for (key, value) in &expr.entries {
+        walk_expr(exprs, visitor, *key);
+        walk_expr(exprs, visitor, *value);
+    }
+}
+
+pub fn walk_index<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &Index) {
+    walk_expr(exprs, visitor, expr.object);
+    walk_expr(exprs, visitor, expr.index);
+}
+
+pub fn walk_set_index<R>(exprs: &Exprs, visitor: &mut impl ExprVisitor<R>, expr: &SetIndex) {
+    walk_expr(exprs, visitor, expr.object);
+    walk_expr(exprs, visitor, expr.index);
+    walk_expr(exprs, visitor, expr.value);
+}
+
+pub fn walk_expression<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &Expression) {
+    walk_expr(exprs, visitor, stmt.expression);
+}
+
+pub fn walk_print<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &Print) {
+    walk_expr(exprs, visitor, stmt.expression);
+}
+
+pub fn walk_var<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &Var) {
+    if let Some(initializer) = &stmt.initializer {
+        walk_expr(exprs, visitor, *initializer);
+    }
+}
+
+pub fn walk_block<R>(visitor: &mut impl Visitor<R>, stmt: &Block) {
+    for statement in &stmt.statements {
+        statement.accept(visitor);
+    }
+}
+
+pub fn walk_if<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &If) {
+    walk_expr(exprs, visitor, stmt.condition);
+    stmt.then.accept(visitor);
+    if let Some(r#else) = &stmt.r#else {
+        r#else.accept(visitor);
+    }
+}
+
+pub fn walk_while<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &While) {
+    walk_expr(exprs, visitor, stmt.condition);
+    stmt.body.accept(visitor);
+    if let Some(increment) = &stmt.increment {
+        walk_expr(exprs, visitor, *increment);
+    }
+}
+
+pub fn walk_for_in<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &ForIn) {
+    walk_expr(exprs, visitor, stmt.iterable);
+    stmt.body.accept(visitor);
+}
+
+pub fn walk_function<R>(visitor: &mut impl Visitor<R>, stmt: &Function) {
+    for statement in &stmt.body {
+        statement.accept(visitor);
+    }
+}
+
+pub fn walk_return<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &Return) {
+    if let Some(value) = &stmt.value {
+        walk_expr(exprs, visitor, *value);
+    }
+}
+
+pub fn walk_class<R>(visitor: &mut impl Visitor<R>, stmt: &Class) {
+    for method in &stmt.methods {
+        visitor.visit_function(method);
+    }
+}
+
+pub fn walk_match<R>(exprs: &Exprs, visitor: &mut impl Visitor<R>, stmt: &Match) {
+    walk_expr(exprs, visitor, stmt.subject);
+    for (value, body) in &stmt.arms {
+        walk_expr(exprs, visitor, *value);
+        body.accept(visitor);
+    }
+    if let Some(r#else) = &stmt.r#else {
+        r#else.accept(visitor);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_walker::parser::Parser;
+    use crate::tree_walker::scanner::Scanner;
+
+    use super::*;
+
+    // Counts the calls in a program, walking past everything else.
+    struct Calls<'a> {
+        exprs: &'a Exprs,
+        count: usize,
+    }
+
+    impl ExprVisitor<()> for Calls<'_> {
+        fn visit_assign(&mut self, expr: &Assign) {
+            walk_assign(self.exprs, self, expr)
+        }
+        fn visit_ternary(&mut self, expr: &Ternary) {
+            walk_ternary(self.exprs, self, expr)
+        }
+        fn visit_logical(&mut self, expr: &Logical) {
+            walk_logical(self.exprs, self, expr)
+        }
+        fn visit_binary(&mut self, expr: &Binary) {
+            walk_binary(self.exprs, self, expr)
+        }
+        fn visit_unary(&mut self, expr: &Unary) {
+            walk_unary(self.exprs, self, expr)
+        }
+        fn visit_grouping(&mut self, expr: &Grouping) {
+            walk_grouping(self.exprs, self, expr)
+        }
+        fn visit_literal(&mut self, _: &Literal) {}
+        fn visit_variable(&mut self, _: &Variable) {}
+        fn visit_call(&mut self, expr: &Call) {
+            self.count += 1;
+            walk_call(self.exprs, self, expr)
+        }
+        fn visit_get(&mut self, expr: &Get) {
+            walk_get(self.exprs, self, expr)
+        }
+        fn visit_set(&mut self, expr: &Set) {
+            walk_set(self.exprs, self, expr)
+        }
+        fn visit_this(&mut self, _: &This) {}
+        fn visit_list(&mut self, expr: &List) {
+            walk_list(self.exprs, self, expr)
+        }
+        fn visit_map(&mut self, expr: &Map) {
+            walk_map(self.exprs, self, expr)
+        }
+        fn visit_index(&mut self, expr: &Index) {
+            walk_index(self.exprs, self, expr)
+        }
+        fn visit_set_index(&mut self, expr: &SetIndex) {
+            walk_set_index(self.exprs, self, expr)
+        }
+    }
+
+    impl StmtVisitor<()> for Calls<'_> {
+        fn visit_expression(&mut self, stmt: &Expression) {
+            walk_expression(self.exprs, self, stmt)
+        }
+        fn visit_print(&mut self, stmt: &Print) {
+            walk_print(self.exprs, self, stmt)
+        }
+        fn visit_var(&mut self, stmt: &Var) {
+            walk_var(self.exprs, self, stmt)
+        }
+        fn visit_block(&mut self, stmt: &Block) {
+            walk_block(self, stmt)
+        }
+        fn visit_if(&mut self, stmt: &If) {
+            walk_if(self.exprs, self, stmt)
+        }
+        fn visit_while(&mut self, stmt: &While) {
+            walk_while(self.exprs, self, stmt)
+        }
+        fn visit_for_in(&mut self, stmt: &ForIn) {
+            walk_for_in(self.exprs, self, stmt)
+        }
+        fn visit_function(&mut self, stmt: &Rc<Function>) {
+            walk_function(self, stmt)
+        }
+        fn visit_return(&mut self, stmt: &Return) {
+            walk_return(self.exprs, self, stmt)
+        }
+        fn visit_class(&mut self, stmt: &Class) {
+            walk_class(self, stmt)
+        }
+        fn visit_break(&mut self, _: &Break) {}
+        fn visit_continue(&mut self, _: &Continue) {}
+        fn visit_match(&mut self, stmt: &Match) {
+            walk_match(self.exprs, self, stmt)
+        }
+    }
+
+    #[test]
+    fn walks_every_node() {
+        let source = "fun f(a) { return g(a)[h()]; }\n\
+                      class C { m() { for (x in [i()]) print {j(): k(x)}; } }\n\
+                      match (l()) { 1 -> m(); else -> n(); }\n\
+                      var v = o() ? (p().q = 1) : !r();";
+        let tokens = Scanner::new(source.to_string()).scan_tokens();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse();
+
+        let mut calls = Calls {
+            exprs: &parser.exprs,
+            count: 0,
+        };
+        for statement in &statements {
+            statement.accept(&mut calls);
+        }
+        assert_eq!(calls.count, 11);
+
+        // Walking down a tree a hundred thousand deep.
+        let source = format!("f({});", vec!["g()"; 100_000].join(" + "));
+        let mut parser = Parser::new(Scanner::new(source).scan_tokens());
+        let statements = parser.parse();
+        let mut calls = Calls {
+            exprs: &parser.exprs,
+            count: 0,
+        };
+        statements[0].accept(&mut calls);
+        assert_eq!(calls.count, 100_001);
+    }
+}
@@ -0,0 +1,38 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    // Milliseconds since the epoch, from the browser.
+    #[wasm_bindgen(js_namespace = Date)]
+    pub fn now() -> f64;
+}
+
+// What a run printed and the errors it reported, for the JS side.
+#[wasm_bindgen]
+pub struct RunResult {
+    output: String,
+    errors: String,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> String {
+        self.output.to_owned()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn errors(&self) -> String {
+        self.errors.to_owned()
+    }
+}
+
+// From JS: const { output, errors } = run(source);
+#[wasm_bindgen]
+pub fn run(source: &str) -> RunResult {
+    let run = crate::run(source);
+    RunResult {
+        output: run.output,
+        errors: run.errors,
+    }
+}
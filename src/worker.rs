@@ -0,0 +1,96 @@
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::session::{Run, Session};
+use crate::tree_walker::interpreter::Options;
+
+// Values share their objects through Rc, so an interpreter and everything it
+// holds stay on the thread that made them. A worker keeps a session confined
+// to a thread of its own and hands it programs by message, only source and
+// captured text cross threads. The handle itself is Send and Sync, any number
+// of workers can run side by side.
+pub struct Worker {
+    jobs: Option<Sender<(String, Sender<Run>)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    pub fn spawn(options: Options) -> Self {
+        let (jobs, queue) = mpsc::channel::<(String, Sender<Run>)>();
+        let thread = thread::spawn(move || {
+            let mut session = Session::new(options);
+            for (source, done) in queue {
+                // Whoever asked may have gone away meanwhile.
+                let _ = done.send(session.run(&source));
+            }
+        });
+
+        Worker {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    // Runs the program on the worker's thread and waits for it. Programs run
+    // one after the other, sharing their globals like lines at the prompt.
+    pub fn run(&self, source: &str) -> Run {
+        let (done, result) = mpsc::channel();
+        let sent = self
+            .jobs
+            .as_ref()
+            .is_some_and(|jobs| jobs.send((source.to_string(), done)).is_ok());
+
+        match result.recv() {
+            Ok(run) if sent => run,
+            // The thread only dies if the interpreter panicked.
+            _ => Run {
+                output: String::new(),
+                errors: "The worker thread stopped.\n".to_string(),
+                exit: None,
+            },
+        }
+    }
+}
+
+impl Drop for Worker {
+    // Closing the queue ends the thread once it's done with what it has.
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn workers_run_concurrently() {
+        assert_send_sync::<Worker>();
+
+        let workers: Vec<Arc<Worker>> = (0..4)
+            .map(|_| Arc::new(Worker::spawn(Options::default())))
+            .collect();
+        let threads: Vec<_> = workers
+            .iter()
+            .enumerate()
+            .map(|(i, worker)| {
+                let worker = Arc::clone(worker);
+                thread::spawn(move || {
+                    worker.run(&format!("var n = {};", i));
+                    worker.run("var total = 0; for (var i = 0; i < 1000; i = i + 1) total = total + n; print total;")
+                })
+            })
+            .collect();
+
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap().output, format!("{}\n", i * 1000));
+        }
+    }
+}